@@ -11,7 +11,10 @@ struct BitSorter {
     dim: u32,
 }
 
-@group(0) @binding(0) var<uniform> num_particles: u32;
+// Used in bitsort, padded to a power of two
+@group(0) @binding(0) var<uniform> sort_length: u32;
+// Used elsewhere, live and boundary particles
+@group(0) @binding(0) var<uniform> num_sorted: u32;
 @group(0) @binding(1) var<storage, read_write> particle_indicies: array<u32>;
 @group(0) @binding(2) var<storage, read_write> particle_cell_indicies: array<u32>;
 // Used in bitsort
@@ -24,22 +27,21 @@ fn bitonic_sort(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     let i = invocation_id.x + invocation_id.y * 262144u;  // 256 * 1024
     let j = i ^ bit_sorter.block_size;
 
-    if j < i || i >= num_particles {
+    if j < i || j >= sort_length {
         return;
     }
 
-    var sign = 1;
-    if (i & bit_sorter.dim) != 0 {
-        sign = -1;
-    }
-
     let key_i = particle_indicies[i];
     let key_j = particle_indicies[j];
     let value_i = particle_cell_indicies[key_i];
     let value_j = particle_cell_indicies[key_j];
 
-    let diff = i32(value_i - value_j) * sign;
-    if diff > 0 {
+    // Compare directly, the padding sentinel overflows a signed difference
+    var swap = value_i > value_j;
+    if (i & bit_sorter.dim) != 0 {
+        swap = value_i < value_j;
+    }
+    if swap {
         particle_indicies[i] = key_j;
         particle_indicies[j] = key_i;
     }
@@ -49,10 +51,11 @@ fn bitonic_sort(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
 fn calculate_cell_offsets(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     // Check workgroup boundary
     let index = invocation_id.x;
-    if index >= num_particles {
+    if index >= num_sorted {
         return;
     }
 
+    // Padding keys sort past the simulated ones, so none are left below num_sorted
     let particle_index = particle_indicies[index];
     let cell_index = particle_cell_indicies[particle_index];
     atomicMin(&cell_offsets[cell_index], index);
@@ -0,0 +1,84 @@
+const WORKGROUP_SIZE: u32 = 1024;
+
+// Used in clear and prefix sum, one bucket per cell plus the padding bucket
+@group(0) @binding(0) var<storage, read_write> buckets: array<u32>;
+// Used in count and scatter
+@group(0) @binding(0) var<storage, read_write> particle_cell_indicies: array<u32>;
+@group(0) @binding(2) var<storage, read_write> particle_ranks: array<u32>;
+// Used in count
+@group(0) @binding(1) var<storage, read_write> cell_counts: array<atomic<u32> >;
+// Used in scatter
+@group(0) @binding(1) var<storage, read_write> cell_starts: array<u32>;
+@group(0) @binding(3) var<storage, read_write> particle_indicies: array<u32>;
+
+var<workgroup> chunk_sums: array<u32, WORKGROUP_SIZE>;
+
+// Padding and unused slots hold the sentinel, they share the last bucket and land behind every cell
+fn get_bucket(cell_index: u32, num_buckets: u32) -> u32 {
+    return min(cell_index, num_buckets - 1u);
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, 1, 1)
+fn clear_cell_counts(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let index = invocation_id.x;
+    if index >= arrayLength(&buckets) {
+        return;
+    }
+    buckets[index] = 0u;
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, 1, 1)
+fn count_cells(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let particle_index = invocation_id.x;
+    if particle_index >= arrayLength(&particle_cell_indicies) {
+        return;
+    }
+    let bucket = get_bucket(particle_cell_indicies[particle_index], arrayLength(&cell_counts));
+    // The slot taken in the cell, the order within a cell doesn't matter
+    particle_ranks[particle_index] = atomicAdd(&cell_counts[bucket], 1u);
+}
+
+// Single workgroup: every invocation sums a contiguous chunk, the chunk sums are scanned in shared memory
+@compute @workgroup_size(WORKGROUP_SIZE, 1, 1)
+fn prefix_sum_cells(@builtin(local_invocation_id) local_id: vec3<u32>) {
+    let length = arrayLength(&buckets);
+    let chunk = (length + WORKGROUP_SIZE - 1u) / WORKGROUP_SIZE;
+    let first = min(local_id.x * chunk, length);
+    let last = min(first + chunk, length);
+
+    var sum = 0u;
+    for (var i = first; i < last; i++) {
+        sum += buckets[i];
+    }
+    chunk_sums[local_id.x] = sum;
+    workgroupBarrier();
+
+    // Inclusive Hillis-Steele scan
+    for (var offset = 1u; offset < WORKGROUP_SIZE; offset <<= 1u) {
+        var value = 0u;
+        if local_id.x >= offset {
+            value = chunk_sums[local_id.x - offset];
+        }
+        workgroupBarrier();
+        chunk_sums[local_id.x] += value;
+        workgroupBarrier();
+    }
+
+    // Counts become exclusive starts
+    var start = chunk_sums[local_id.x] - sum;
+    for (var i = first; i < last; i++) {
+        let count = buckets[i];
+        buckets[i] = start;
+        start += count;
+    }
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE, 1, 1)
+fn scatter_particles(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let particle_index = invocation_id.x;
+    if particle_index >= arrayLength(&particle_cell_indicies) {
+        return;
+    }
+    let bucket = get_bucket(particle_cell_indicies[particle_index], arrayLength(&cell_starts));
+    particle_indicies[cell_starts[bucket] + particle_ranks[particle_index]] = particle_index;
+}
@@ -0,0 +1,16 @@
+#import bevy_ui::ui_vertex_output::UiVertexOutput
+
+const SURFACE_LEVEL: f32 = 0.5;  // Keep in sync with the metaballs module
+const SURFACE_SOFTNESS: f32 = 0.05;
+
+@group(1) @binding(0) var<uniform> color: vec4<f32>;
+@group(1) @binding(1) var density_texture: texture_2d<f32>;
+@group(1) @binding(2) var density_sampler: sampler;
+
+@fragment
+fn fragment(in: UiVertexOutput) -> @location(0) vec4<f32> {
+    let density = textureSample(density_texture, density_sampler, in.uv).r;
+    // The filtered texture gives a smooth edge, blur it a little further to hide the texels
+    let coverage = smoothstep(SURFACE_LEVEL - SURFACE_SOFTNESS, SURFACE_LEVEL + SURFACE_SOFTNESS, density);
+    return vec4(color.rgb, color.a * coverage);
+}
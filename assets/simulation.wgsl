@@ -1,7 +1,13 @@
 const WORKGROUP_SIZE: u32 = 1024;
 
-const LOOKAHEAD_FACTOR: f32 = 1. / 50.;
 const DENSITY_PADDING: f32 = 0.00001;
+const SORT_SENTINEL: u32 = 4294967295u;  // Cell index of the sort padding slots
+const OBSTACLES_MAX: u32 = 16u;  // Keep in sync with the obstacles module
+const FLUID_TYPES_MAX: u32 = 2u;  // Keep in sync with the fluid compute module
+
+const SPLASH_MIN_IMPACT_SPEED: f32 = 1.;  // Resting contact does not count as a splash
+const SPLASH_FIXED_POINT_SCALE: f32 = 100.;
+const BALL_FIXED_POINT_SCALE: f32 = 1000.;  // Keep in sync with the rigid ball module
 
 const OFFSET_TABLE: array<vec3i, 27> = array<vec3i, 27>(
     vec3i(-1, -1, -1),
@@ -35,10 +41,6 @@ const OFFSET_TABLE: array<vec3i, 27> = array<vec3i, 27>(
 
 const INF: u32 = 999999999;
 
-const P1: u32 = 15823;  // Some large primes for hashing
-const P2: u32 = 9737333;
-const P3: u32 = 440817757;
-
 struct FluidProps {
     delta_time: f32,
     collision_damping: f32,
@@ -47,6 +49,13 @@ struct FluidProps {
     pressure_scalar: f32,
     near_pressure_scalar: f32,
     viscosity_strength: f32,
+    lookahead_time: f32,
+    cohesion_strength: f32,
+    max_speed: f32,
+    xsph_strength: f32,
+    thermal_diffusion: f32,
+    buoyancy: f32,
+    ambient_temperature: f32,
 }
 
 struct SmoothingKernel {
@@ -55,15 +64,70 @@ struct SmoothingKernel {
     pow3: f32,
     pow3_der: f32,
     spikey_pow3: f32,
+    cohesion: f32,
 }
 
 struct FluidContainer {
     ext_min: vec4<f32>,
     ext_max: vec4<f32>,
+    restitution_min: vec4<f32>,
+    restitution_max: vec4<f32>,
+    friction_min: vec4<f32>,
+    friction_max: vec4<f32>,
+    bounce_limit: vec4<f32>,
+    wrap: vec4<f32>,  // One on the axes that wrap around
+    center: vec4<f32>,
+    rotation: mat4x4<f32>,
+}
+
+struct SpatialGrid {
+    origin: vec4<f32>,
+    dims: vec4<u32>,
+    cell_size: vec4<f32>,  // Size in x
+    mirror: vec4<f32>,
+    boundary: vec4<u32>,  // First slot in x, count in y
+}
+
+struct Paddle {
+    position: vec4<f32>,
+    half_size: vec4<f32>,
+    velocity: vec4<f32>,
+}
+
+struct Obstacle {
+    a: vec4<f32>,  // Radius in w
+    b: vec4<f32>,
+}
+
+struct Obstacles {
+    count: vec4<u32>,
+    items: array<Obstacle, OBSTACLES_MAX>,
 }
 
 struct Gravity {
     value: vec4<f32>,
+    radial: vec4<f32>,  // Center in xyz, strength in w
+}
+
+struct WorldCursor {
+    position: vec4<f32>,  // Radius in w
+    axis: vec4<f32>,  // Towards the camera
+    force: vec4<f32>,  // Radial in x, tangential in y
+}
+
+struct RigidBall {
+    position: vec4<f32>,  // Radius in w, zero while disabled
+    velocity: vec4<f32>,
+}
+
+struct HeatSource {
+    position: vec4<f32>,  // Radius in w
+    rate: vec4<f32>,  // Degrees per second in x
+}
+
+// Mass in x, target density scale in y
+struct FluidTypes {
+    items: array<vec4<f32>, FLUID_TYPES_MAX>,
 }
 
 struct FluidParticle {
@@ -73,6 +137,8 @@ struct FluidParticle {
     velocity: vec4<f32>,
     acceleration: vec4<f32>,
     predicted_position: vec4<f32>,
+    temperature: vec4<f32>,  // Current in x, diffused in y
+    neighbors: vec4<f32>,  // Count within the smoothing radius in x
 }
 
 // Shared between passes
@@ -82,11 +148,21 @@ struct FluidParticle {
 // Only used in integrate
 @group(0) @binding(3) var<uniform> fluid_container: FluidContainer;
 @group(0) @binding(4) var<uniform> gravity: Gravity;
+@group(0) @binding(5) var<uniform> paddle: Paddle;
+@group(0) @binding(6) var<storage, read_write> wall_impact: atomic<u32>;
+@group(0) @binding(7) var<uniform> obstacles: Obstacles;
+@group(0) @binding(8) var<uniform> substeps: u32;
+@group(0) @binding(9) var<uniform> heat_source: HeatSource;
+@group(0) @binding(10) var<uniform> rigid_ball: RigidBall;
+@group(0) @binding(11) var<storage, read_write> ball_impulse: array<atomic<i32>, 4>;
+@group(0) @binding(12) var<uniform> world_cursor: WorldCursor;
 // Used elsewhere
 @group(0) @binding(3) var<storage> particle_indicies: array<u32>;
 @group(0) @binding(4) var<storage, read_write> particle_cell_indicies: array<u32>;
 @group(0) @binding(5) var<storage, read_write> cell_offsets: array<u32>;
-@group(0) @binding(6) var<uniform> kernel: SmoothingKernel;
+@group(0) @binding(6) var<uniform> grid: SpatialGrid;
+@group(0) @binding(7) var<uniform> kernel: SmoothingKernel;
+@group(0) @binding(8) var<uniform> fluid_types: FluidTypes;
 
 // Smothing radius kernel functions
 
@@ -116,27 +192,95 @@ fn smoothing_kernel_viscosity(dst: f32) -> f32 {
     return v * v * v * kernel.spikey_pow3;
 }
 
+// Akinci et al. 2013 spline, repulsive within about a quarter of the radius and attractive beyond it.
+// Mirrored by `FluidStaticProps::get_cohesion` and `get_cohesion_force` for the pair force.
+fn smoothing_kernel_cohesion(dst: f32) -> f32 {
+    let radius = fluid_props.smoothing_radius;
+    let v = (radius - dst) * (radius - dst) * (radius - dst) * dst * dst * dst;
+    if 2. * dst > radius {
+        return v * kernel.cohesion;
+    }
+    return (2. * v - pow(radius, 6.) / 64.) * kernel.cohesion;
+}
+
+// Fluid types, the id is kept in the position w
+
+fn get_fluid_type(position: vec4<f32>) -> vec4<f32> {
+    return fluid_types.items[min(u32(position.w), FLUID_TYPES_MAX - 1u)];
+}
+
+// Against the target density of the particle's own fluid, mirrored by `FluidStaticProps::get_pressure`
+fn get_pressure(density: f32, position: vec4<f32>, is_wall: bool) -> f32 {
+    let target_density = fluid_props.target_density * get_fluid_type(position).y;
+    let pressure = fluid_props.pressure_scalar * (density - target_density);
+    if is_wall {
+        // Walls only push, a thin boundary layer is always below the target density
+        return max(pressure, 0.);
+    }
+    return pressure;
+}
+
+// Boundary particles, fixed at the end of the buffer. They are sorted and act as neighbours but never move.
+
+fn get_num_sorted() -> u32 {
+    return num_particles + grid.boundary.y;
+}
+
+fn is_boundary(particle_index: u32) -> bool {
+    return particle_index >= grid.boundary.x && particle_index < grid.boundary.x + grid.boundary.y;
+}
+
 // Hashing cell indicies
 
 fn get_cell(position: vec3<f32>) -> vec3<i32> {
-    return vec3<i32>(floor(position / fluid_props.smoothing_radius));
+    let cell = vec3<i32>(floor((position - grid.origin.xyz) / grid.cell_size.x));
+    // Predicted positions may leave the container, keep them in the border cells
+    return clamp(cell, vec3<i32>(0), vec3<i32>(grid.dims.xyz) - 1);
+}
+
+fn is_cell_in_grid(cell_index: vec3<i32>) -> bool {
+    return all(cell_index >= vec3<i32>(0)) && all(cell_index < vec3<i32>(grid.dims.xyz));
 }
 
 fn hash_cell(cell_index: vec3<i32>) -> u32 {
     let cell = vec3<u32>(cell_index);
-    return (cell.x * P1 + cell.y * P2 + cell.z * P3) % num_particles;
+    return (cell.x + cell.y * grid.dims.x + cell.z * grid.dims.x * grid.dims.y) % get_num_sorted();
+}
+
+// Mirror plane ghosts
+
+fn get_image_count(origin: vec4<f32>) -> i32 {
+    // Ghosts are only within reach next to the plane
+    if grid.mirror.y > 0. && grid.mirror.x - origin.x < fluid_props.smoothing_radius {
+        return 2;
+    }
+    return 1;
+}
+
+fn mirror_vector(value: vec4<f32>) -> vec4<f32> {
+    return vec4(-value.x, value.yzw);
+}
+
+fn mirror_position(position: vec4<f32>) -> vec4<f32> {
+    return vec4(2. * grid.mirror.x - position.x, position.yzw);
 }
 
 @compute @workgroup_size(WORKGROUP_SIZE, 1, 1)
 fn hash_particles(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
-    // Check workgroup boundary
+    // Check workgroup boundary, padding slots up to the sort length are hashed too
     let index = invocation_id.x;
-    if index >= num_particles {
+    if index >= arrayLength(&particle_indicies) {
         return;
     }
 
-    cell_offsets[index] = INF;
+    if index < arrayLength(&cell_offsets) {
+        cell_offsets[index] = INF;
+    }
     let particle_index = particle_indicies[index];
+    if particle_index >= num_particles && !is_boundary(particle_index) {
+        particle_cell_indicies[particle_index] = SORT_SENTINEL;
+        return;
+    }
     particle_cell_indicies[particle_index] = hash_cell(get_cell(particles[particle_index].predicted_position.xyz));
 }
 
@@ -144,7 +288,7 @@ fn hash_particles(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
 fn update_density(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     // Check workgroup boundary
     let index = invocation_id.x;
-    if index >= num_particles {
+    if index >= get_num_sorted() {
         return;
     }
 
@@ -152,34 +296,50 @@ fn update_density(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
 
     let particle_index = particle_indicies[index];
     let origin = particles[particle_index].predicted_position;
-    let cell_index = get_cell(origin.xyz);
+    let image_count = get_image_count(origin);
 
     // Accumulate density
     var density: f32 = 0.;
     var near_density: f32 = 0.;
+    var neighbor_count = 0u;
+
+    // Iterate real neighbours, then the ghosts behind the mirror plane.
+    // The distance to a ghost equals the distance from the mirrored origin to the real particle.
+    for (var image = 0; image < image_count; image++) {
+        var search_origin = origin;
+        if image > 0 {
+            search_origin = mirror_position(origin);
+        }
+        let cell_index = get_cell(search_origin.xyz);
 
-    // Iterate neighbour cells
-    for (var i = 0; i < 27; i++) {
-        let neighbour_cell_index = cell_index + offset_table[i];
-        let hash_index = hash_cell(neighbour_cell_index);
-        var neighbour_it = cell_offsets[hash_index];
-        // Iterate neighbours in the cell
-        while (neighbour_it < num_particles) {
-            let neighbour_index = particle_indicies[neighbour_it];
-            if particle_cell_indicies[neighbour_index] != hash_index {
-                break;
-            }
-            neighbour_it++;
-
-            let neighbour = particles[neighbour_index];
-
-            let dst = distance(neighbour.predicted_position, origin);
-            if dst > fluid_props.smoothing_radius {
+        // Iterate neighbour cells
+        for (var i = 0; i < 27; i++) {
+            let neighbour_cell_index = cell_index + offset_table[i];
+            if !is_cell_in_grid(neighbour_cell_index) {
                 continue;
             }
-
-            density += smoothing_kernel(dst);
-            near_density += smoothing_kernel_near(dst);
+            let hash_index = hash_cell(neighbour_cell_index);
+            var neighbour_it = cell_offsets[hash_index];
+            // Iterate neighbours in the cell
+            while (neighbour_it < get_num_sorted()) {
+                let neighbour_index = particle_indicies[neighbour_it];
+                if particle_cell_indicies[neighbour_index] != hash_index {
+                    break;
+                }
+                neighbour_it++;
+
+                let neighbour = particles[neighbour_index];
+
+                let dst = distance(neighbour.predicted_position, search_origin);
+                if dst > fluid_props.smoothing_radius {
+                    continue;
+                }
+
+                let mass = get_fluid_type(neighbour.position).x;
+                density += mass * smoothing_kernel(dst);
+                near_density += mass * smoothing_kernel_near(dst);
+                neighbor_count++;
+            }
         }
     }
 
@@ -187,9 +347,11 @@ fn update_density(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     density = density + DENSITY_PADDING;
     near_density = near_density + DENSITY_PADDING;
     particles[particle_index].density = vec2(density, near_density);
+    // The particle itself is always found
+    particles[particle_index].neighbors = vec4(f32(neighbor_count) - 1., 0., 0., 0.);
 
     // Convert density to pressure
-    let pressure = fluid_props.pressure_scalar * (density - fluid_props.target_density);
+    let pressure = get_pressure(density, particles[particle_index].position, particle_index >= num_particles);
     let near_pressure = fluid_props.near_pressure_scalar * near_density;
     particles[particle_index].pressure = vec2(pressure, near_pressure);
 }
@@ -198,74 +360,294 @@ fn update_density(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
 fn update_pressure_force(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     // Check workgroup boundary
     let index = invocation_id.x;
-    if index >= num_particles {
+    if index >= get_num_sorted() {
         return;
     }
 
     var offset_table = OFFSET_TABLE;
 
     let particle_index = particle_indicies[index];
+    if particle_index >= num_particles {
+        return;
+    }
     let origin = particles[particle_index].predicted_position;
     let velocity = particles[particle_index].velocity;
     let pressure = particles[particle_index].pressure.x;
     let near_pressure = particles[particle_index].pressure.y;
-    let cell_index = get_cell(origin.xyz);
+    let temperature = particles[particle_index].temperature.x;
+    let own_density = particles[particle_index].density.x;
+    let image_count = get_image_count(origin);
 
     // Accumulate pressure force
     var pressure_force = vec3(0.);
     var viscosity_force = vec3(0.);
+    var cohesion_force = vec3(0.);
+    var xsph_velocity = vec3(0.);
+    var heat_flow = 0.;
+
+    // Iterate real neighbours, then the ghosts behind the mirror plane
+    for (var image = 0; image < image_count; image++) {
+        let is_ghost = image > 0;
+        var search_origin = origin;
+        if is_ghost {
+            search_origin = mirror_position(origin);
+        }
+        let cell_index = get_cell(search_origin.xyz);
 
-    // Iterate neighbour cells
-    for (var i = 0; i < 27; i++) {
-        let neighbour_cell_index = cell_index + offset_table[i];
-        let hash_index = hash_cell(neighbour_cell_index);
-        var neighbour_it = cell_offsets[hash_index];
-
-        // Iterate neighbours in the cell
-        while (neighbour_it < num_particles) {
-            let neighbour_index = particle_indicies[neighbour_it];
-            if particle_cell_indicies[neighbour_index] != hash_index {
-                break;
-            }
-            neighbour_it++;
-
-            if particle_index == neighbour_index {
-                continue;
-            }
-
-            let neighbour = particles[neighbour_index];
-
-            // Find direction of the force
-            let dst = distance(neighbour.predicted_position, origin);
-            if dst > fluid_props.smoothing_radius {
+        // Iterate neighbour cells
+        for (var i = 0; i < 27; i++) {
+            let neighbour_cell_index = cell_index + offset_table[i];
+            if !is_cell_in_grid(neighbour_cell_index) {
                 continue;
             }
-            var dir = (neighbour.predicted_position - origin).xyz;
-            if dst > 0. {
-                dir /= dst;
-            } else {
-                dir = vec3(0., 1., 0.);
+            let hash_index = hash_cell(neighbour_cell_index);
+            var neighbour_it = cell_offsets[hash_index];
+
+            // Iterate neighbours in the cell
+            while (neighbour_it < get_num_sorted()) {
+                let neighbour_index = particle_indicies[neighbour_it];
+                if particle_cell_indicies[neighbour_index] != hash_index {
+                    break;
+                }
+                neighbour_it++;
+
+                // A particle's own ghost is a valid neighbour
+                if !is_ghost && particle_index == neighbour_index {
+                    continue;
+                }
+
+                var neighbour = particles[neighbour_index];
+                if is_ghost {
+                    neighbour.predicted_position = mirror_position(neighbour.predicted_position);
+                    neighbour.velocity = mirror_vector(neighbour.velocity);
+                }
+
+                // Find direction of the force
+                let dst = distance(neighbour.predicted_position, origin);
+                if dst > fluid_props.smoothing_radius {
+                    continue;
+                }
+                var dir = (neighbour.predicted_position - origin).xyz;
+                if dst > 0. {
+                    dir /= dst;
+                } else if is_ghost {
+                    dir = vec3(1., 0., 0.);  // Ghosts are always across the plane
+                } else {
+                    dir = vec3(0., 1., 0.);
+                }
+
+                // Calculate pressure contribution taking into account shared pressure
+                let slope = smoothing_kernel_derivative(dst);
+                let shared_pressure = (pressure + neighbour.pressure.x) / 2.;
+
+                // Calculate near pressure contribution
+                let slope_near = smoothing_kernel_derivative_near(dst);
+                let shared_pressure_near = (near_pressure + neighbour.pressure.y) / 2.;
+
+                let mass = get_fluid_type(neighbour.position).x;
+                pressure_force += dir * mass * shared_pressure * slope / neighbour.density.x;
+                pressure_force += dir * mass * shared_pressure_near * slope_near / neighbour.density.y;
+
+                let viscosity = smoothing_kernel_viscosity(dst);
+                viscosity_force += (neighbour.velocity - velocity).xyz * viscosity;
+                xsph_velocity += (neighbour.velocity - velocity).xyz * smoothing_kernel(dst) / neighbour.density.x;
+
+                // Walls neither take nor give heat
+                if !is_boundary(neighbour_index) {
+                    heat_flow += (neighbour.temperature.x - temperature) * smoothing_kernel(dst) * 2. / (neighbour.density.x + own_density);
+                }
+
+                if dst > 0. {
+                    cohesion_force += dir * smoothing_kernel_cohesion(dst);
+                }
             }
+        }
+    }
+    let pressure_contribution = pressure_force / particles[particle_index].density.x;
+    let viscosity_contribution = viscosity_force * fluid_props.viscosity_strength;
+    let cohesion_contribution = cohesion_force * fluid_props.cohesion_strength;
+    // XSPH moves the velocity itself, integrate applies the acceleration over one step
+    let xsph_contribution = xsph_velocity * fluid_props.xsph_strength / fluid_props.delta_time;
 
-            // Calculate pressure contribution taking into account shared pressure
-            let slope = smoothing_kernel_derivative(dst);
-            let shared_pressure = (pressure + neighbour.pressure.x) / 2.;
+    particles[particle_index].acceleration = vec4(pressure_contribution + viscosity_contribution + cohesion_contribution + xsph_contribution, 0.);
+    // Neighbours still read the old temperature, integrate swaps the new one in
+    particles[particle_index].temperature.y = temperature + heat_flow * fluid_props.thermal_diffusion * fluid_props.delta_time;
+}
 
-            // Calculate near pressure contribution
-            let slope_near = smoothing_kernel_derivative_near(dst);
-            let shared_pressure_near = (near_pressure + neighbour.pressure.y) / 2.;
+// Outgoing speed away from a wall, restitution above 1 only boosts up to the bounce limit.
+// Mirrored by `FluidContainerExt::get_bounce_speed`.
+fn get_bounce_speed(speed: f32, restitution: f32) -> f32 {
+    let outgoing = abs(speed) * restitution;
+    if restitution > 1. {
+        return min(outgoing, max(abs(speed), fluid_container.bounce_limit.x));
+    }
+    return outgoing;
+}
 
-            pressure_force += dir * shared_pressure * slope / neighbour.density.x;
-            pressure_force += dir * shared_pressure_near * slope_near / neighbour.density.y;
+// Takes `friction` of the velocity along a wall, `axis` is the wall normal.
+// Mirrored by `get_wall_friction_velocity` in fluid_container.rs.
+fn get_wall_friction_velocity(velocity: vec3<f32>, axis: vec3<f32>, friction: f32) -> vec3<f32> {
+    let normal_velocity = axis * dot(velocity, axis);
+    return normal_velocity + (velocity - normal_velocity) * (1. - friction);
+}
 
-            let viscosity = smoothing_kernel_viscosity(dst);
-            viscosity_force += (neighbour.velocity - velocity).xyz * viscosity;
+// Wraps or bounces off the two walls of `axis`, in container space. Returns the impact speed.
+// Mirrored by `FluidContainerExt::collide_axis`.
+fn collide_container_axis(index: u32, axis: u32) -> f32 {
+    let ext_min = fluid_container.ext_min[axis];
+    let ext_max = fluid_container.ext_max[axis];
+    var position = particles[index].position.xyz;
+    var velocity = particles[index].velocity.xyz;
+    var normal = vec3(0.);
+    normal[axis] = 1.;
+
+    var impact: f32 = 0.;
+    if fluid_container.wrap[axis] != 0. {
+        let size = ext_max - ext_min;
+        if position[axis] < ext_min {
+            position[axis] += size;
+        } else if position[axis] > ext_max {
+            position[axis] -= size;
         }
+    } else if position[axis] < ext_min {
+        impact = abs(velocity[axis]);
+        velocity[axis] = get_bounce_speed(velocity[axis], fluid_container.restitution_min[axis]);
+        velocity = get_wall_friction_velocity(velocity, normal, fluid_container.friction_min[axis]);
+        position[axis] = ext_min;
+    } else if position[axis] > ext_max {
+        impact = abs(velocity[axis]);
+        velocity[axis] = -get_bounce_speed(velocity[axis], fluid_container.restitution_max[axis]);
+        velocity = get_wall_friction_velocity(velocity, normal, fluid_container.friction_max[axis]);
+        position[axis] = ext_max;
     }
-    let pressure_contribution = pressure_force / particles[particle_index].density.x;
-    let viscosity_contribution = viscosity_force * fluid_props.viscosity_strength;
 
-    particles[particle_index].acceleration = vec4(pressure_contribution + viscosity_contribution, 0.);
+    particles[index].position = vec4(position, particles[index].position.w);
+    particles[index].velocity = vec4(velocity, particles[index].velocity.w);
+    return impact;
+}
+
+// Resolves the wall collisions in container space, where the walls are axis aligned. Returns the impact speed.
+// Mirrored by `FluidContainerExt::collide`.
+fn collide_container(index: u32) -> f32 {
+    let rotation = mat3x3<f32>(fluid_container.rotation[0].xyz, fluid_container.rotation[1].xyz, fluid_container.rotation[2].xyz);
+    let inverse_rotation = transpose(rotation);
+    let center = fluid_container.center.xyz;
+    particles[index].position = vec4(center + inverse_rotation * (particles[index].position.xyz - center), particles[index].position.w);
+    particles[index].velocity = vec4(inverse_rotation * particles[index].velocity.xyz, particles[index].velocity.w);
+
+    var impact: f32 = 0.;
+    for (var axis = 0u; axis < 3u; axis++) {
+        impact += collide_container_axis(index, axis);
+    }
+
+    particles[index].position = vec4(center + rotation * (particles[index].position.xyz - center), particles[index].position.w);
+    particles[index].velocity = vec4(rotation * particles[index].velocity.xyz, particles[index].velocity.w);
+    return impact;
+}
+
+fn collide_paddle(index: u32) {
+    let paddle_offset = particles[index].position.xyz - paddle.position.xyz;
+    let penetration = paddle.half_size.xyz - abs(paddle_offset);
+    if !all(penetration > vec3(0.)) {
+        return;
+    }
+
+    // Push out along the axis of the least penetration
+    var normal = vec3(0.);
+    if penetration.x <= penetration.y && penetration.x <= penetration.z {
+        normal.x = sign(paddle_offset.x);
+    } else if penetration.y <= penetration.z {
+        normal.y = sign(paddle_offset.y);
+    } else {
+        normal.z = sign(paddle_offset.z);
+    }
+    particles[index].position += vec4(normal * dot(penetration, abs(normal)), 0.);
+
+    // Reflect the velocity relative to the paddle, so the paddle motion is imparted
+    let normal_speed = dot(particles[index].velocity.xyz - paddle.velocity.xyz, normal);
+    if normal_speed < 0. {
+        particles[index].velocity -= vec4(normal * normal_speed * (1. + fluid_props.collision_damping), 0.);
+    }
+}
+
+// Same as `clamp_speed` on the CPU, zero disables the clamp
+fn clamp_speed(velocity: vec3<f32>) -> vec3<f32> {
+    let speed = length(velocity);
+    if fluid_props.max_speed > 0. && speed > fluid_props.max_speed {
+        return velocity * (fluid_props.max_speed / speed);
+    }
+    return velocity;
+}
+
+// Push and pull along the radial direction, stir around the camera axis, fading out to the radius
+fn get_cursor_acceleration(position: vec3<f32>) -> vec3<f32> {
+    let radius = world_cursor.position.w;
+    let offset = position - world_cursor.position.xyz;
+    let dst = length(offset);
+    if (world_cursor.force.x == 0. && world_cursor.force.y == 0.) || dst >= radius || dst == 0. {
+        return vec3(0.);
+    }
+    let radial = offset / dst;
+    // Perpendicular to the radial direction, counterclockwise as seen from the camera
+    let tangent = cross(world_cursor.axis.xyz, radial);
+    let falloff = 1. - dst / radius;
+    return (radial * world_cursor.force.x + tangent * world_cursor.force.y) * falloff;
+}
+
+// Particles count as unit masses, what they lose is handed to the ball in fixed point
+fn collide_rigid_ball(index: u32) {
+    let radius = rigid_ball.position.w;
+    let offset = particles[index].position.xyz - rigid_ball.position.xyz;
+    let dst = length(offset);
+    if dst >= radius {
+        return;
+    }
+    var normal = vec3(0., 1., 0.);
+    if dst > 0. {
+        normal = offset / dst;
+    }
+    particles[index].position = vec4(rigid_ball.position.xyz + normal * radius, particles[index].position.w);
+
+    // Reflect the velocity relative to the ball, like the paddle
+    let normal_speed = dot(particles[index].velocity.xyz - rigid_ball.velocity.xyz, normal);
+    if normal_speed < 0. {
+        let change = -normal * normal_speed * (1. + fluid_props.collision_damping);
+        particles[index].velocity += vec4(change, 0.);
+        let reaction = -change * BALL_FIXED_POINT_SCALE;
+        atomicAdd(&ball_impulse[0], i32(reaction.x));
+        atomicAdd(&ball_impulse[1], i32(reaction.y));
+        atomicAdd(&ball_impulse[2], i32(reaction.z));
+    }
+}
+
+// Spheres are capsules with both ends in the center
+fn collide_obstacles(index: u32) {
+    for (var i = 0u; i < obstacles.count.x; i++) {
+        let obstacle = obstacles.items[i];
+        let segment = obstacle.b.xyz - obstacle.a.xyz;
+        let segment_length_squared = dot(segment, segment);
+        var t = 0.;
+        if segment_length_squared > 0. {
+            t = clamp(dot(particles[index].position.xyz - obstacle.a.xyz, segment) / segment_length_squared, 0., 1.);
+        }
+        let closest = obstacle.a.xyz + segment * t;
+        let offset = particles[index].position.xyz - closest;
+        let dst = length(offset);
+        if dst >= obstacle.a.w {
+            continue;
+        }
+
+        var normal = vec3(0., 1., 0.);
+        if dst > 0. {
+            normal = offset / dst;
+        }
+        particles[index].position = vec4(closest + normal * obstacle.a.w, particles[index].position.w);
+
+        let normal_speed = dot(particles[index].velocity.xyz, normal);
+        if normal_speed < 0. {
+            particles[index].velocity -= vec4(normal * normal_speed * (1. + fluid_props.collision_damping), 0.);
+        }
+    }
 }
 
 @compute @workgroup_size(WORKGROUP_SIZE, 1, 1)
@@ -276,35 +658,46 @@ fn integrate(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
         return;
     }
 
-    // Integrate
-    particles[index].velocity += (gravity.value + particles[index].acceleration) * fluid_props.delta_time;
-    particles[index].position += particles[index].velocity * fluid_props.delta_time;
-
-    // Handle collisions
-    if particles[index].position.x < fluid_container.ext_min.x {
-        particles[index].velocity.x *= -1. * fluid_props.collision_damping;
-        particles[index].position.x = fluid_container.ext_min.x;
-    } else if particles[index].position.x > fluid_container.ext_max.x {
-        particles[index].velocity.x *= -1. * fluid_props.collision_damping;
-        particles[index].position.x = fluid_container.ext_max.x;
+    // Fast particles move in several shorter steps so they can't skip past a collider.
+    // The forces from this step's pressure pass are held for all of them.
+    var temperature = particles[index].temperature.y;
+    if heat_source.rate.x != 0. && distance(particles[index].position.xyz, heat_source.position.xyz) < heat_source.position.w {
+        temperature += heat_source.rate.x * fluid_props.delta_time;
     }
-
-    if particles[index].position.y < fluid_container.ext_min.y {
-        particles[index].velocity.y *= -1. * fluid_props.collision_damping;
-        particles[index].position.y = fluid_container.ext_min.y;
-    } else if particles[index].position.y > fluid_container.ext_max.y {
-        particles[index].velocity.y *= -1. * fluid_props.collision_damping;
-        particles[index].position.y = fluid_container.ext_max.y;
+    particles[index].temperature = vec4(temperature, temperature, 0., 0.);
+    // Warm particles rise against gravity, cool ones sink
+    let buoyancy_scale = -fluid_props.buoyancy * (temperature - fluid_props.ambient_temperature);
+
+    let substep_count = max(substeps, 1u);
+    let substep_time = fluid_props.delta_time / f32(substep_count);
+    var impact: f32 = 0.;
+    for (var substep = 0u; substep < substep_count; substep++) {
+        // Integrate
+        var gravity_value = gravity.value;
+        if gravity.radial.w != 0. {
+            let to_center = gravity.radial.xyz - particles[index].position.xyz;
+            if dot(to_center, to_center) > 0. {
+                gravity_value = vec4(normalize(to_center) * gravity.radial.w, 0.);
+            } else {
+                gravity_value = vec4(0.);
+            }
+        }
+        let cursor_acceleration = vec4(get_cursor_acceleration(particles[index].position.xyz), 0.);
+        particles[index].velocity += (gravity_value * (1. + buoyancy_scale) + particles[index].acceleration + cursor_acceleration) * substep_time;
+        particles[index].velocity = vec4(clamp_speed(particles[index].velocity.xyz), 0.);
+        particles[index].position += particles[index].velocity * substep_time;
+
+        impact += collide_container(index);
+        collide_paddle(index);
+        collide_obstacles(index);
+        collide_rigid_ball(index);
     }
 
-    if particles[index].position.z < fluid_container.ext_min.z {
-        particles[index].velocity.z *= -1. * fluid_props.collision_damping;
-        particles[index].position.z = fluid_container.ext_min.z;
-    } else if particles[index].position.z > fluid_container.ext_max.z {
-        particles[index].velocity.z *= -1. * fluid_props.collision_damping;
-        particles[index].position.z = fluid_container.ext_max.z;
+    // Accumulate wall impact momentum in fixed point, atomics only work on integers
+    if impact > SPLASH_MIN_IMPACT_SPEED {
+        atomicAdd(&wall_impact, u32(impact * SPLASH_FIXED_POINT_SCALE));
     }
 
     // Calculate predicted postions
-    particles[index].predicted_position = particles[index].position + particles[index].velocity * LOOKAHEAD_FACTOR;
+    particles[index].predicted_position = particles[index].position + particles[index].velocity * fluid_props.lookahead_time;
 }
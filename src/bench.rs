@@ -0,0 +1,119 @@
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use bevy_app_compute::prelude::*;
+
+use crate::soak::build_headless_app;
+use crate::fluid_compute::{
+    FluidCapacity, FluidPlugin, FluidShape, FluidStaticProps, FluidWorker, NeighborSearch, ParticleState,
+};
+
+const BENCH_SHAPE: UVec3 = UVec3::new(32, 16, 16);  // Fixed layout, comparable between runs
+const BENCH_MAX_FRAMES_PER_STEP: usize = 4;  // Bail out if the worker stalls
+
+
+#[derive(Clone, Copy, Debug)]
+pub struct BenchResult {
+    pub neighbor_search: NeighborSearch,
+    /// Simulation steps the worker completed, less than requested if it stalled
+    pub steps: usize,
+    pub particles: usize,
+    pub total_time: Duration,
+    pub avg_step_ms: f64,
+    /// Every position and velocity was finite after the last step
+    pub finite: bool,
+}
+
+
+impl BenchResult {
+    /// Stats of `steps` completed steps taking `total_time`, over the particles read back after them
+    pub fn from_run(neighbor_search: NeighborSearch, steps: usize, total_time: Duration, particles: &[ParticleState]) -> Self {
+        Self {
+            neighbor_search,
+            steps,
+            particles: particles.len(),
+            total_time,
+            avg_step_ms: total_time.as_secs_f64() * 1000. / steps.max(1) as f64,
+            finite: particles.iter().all(|it| it.position.is_finite() && it.velocity.is_finite()),
+        }
+    }
+
+    pub fn print(&self) {
+        println!(
+            "Bench ({:?}): {} steps of {} particles in {:.3}s, {:.3} ms/step, finite: {}",
+            self.neighbor_search, self.steps, self.particles, self.total_time.as_secs_f64(), self.avg_step_ms, self.finite,
+        );
+    }
+}
+
+
+/// Runs the default props on a block of particles without a window, `BENCH_SHAPE` unless given.
+/// Comparing the neighbour searches over growing blocks shows where the counting sort pulls ahead.
+pub fn run_headless_bench(steps: usize, neighbor_search: NeighborSearch, shape: Option<UVec3>) -> BenchResult {
+    let fluid_plugin = FluidPlugin::builder()
+        .shape(FluidShape::Cube(shape.unwrap_or(BENCH_SHAPE)))
+        .neighbor_search(neighbor_search)
+        .build();
+    let mut app = build_headless_app(FluidStaticProps::default(), fluid_plugin);
+
+    // Spawning and the first dispatch aren't part of the measurement
+    app.update();
+
+    let start = Instant::now();
+    let mut completed = 0;
+    for _ in 0..steps * BENCH_MAX_FRAMES_PER_STEP {
+        if completed >= steps {
+            break;
+        }
+        app.update();
+        if app.world.resource::<AppComputeWorker<FluidWorker>>().ready() {
+            completed += 1;
+        }
+    }
+    let total_time = start.elapsed();
+
+    let particles = ParticleState::read_all(
+        app.world.resource::<AppComputeWorker<FluidWorker>>(),
+        app.world.resource::<FluidCapacity>(),
+    );
+    BenchResult::from_run(neighbor_search, completed, total_time, &particles)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_particles(num_particles: usize) -> Vec<ParticleState> {
+        (0..num_particles)
+            .map(|it| ParticleState { position: Vec3::splat(it as f32), velocity: Vec3::X, ..default() })
+            .collect()
+    }
+
+    #[test]
+    fn averages_the_step_time() {
+        let result = BenchResult::from_run(NeighborSearch::default(), 4, Duration::from_millis(10), &make_particles(5));
+        assert_eq!(result.steps, 4);
+        assert_eq!(result.particles, 5);
+        assert!((result.avg_step_ms - 2.5).abs() < 1e-9, "{}", result.avg_step_ms);
+        assert!(result.finite);
+    }
+
+    #[test]
+    fn no_completed_steps_keeps_the_average_finite() {
+        let result = BenchResult::from_run(NeighborSearch::default(), 0, Duration::from_millis(3), &[]);
+        assert_eq!(result.steps, 0);
+        assert!((result.avg_step_ms - 3.).abs() < 1e-9, "{}", result.avg_step_ms);
+        assert!(result.finite);
+    }
+
+    #[test]
+    fn flags_non_finite_particles() {
+        let mut particles = make_particles(3);
+        particles[1].velocity.y = f32::NAN;
+        assert!(!BenchResult::from_run(NeighborSearch::default(), 1, Duration::ZERO, &particles).finite);
+        particles[1].velocity.y = 0.;
+        particles[2].position.x = f32::INFINITY;
+        assert!(!BenchResult::from_run(NeighborSearch::default(), 1, Duration::ZERO, &particles).finite);
+    }
+}
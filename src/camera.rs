@@ -1,14 +1,48 @@
 use bevy::prelude::*;
 use bevy::input::mouse::{MouseMotion, MouseWheel};
-use bevy::window::PrimaryWindow;
+use bevy::window::{PrimaryWindow, WindowResized};
 
 use crate::fluid_container::FluidContainer;
+use crate::fluid_compute::FluidStats;
+use crate::paddle::Paddle;
 use crate::schedule::InGameSet;
+use crate::world_cursor::WorldCursor;
+
+const CAMERA_FOLLOW_KEY: KeyCode = KeyCode::KeyF;
+const CAMERA_FOLLOW_SMOOTHING: f32 = 3.;
+const CAMERA_FOLLOW_FIT_MARGIN: f32 = 1.5;
+const CAMERA_PAN_SPEED: f32 = 0.5;  // Orbit radii per second
+const CAMERA_RECENTER_KEY: KeyCode = KeyCode::Home;
+const CAMERA_FRAMING_MARGIN: f32 = 1.2;  // Room left around the container
+const CAMERA_ZOOM_STEP: f32 = 0.2;  // Fraction of the distance per scroll line
+const CAMERA_MIN_RADIUS: f32 = 0.05;  // Zero would get stuck
 
 #[derive(Component, Debug)]
 pub struct Observer;
 
 
+/// Tracks the fluid's center of mass instead of the manually panned focus point
+#[derive(Resource, Debug)]
+pub struct CameraFollow {
+    pub enabled: bool,
+    /// Rate at which the focus catches up with the center of mass, per second
+    pub smoothing: f32,
+    /// Also move the camera in or out so the fluid bounding box stays in frame
+    pub zoom_to_fit: bool,
+}
+
+
+impl Default for CameraFollow {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            smoothing: CAMERA_FOLLOW_SMOOTHING,
+            zoom_to_fit: false,
+        }
+    }
+}
+
+
 #[derive(Component)]
 struct PanOrbitCamera {
     /// The "focus point" to orbit around. It is automatically updated when panning the camera
@@ -29,23 +63,78 @@ impl Default for PanOrbitCamera {
 }
 
 
+/// Which container extent decides the camera distance
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FramingAxis {
+    Vertical,
+    /// The container is wider than the window, relative to their heights
+    Horizontal,
+}
+
+
+/// Width over height of the container's front face against the window's
+pub fn get_framing_axis(container_aspect: f32, window_aspect: f32) -> FramingAxis {
+    if container_aspect > window_aspect { FramingAxis::Horizontal } else { FramingAxis::Vertical }
+}
+
+
+/// Distance from the container center at which its front face fits the vertical field of view with margin
+pub fn get_framing_distance(container_size: Vec3, window_aspect: f32, fov: f32) -> f32 {
+    let half_fov_tan = (fov / 2.).tan();
+    let visible_half_height = match get_framing_axis(container_size.x / container_size.y, window_aspect) {
+        FramingAxis::Vertical => container_size.y / 2.,
+        FramingAxis::Horizontal => container_size.x / 2. / window_aspect,
+    };
+    // Measured to the front face, the container's near half sits between it and the camera
+    visible_half_height * CAMERA_FRAMING_MARGIN / half_fov_tan + container_size.z / 2.
+}
+
+
+/// Scales the orbit about a world point, which keeps that point at the same place on screen.
+/// Returns the new focus and radius.
+pub fn zoom_about_point(focus: Vec3, radius: f32, point: Vec3, new_radius: f32) -> (Vec3, f32) {
+    if radius <= 0. {
+        return (focus, new_radius);
+    }
+    let scale = new_radius / radius;
+    (point + (focus - point) * scale, new_radius)
+}
+
+
 pub struct CameraPlugin;
 
 
 impl Plugin for CameraPlugin {
     fn build(&self, app: &mut App) {
         app
+            .init_resource::<CameraFollow>()
             .add_systems(Startup, spawn_camera)
-            .add_systems(Update, update_camera_position.in_set(InGameSet::UserInput));
+            .add_systems(Update, (
+                toggle_camera_follow,
+                update_camera_position,
+                pan_camera_with_keys,
+            ).in_set(InGameSet::UserInput))
+            .add_systems(Update, follow_center_of_mass.in_set(InGameSet::EntityUpdates))
+            .add_systems(Update, frame_container);
     }
 }
 
 
-fn spawn_camera(mut commands: Commands, container: Res<FluidContainer>) {
-    let mut camera_translation = container.size.xyz() / 2.;
-    camera_translation.z *= 5.;  // Set it further
-    camera_translation.x = 0.; // Set on X axis
-    camera_translation += container.position.xyz();  // Translate to container origin
+fn spawn_camera(
+    mut commands: Commands,
+    container: Res<FluidContainer>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+) {
+    // Slightly from above, looking down the Z axis
+    let mut direction = container.size.xyz() / 2.;
+    direction.z *= 5.;
+    direction.x = 0.;
+    let direction = direction.normalize_or_zero();
+
+    let projection = PerspectiveProjection::default();
+    let window_aspect = window_query.get_single().map_or(projection.aspect_ratio, |window| window.width() / window.height());
+    let radius = get_framing_distance(container.size, window_aspect, projection.fov);
+    let camera_translation = container.position + direction * radius;
 
     commands.spawn((
         Camera3dBundle {
@@ -53,7 +142,8 @@ fn spawn_camera(mut commands: Commands, container: Res<FluidContainer>) {
             ..default()
         },
         PanOrbitCamera {
-            radius: camera_translation.length(),
+            focus: container.position,
+            radius,
             ..default()
         },
         Observer,
@@ -61,12 +151,45 @@ fn spawn_camera(mut commands: Commands, container: Res<FluidContainer>) {
 }
 
 
+/// Moves the camera in or out so the whole container stays in view, on resizes of either
+fn frame_container(
+    mut resize_events: EventReader<WindowResized>,
+    mut query: Query<(&mut PanOrbitCamera, &mut Transform, &Projection), With<Observer>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    container: Res<FluidContainer>,
+    follow: Res<CameraFollow>,
+) {
+    let resized = !resize_events.is_empty();
+    resize_events.clear();
+    if !resized && !(container.is_changed() && !container.is_added()) {
+        return;
+    }
+    // The follow camera fits the fluid instead
+    if follow.enabled && follow.zoom_to_fit {
+        return;
+    }
+    let Ok(window) = window_query.get_single() else { return };
+    if window.height() <= 0. {
+        return;
+    }
+
+    for (mut pan_orbit, mut transform, projection) in query.iter_mut() {
+        let Projection::Perspective(projection) = projection else { continue };
+        pan_orbit.radius = get_framing_distance(container.size, window.width() / window.height(), projection.fov);
+
+        let rot_matrix = Mat3::from_quat(transform.rotation);
+        transform.translation = pan_orbit.focus + rot_matrix.mul_vec3(Vec3::new(0.0, 0.0, pan_orbit.radius));
+    }
+}
+
+
 fn update_camera_position(
     mut motion_events: EventReader<MouseMotion>,
     mut scroll_events: EventReader<MouseWheel>,
     mut query: Query<(&mut PanOrbitCamera, &mut Transform, &Projection)>,
     window_query: Query<&Window, With<PrimaryWindow>>,
     mouse_input: Res<ButtonInput<MouseButton>>,
+    world_cursor: Option<Res<WorldCursor>>,
 ) {
     let Ok(window) = window_query.get_single() else { return };
     let window = Vec2::new(window.width() as f32, window.height() as f32);
@@ -84,7 +207,7 @@ fn update_camera_position(
         for event in motion_events.read() {
             rotation_move += event.delta;
         }
-    } else if mouse_input.pressed(pan_button) {
+    } else if mouse_input.pressed(pan_button) && !world_cursor.as_ref().is_some_and(|cursor| cursor.captures_drag()) {
         // Pan only if we're not rotating at the moment
         for event in motion_events.read() {
             pan += event.delta;
@@ -131,9 +254,10 @@ fn update_camera_position(
             pan_orbit.focus += translation;
         } else if scroll.abs() > 0.0 {
             any = true;
-            pan_orbit.radius -= scroll * pan_orbit.radius * 0.2;
-            // dont allow zoom to reach zero or you get stuck
-            pan_orbit.radius = pan_orbit.radius.max(0.05);
+            let new_radius = (pan_orbit.radius - scroll * pan_orbit.radius * CAMERA_ZOOM_STEP).max(CAMERA_MIN_RADIUS);
+            // Toward the point under the cursor, the orbit focus when there is none
+            let point = world_cursor.as_ref().map_or(pan_orbit.focus, |cursor| cursor.position);
+            (pan_orbit.focus, pan_orbit.radius) = zoom_about_point(pan_orbit.focus, pan_orbit.radius, point, new_radius);
         }
 
         if any {
@@ -149,3 +273,172 @@ fn update_camera_position(
     // (and also to avoid Bevy warning us about not checking events every frame update)
     motion_events.clear();
 }
+
+
+/// Left/right arrows pan sideways while the paddle doesn't use them, page up/down pan vertically
+fn pan_camera_with_keys(
+    mut query: Query<(&mut PanOrbitCamera, &mut Transform)>,
+    follow: Res<CameraFollow>,
+    paddle: Res<Paddle>,
+    container: Res<FluidContainer>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+) {
+    // The follow camera owns the focus
+    if follow.enabled {
+        return;
+    }
+
+    let recenter = keyboard_input.just_pressed(CAMERA_RECENTER_KEY);
+    let mut direction = Vec2::ZERO;
+    if !paddle.enabled {
+        if keyboard_input.pressed(KeyCode::ArrowLeft) {
+            direction.x -= 1.;
+        }
+        if keyboard_input.pressed(KeyCode::ArrowRight) {
+            direction.x += 1.;
+        }
+    }
+    if keyboard_input.pressed(KeyCode::PageUp) {
+        direction.y += 1.;
+    }
+    if keyboard_input.pressed(KeyCode::PageDown) {
+        direction.y -= 1.;
+    }
+    if direction == Vec2::ZERO && !recenter {
+        return;
+    }
+
+    for (mut pan_orbit, mut transform) in query.iter_mut() {
+        if recenter {
+            pan_orbit.focus = container.position;
+        } else {
+            // Proportional to the distance, so it feels the same at every zoom level
+            let distance = CAMERA_PAN_SPEED * pan_orbit.radius * time.delta_seconds();
+            let right = transform.rotation * Vec3::X * direction.x;
+            let up = transform.rotation * Vec3::Y * direction.y;
+            pan_orbit.focus += (right + up) * distance;
+        }
+
+        let rot_matrix = Mat3::from_quat(transform.rotation);
+        transform.translation = pan_orbit.focus + rot_matrix.mul_vec3(Vec3::new(0.0, 0.0, pan_orbit.radius));
+    }
+}
+
+
+fn toggle_camera_follow(mut follow: ResMut<CameraFollow>, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if keyboard_input.just_pressed(CAMERA_FOLLOW_KEY) {
+        follow.enabled = !follow.enabled;
+    }
+}
+
+
+fn follow_center_of_mass(
+    mut query: Query<(&mut PanOrbitCamera, &mut Transform, &Projection)>,
+    follow: Res<CameraFollow>,
+    stats: Res<FluidStats>,
+    time: Res<Time>,
+) {
+    if !follow.enabled {
+        return;
+    }
+
+    // Frame rate independent exponential smoothing
+    let factor = 1. - (-follow.smoothing * time.delta_seconds()).exp();
+    for (mut pan_orbit, mut transform, projection) in query.iter_mut() {
+        pan_orbit.focus = pan_orbit.focus.lerp(stats.center_of_mass, factor);
+
+        if follow.zoom_to_fit {
+            if let Projection::Perspective(projection) = projection {
+                let half_extent = (stats.bounds_max - stats.bounds_min).length() / 2.;
+                let fit_radius = half_extent * CAMERA_FOLLOW_FIT_MARGIN / (projection.fov / 2.).tan();
+                pan_orbit.radius += (fit_radius.max(0.05) - pan_orbit.radius) * factor;
+            }
+        }
+
+        let rot_matrix = Mat3::from_quat(transform.rotation);
+        transform.translation = pan_orbit.focus + rot_matrix.mul_vec3(Vec3::new(0.0, 0.0, pan_orbit.radius));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FOV: f32 = std::f32::consts::FRAC_PI_4;
+    const ASPECT: f32 = 16. / 9.;
+
+    /// Normalized device coordinates of a world point, seen from the camera transform
+    fn project(transform: &Transform, point: Vec3) -> Vec2 {
+        let projection = Mat4::perspective_rh(FOV, ASPECT, 0.1, 1000.);
+        let clip = projection * transform.compute_matrix().inverse() * point.extend(1.);
+        clip.xy() / clip.w
+    }
+
+    fn orbit_transform(focus: Vec3, radius: f32, rotation: Quat) -> Transform {
+        Transform::from_translation(focus + rotation * Vec3::new(0., 0., radius)).with_rotation(rotation)
+    }
+
+    #[test]
+    fn zoom_keeps_the_point_on_screen() {
+        let rotation = Quat::from_rotation_y(0.4) * Quat::from_rotation_x(-0.3);
+        let focus = Vec3::new(1., 0.5, -2.);
+        let radius = 10.;
+        let point = Vec3::new(2.5, 1., -1.);
+
+        let before = project(&orbit_transform(focus, radius, rotation), point);
+        for new_radius in [radius * 0.8, radius * 1.2] {
+            let (new_focus, new_radius) = zoom_about_point(focus, radius, point, new_radius);
+            let after = project(&orbit_transform(new_focus, new_radius, rotation), point);
+            assert!(before.distance(after) < 1e-4, "{:?} moved to {:?}", before, after);
+        }
+    }
+
+    #[test]
+    fn zoom_about_the_focus_keeps_it() {
+        let focus = Vec3::new(1., 2., 3.);
+        let (new_focus, new_radius) = zoom_about_point(focus, 5., focus, 4.);
+        assert!(new_focus.distance(focus) < 1e-6);
+        assert_eq!(new_radius, 4.);
+    }
+
+    #[test]
+    fn zoom_from_a_zero_radius_keeps_the_focus() {
+        let focus = Vec3::new(1., 2., 3.);
+        assert_eq!(zoom_about_point(focus, 0., Vec3::ZERO, 2.), (focus, 2.));
+        assert_eq!(zoom_about_point(focus, -1., Vec3::ZERO, 2.), (focus, 2.));
+    }
+
+    #[test]
+    fn tall_container_frames_vertically() {
+        assert_eq!(get_framing_axis(0.5, ASPECT), FramingAxis::Vertical);
+    }
+
+    #[test]
+    fn wide_container_frames_horizontally() {
+        assert_eq!(get_framing_axis(4., ASPECT), FramingAxis::Horizontal);
+    }
+
+    #[test]
+    fn square_container_in_a_square_window_frames_vertically() {
+        assert_eq!(get_framing_axis(1., 1.), FramingAxis::Vertical);
+    }
+
+    #[test]
+    fn framed_container_fits_the_view() {
+        for size in [Vec3::new(2., 8., 2.), Vec3::new(20., 4., 3.), Vec3::new(6., 6., 6.)] {
+            let distance = get_framing_distance(size, ASPECT, FOV);
+            let transform = Transform::from_translation(Vec3::new(0., 0., distance));
+            for corner in 0..8 {
+                let sign = Vec3::new(
+                    if corner & 1 == 0 { -1. } else { 1. },
+                    if corner & 2 == 0 { -1. } else { 1. },
+                    if corner & 4 == 0 { -1. } else { 1. },
+                );
+                let ndc = project(&transform, sign * size / 2.);
+                assert!(ndc.abs().max_element() <= 1., "corner {:?} of {:?} at {:?}", sign, size, ndc);
+            }
+        }
+    }
+}
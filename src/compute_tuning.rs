@@ -0,0 +1,152 @@
+use bevy::prelude::*;
+use bevy::render::render_resource::Source;
+
+const DEFAULT_WORKGROUP_SIZE: u32 = 1024;
+const WORKGROUP_SIZE_DECLARATION: &str = "const WORKGROUP_SIZE: u32";
+const TUNED_SHADERS: [&str; 3] = ["simulation.wgsl", "bitonic_sort.wgsl", "counting_sort.wgsl"];
+
+
+/// Device dependent dispatch settings, insert before the fluid plugin builds to override
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct ComputeTuning {
+    /// Invocations per workgroup, shared by every compute pass
+    pub workgroup_size: u32,
+}
+
+
+impl Default for ComputeTuning {
+    fn default() -> Self {
+        Self {
+            workgroup_size: DEFAULT_WORKGROUP_SIZE,
+        }
+    }
+}
+
+
+impl ComputeTuning {
+    /// Panics on a size the shaders or the device can't run
+    pub fn validate(&self, max_invocations: u32) {
+        if let Err(err) = check_workgroup_size(self.workgroup_size, max_invocations) {
+            panic!("ComputeTuning: {}", err);
+        }
+    }
+
+    /// Workgroups needed to cover `data_length` invocations
+    pub fn get_batch_size(&self, data_length: u32) -> u32 {
+        data_length.div_ceil(self.workgroup_size)
+    }
+
+    /// Rewrites the `WORKGROUP_SIZE` constant of a shader source
+    fn patch_source(&self, source: &str) -> String {
+        source
+            .lines()
+            .map(|line| if line.starts_with(WORKGROUP_SIZE_DECLARATION) {
+                format!("{} = {};", WORKGROUP_SIZE_DECLARATION, self.workgroup_size)
+            } else {
+                line.to_string()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+
+/// The shaders need a non-zero power of two, the device caps the invocations per workgroup
+pub fn check_workgroup_size(workgroup_size: u32, max_invocations: u32) -> Result<(), String> {
+    if !workgroup_size.is_power_of_two() {
+        return Err(format!("workgroup_size must be a non-zero power of two, got {}", workgroup_size));
+    }
+    if workgroup_size > max_invocations {
+        return Err(format!(
+            "workgroup_size {} exceeds the device limit of {} invocations per workgroup",
+            workgroup_size,
+            max_invocations,
+        ));
+    }
+    Ok(())
+}
+
+
+pub struct ComputeTuningPlugin;
+
+
+impl Plugin for ComputeTuningPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<ComputeTuning>()
+            .add_systems(Update, sync_shader_workgroup_size);
+    }
+}
+
+
+/// Keeps `@workgroup_size` in the compute shaders in sync with the tuning.
+/// The pipeline cache recompiles the passes once the patched shader lands.
+fn sync_shader_workgroup_size(
+    mut events: EventReader<AssetEvent<Shader>>,
+    mut shaders: ResMut<Assets<Shader>>,
+    asset_server: Res<AssetServer>,
+    tuning: Res<ComputeTuning>,
+) {
+    for event in events.read() {
+        let (AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id }) = event else {
+            continue;
+        };
+        let Some(path) = asset_server.get_path(*id) else { continue };
+        if !TUNED_SHADERS.iter().any(|name| path.path().ends_with(name)) {
+            continue;
+        }
+        let Some(shader) = shaders.get(*id) else { continue };
+        let Source::Wgsl(source) = &shader.source else { continue };
+
+        let patched = tuning.patch_source(source);
+        // Already in sync, this also ends the modified event our own insert triggers
+        if patched == *source {
+            continue;
+        }
+        let patched = Shader::from_wgsl(patched, shader.path.clone());
+        shaders.insert(*id, patched);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_size_covers_every_invocation() {
+        let tuning = ComputeTuning { workgroup_size: 64 };
+        assert_eq!(tuning.get_batch_size(0), 0);
+        assert_eq!(tuning.get_batch_size(1), 1);
+        assert_eq!(tuning.get_batch_size(64), 1);
+        assert_eq!(tuning.get_batch_size(65), 2);
+        assert_eq!(tuning.get_batch_size(65536), 1024);
+    }
+
+    #[test]
+    fn workgroup_size_is_a_power_of_two() {
+        assert!(check_workgroup_size(64, 1024).is_ok());
+        assert!(check_workgroup_size(1, 1024).is_ok());
+        assert!(check_workgroup_size(0, 1024).is_err());
+        assert!(check_workgroup_size(96, 1024).is_err());
+    }
+
+    #[test]
+    fn workgroup_size_is_within_the_device_limit() {
+        assert!(check_workgroup_size(256, 256).is_ok());
+        assert!(check_workgroup_size(512, 256).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "power of two")]
+    fn validate_panics_with_the_reason() {
+        ComputeTuning { workgroup_size: 48 }.validate(1024);
+    }
+
+    #[test]
+    fn patch_source_rewrites_the_workgroup_size() {
+        let tuning = ComputeTuning { workgroup_size: 256 };
+        let source = "const WORKGROUP_SIZE: u32 = 1024;\nfn main() {}";
+        assert_eq!(tuning.patch_source(source), "const WORKGROUP_SIZE: u32 = 256;\nfn main() {}");
+    }
+}
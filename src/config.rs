@@ -0,0 +1,142 @@
+use std::path::Path;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::gravity::Gravity;
+use crate::fluid_container::FluidContainer;
+use crate::fluid_compute::{FluidRandomSeed, FluidSpawnImage, FluidStaticProps, ParticleMeshSettings};
+
+const CONFIG_PATH: &str = "config.ron";
+
+
+/// Run settings read from `config.ron`, every field is optional
+#[derive(Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct FluidConfig {
+    pub fluid_props: FluidStaticProps,
+    /// Simulation steps per second, sets the step length and the fixed clock dispatching the steps.
+    /// At most one step runs per frame, rates above the frame rate slow the simulation down.
+    /// Higher rates are more stable and cost more per simulated second.
+    pub sim_hz: Option<f32>,
+    /// Prediction horizon of the density and pressure passes, independent of the step length.
+    /// Longer horizons push back on compression earlier and feel stiffer.
+    pub lookahead_seconds: Option<f32>,
+    /// Downward acceleration
+    pub gravity: Option<f32>,
+    pub container_size: Option<[f32; 3]>,
+    /// Seed of the random scenario
+    pub seed: Option<u64>,
+    /// Grayscale image of the image scenario
+    pub spawn_image: Option<String>,
+    /// Drawn particle size, larger than the physics radius gives a fuller look
+    pub render_radius: Option<f32>,
+}
+
+
+impl FluidConfig {
+    /// Falls back to the defaults when the file is absent or malformed
+    pub fn load(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(_) => return Self::default(),
+        };
+        match ron::from_str(&source) {
+            Ok(config) => {
+                println!("Config: loaded {}", path.display());
+                config
+            },
+            Err(err) => {
+                println!("Config: {} is malformed, falling back to defaults ({})", path.display(), err);
+                Self::default()
+            },
+        }
+    }
+}
+
+
+/// Add before the fluid plugin, the worker is built from the loaded resources
+pub struct ConfigPlugin;
+
+
+impl Plugin for ConfigPlugin {
+    fn build(&self, app: &mut App) {
+        let mut config = FluidConfig::load(CONFIG_PATH);
+        match config.sim_hz {
+            Some(hz) if hz > 0. => config.fluid_props.delta_time = 1. / hz,
+            Some(hz) => println!("Warning: sim_hz of {} ignored, it has to be positive", hz),
+            None => (),
+        }
+        if let Some(lookahead) = config.lookahead_seconds {
+            config.fluid_props.lookahead_time = lookahead;
+        }
+        app.insert_resource(config.fluid_props);
+        if let Some(gravity) = config.gravity {
+            app.insert_resource(Gravity::new(Vec4::new(0., -gravity, 0., 0.)));
+        }
+        if let Some(size) = config.container_size {
+            app.insert_resource(FluidContainer {
+                size: Vec3::from_array(size),
+                ..default()
+            });
+        }
+        if let Some(seed) = config.seed {
+            app.insert_resource(FluidRandomSeed(seed));
+        }
+        if let Some(path) = config.spawn_image {
+            app.insert_resource(FluidSpawnImage(path.into()));
+        }
+        if let Some(render_radius) = config.render_radius {
+            app.insert_resource(ParticleMeshSettings {
+                render_radius,
+                ..default()
+            });
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Unique per test, the tests run in parallel
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("water-sandbox-{}-{}.ron", std::process::id(), name))
+    }
+
+    #[test]
+    fn loads_a_present_file() {
+        let path = temp_path("present");
+        std::fs::write(&path, "(sim_hz: Some(120.), gravity: Some(4.5), container_size: Some((8., 4., 2.)), seed: Some(7))").unwrap();
+        let config = FluidConfig::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(config.sim_hz, Some(120.));
+        assert_eq!(config.gravity, Some(4.5));
+        assert_eq!(config.container_size, Some([8., 4., 2.]));
+        assert_eq!(config.seed, Some(7));
+        // Missing fields keep their defaults
+        assert_eq!(config.spawn_image, None);
+        assert_eq!(config.render_radius, None);
+    }
+
+    #[test]
+    fn absent_file_keeps_the_defaults() {
+        let config = FluidConfig::load(temp_path("absent"));
+        assert_eq!(config.sim_hz, None);
+        assert_eq!(config.gravity, None);
+        assert_eq!(config.seed, None);
+    }
+
+    #[test]
+    fn malformed_file_keeps_the_defaults() {
+        let path = temp_path("malformed");
+        std::fs::write(&path, "(sim_hz: \"fast\", gravity: ").unwrap();
+        let config = FluidConfig::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(config.sim_hz, None);
+        assert_eq!(config.gravity, None);
+        assert_eq!(config.container_size, None);
+    }
+}
@@ -0,0 +1,129 @@
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+
+use crate::schedule::InGameSet;
+use crate::camera::Observer;
+use crate::fluid_container::FluidContainer;
+use crate::fluid_compute::FluidStaticProps;
+
+const CONTAINER_HANDLE_BUTTON: MouseButton = MouseButton::Left;
+const CONTAINER_HANDLE_PICK_RADIUS: f32 = 24.;  // Pixels
+const CONTAINER_HANDLE_RADIUS: f32 = 0.25;
+const CONTAINER_HANDLE_COLOR: Color = Color::WHITE;
+const CONTAINER_HANDLE_ACTIVE_COLOR: Color = Color::ORANGE;
+
+
+/// Side walls of the container that can be dragged
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ContainerWall {
+    MinX,
+    MaxX,
+    MinZ,
+    MaxZ,
+}
+
+
+impl ContainerWall {
+    const ALL: [ContainerWall; 4] = [ContainerWall::MinX, ContainerWall::MaxX, ContainerWall::MinZ, ContainerWall::MaxZ];
+
+    /// Outward normal in the unrotated container frame
+    fn get_normal(&self) -> Vec3 {
+        match self {
+            ContainerWall::MinX => Vec3::NEG_X,
+            ContainerWall::MaxX => Vec3::X,
+            ContainerWall::MinZ => Vec3::NEG_Z,
+            ContainerWall::MaxZ => Vec3::Z,
+        }
+    }
+
+    fn get_handle_position(&self, container: &FluidContainer) -> Vec3 {
+        let normal = self.get_normal();
+        container.position + container.rotation * (normal * container.size / 2.)
+    }
+}
+
+
+#[derive(Resource, Default, Debug)]
+pub struct ContainerDrag {
+    pub wall: Option<ContainerWall>,
+    last_cursor: Vec2,
+}
+
+
+pub struct ContainerHandlesPlugin;
+
+
+impl Plugin for ContainerHandlesPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<ContainerDrag>()
+            .add_systems(Update, drag_container_walls.in_set(InGameSet::UserInput))
+            .add_systems(Update, draw_container_handles.in_set(InGameSet::EntityUpdates));
+    }
+}
+
+
+/// Picks the handle under the cursor in screen space and moves its wall along the wall normal
+fn drag_container_walls(
+    mut drag: ResMut<ContainerDrag>,
+    mut container: ResMut<FluidContainer>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<Observer>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    fluid_props: Res<FluidStaticProps>,
+) {
+    if mouse_input.just_released(CONTAINER_HANDLE_BUTTON) {
+        drag.wall = None;
+        return;
+    }
+    let Ok((camera, camera_transform)) = camera_query.get_single() else { return };
+    let Ok(window) = window_query.get_single() else { return };
+    let Some(cursor) = window.cursor_position() else { return };
+
+    if mouse_input.just_pressed(CONTAINER_HANDLE_BUTTON) {
+        drag.wall = ContainerWall::ALL.iter()
+            .filter_map(|wall| {
+                let handle = camera.world_to_viewport(camera_transform, wall.get_handle_position(&container))?;
+                Some((*wall, handle.distance(cursor)))
+            })
+            .filter(|(_, distance)| *distance < CONTAINER_HANDLE_PICK_RADIUS)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(wall, _)| wall);
+        drag.last_cursor = cursor;
+        return;
+    }
+    let Some(wall) = drag.wall else { return };
+    let cursor_delta = cursor - drag.last_cursor;
+    drag.last_cursor = cursor;
+    if cursor_delta == Vec2::ZERO {
+        return;
+    }
+
+    // Screen space direction and length of one world unit along the wall normal
+    let handle = wall.get_handle_position(&container);
+    let world_normal = container.rotation * wall.get_normal();
+    let Some(start) = camera.world_to_viewport(camera_transform, handle) else { return };
+    let Some(end) = camera.world_to_viewport(camera_transform, handle + world_normal) else { return };
+    let screen_normal = end - start;
+    let pixels_per_unit = screen_normal.length();
+    if pixels_per_unit < f32::EPSILON {
+        return;
+    }
+    let movement = cursor_delta.dot(screen_normal) / (pixels_per_unit * pixels_per_unit);
+
+    // The wall moves, the opposite one stays put
+    let axis = wall.get_normal().abs();
+    let min_size = 2. * (fluid_props.smoothing_radius + container.wall_margin);
+    let size = container.size.dot(axis);
+    let movement = (size + movement).max(min_size) - size;
+    container.size += axis * movement;
+    container.position += world_normal * movement / 2.;
+}
+
+
+fn draw_container_handles(mut gizmos: Gizmos, container: Res<FluidContainer>, drag: Res<ContainerDrag>) {
+    for wall in ContainerWall::ALL {
+        let color = if drag.wall == Some(wall) { CONTAINER_HANDLE_ACTIVE_COLOR } else { CONTAINER_HANDLE_COLOR };
+        gizmos.sphere(wall.get_handle_position(&container), container.rotation, CONTAINER_HANDLE_RADIUS, color);
+    }
+}
@@ -1,11 +1,91 @@
 use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use bevy_app_compute::prelude::*;
+
+use crate::schedule::InGameSet;
+use crate::fluid_container::FluidContainer;
+use crate::fluid_compute::{
+    FluidCapacity, FluidParticle, FluidPassSchedule, FluidStaticProps, FluidWorker, IndexReadback, SpatialGrid,
+};
+
+const DUMP_PASS_SCHEDULE_KEY: KeyCode = KeyCode::F3;
+const NEIGHBOR_SEARCH_CHECK_KEY: KeyCode = KeyCode::F4;
+const NEIGHBOR_SEARCH_CHECK_SAMPLES: usize = 64;  // Each brute-forces over every particle
+const GRID_OVERLAY_KEY: KeyCode = KeyCode::F10;
+const GRID_OVERLAY_BOUNDS_COLOR: Color = Color::rgba(1., 1., 1., 0.3);
+const GRID_OVERLAY_MISMATCH_COLOR: Color = Color::FUCHSIA;  // Hashed somewhere the position doesn't lead to
+const VELOCITY_GIZMOS_KEY: KeyCode = KeyCode::Comma;
+const VELOCITY_GIZMOS_SCALE_UP_KEY: KeyCode = KeyCode::NumpadAdd;
+const VELOCITY_GIZMOS_SCALE_DOWN_KEY: KeyCode = KeyCode::NumpadSubtract;
+const VELOCITY_GIZMOS_STRIDE: usize = 8;  // Every Nth particle gets a line
+const VELOCITY_GIZMOS_SCALE: f32 = 0.05;  // Line length per unit of speed
+const VELOCITY_GIZMOS_SCALE_STEP: f32 = 1.5;
+const VELOCITY_GIZMOS_COLOR: Color = Color::rgb(1., 0.9, 0.3);
+
+
+/// Compares the GPU cell lookup against a brute-force scan for a sample of particles.
+/// Expensive and needs the index buffers staged, so it is off until toggled.
+#[derive(Resource, Default, Debug)]
+pub struct NeighborSearchCheck {
+    pub enabled: bool,
+    pub miss_rate: f32,
+}
+
+
+/// Draws the occupied spatial hash cells, tinted by how many particles the GPU put in their hash slot.
+/// Off until toggled, the read-backs are only done while it is shown.
+#[derive(Resource, Default, Debug)]
+pub struct GridOverlay {
+    pub enabled: bool,
+    pub cell_size: f32,
+    pub non_empty_cells: usize,
+    /// Live particles whose hash slot has no offset, or an offset into another slot
+    pub mismatched: usize,
+}
+
+
+/// Draws a line along the velocity of a sample of the particles, from the read-back buffer.
+/// Off until toggled.
+#[derive(Resource, Debug)]
+pub struct VelocityGizmos {
+    pub enabled: bool,
+    /// Sample every Nth particle
+    pub stride: usize,
+    /// Line length per unit of speed
+    pub scale: f32,
+}
+
+
+impl Default for VelocityGizmos {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            stride: VELOCITY_GIZMOS_STRIDE,
+            scale: VELOCITY_GIZMOS_SCALE,
+        }
+    }
+}
+
 
 pub struct DebugPlugin;
 
 
 impl Plugin for DebugPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, log_debug_presence);
+        app
+            .init_resource::<NeighborSearchCheck>()
+            .init_resource::<GridOverlay>()
+            .init_resource::<VelocityGizmos>()
+            .add_systems(Startup, log_debug_presence)
+            .add_systems(Update, dump_pass_schedule.run_if(resource_exists::<FluidPassSchedule>))
+            .add_systems(Update, (
+                toggle_neighbor_search_check,
+                check_neighbor_search,
+                toggle_grid_overlay,
+                draw_grid_overlay,
+                update_velocity_gizmos,
+                draw_velocity_gizmos,
+            ).chain().in_set(InGameSet::EntityUpdates));
     }
 }
 
@@ -13,3 +93,261 @@ impl Plugin for DebugPlugin {
 fn log_debug_presence() {
     println!("[DEBUG] INFO log: Debugger is active for this session!");
 }
+
+
+fn dump_pass_schedule(schedule: Res<FluidPassSchedule>, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if !keyboard_input.just_pressed(DUMP_PASS_SCHEDULE_KEY) {
+        return;
+    }
+
+    println!("[DEBUG] INFO log: GPU pass schedule ({} passes)", schedule.passes.len());
+    for (it, pass) in schedule.passes.iter().enumerate() {
+        println!("[DEBUG]   {:>3}: {} {:?}", it, pass.name, pass.workgroups);
+    }
+}
+
+
+fn toggle_neighbor_search_check(
+    mut check: ResMut<NeighborSearchCheck>,
+    mut readback: ResMut<IndexReadback>,
+    overlay: Res<GridOverlay>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+) {
+    if keyboard_input.just_pressed(NEIGHBOR_SEARCH_CHECK_KEY) {
+        check.enabled = !check.enabled;
+        check.miss_rate = 0.;
+        readback.enabled = check.enabled || overlay.enabled;
+    }
+}
+
+
+/// Read-back of the staged neighbour search buffers
+struct IndexBuffers {
+    particle_indicies: Vec<u32>,
+    particle_cell_indicies: Vec<u32>,
+    cell_offsets: Vec<u32>,
+}
+
+
+impl IndexBuffers {
+    fn read(worker: &AppComputeWorker<FluidWorker>) -> Self {
+        Self {
+            particle_indicies: worker.read_vec::<u32>("particle_indicies"),
+            particle_cell_indicies: worker.read_vec::<u32>("particle_cell_indicies"),
+            cell_offsets: worker.read_vec::<u32>("cell_offsets"),
+        }
+    }
+}
+
+
+/// Cell of a position like the shader's `get_cell`, clamped into the grid
+fn get_grid_cell(grid: &SpatialGrid, position: Vec3) -> IVec3 {
+    let cell = ((position - grid.origin.xyz()) / grid.cell_size.x).floor().as_ivec3();
+    cell.clamp(IVec3::ZERO, grid.dims.xyz().as_ivec3() - 1)
+}
+
+
+fn hash_grid_cell(grid: &SpatialGrid, cell: IVec3, num_sorted: u32) -> u32 {
+    let cell = cell.as_uvec3();
+    (cell.x + cell.y * grid.dims.x + cell.z * grid.dims.x * grid.dims.y) % num_sorted
+}
+
+
+/// Particles within the smoothing radius of `origin`, following the GPU cell offsets through the 27 cells
+/// around it like the density pass does
+fn walk_neighbors(
+    grid: &SpatialGrid,
+    particles: &[FluidParticle],
+    buffers: &IndexBuffers,
+    num_sorted: u32,
+    origin: Vec3,
+    radius: f32,
+) -> HashSet<usize> {
+    let dims = grid.dims.xyz().as_ivec3();
+    let cell = get_grid_cell(grid, origin);
+    let mut found = HashSet::default();
+    for offset in (-1..=1).flat_map(|x| (-1..=1).flat_map(move |y| (-1..=1).map(move |z| IVec3::new(x, y, z)))) {
+        let neighbour_cell = cell + offset;
+        if neighbour_cell.cmplt(IVec3::ZERO).any() || neighbour_cell.cmpge(dims).any() {
+            continue;
+        }
+        let hash_index = hash_grid_cell(grid, neighbour_cell, num_sorted);
+        let mut neighbour_it = buffers.cell_offsets[hash_index as usize] as usize;
+        while neighbour_it < num_sorted as usize {
+            let neighbour_index = buffers.particle_indicies[neighbour_it] as usize;
+            if buffers.particle_cell_indicies[neighbour_index] != hash_index {
+                break;
+            }
+            neighbour_it += 1;
+            if particles[neighbour_index].predicted_position.xyz().distance(origin) <= radius {
+                found.insert(neighbour_index);
+            }
+        }
+    }
+    found
+}
+
+
+/// Brute-forces the neighbours of a sample of the live particles over every sorted one, boundary included,
+/// and counts those the cell walk doesn't reach. Ghosts behind the mirror plane aren't compared.
+/// Particles that crossed a cell since the hash pass leave a small floor, the read-back is a step ahead.
+fn check_neighbor_search(
+    mut check: ResMut<NeighborSearchCheck>,
+    worker: Res<AppComputeWorker<FluidWorker>>,
+    readback: Res<IndexReadback>,
+    capacity: Res<FluidCapacity>,
+    fluid_props: Res<FluidStaticProps>,
+    container: Res<FluidContainer>,
+) {
+    let num_sorted = capacity.get_num_sorted();
+    if !check.enabled || !readback.is_staged() || !worker.ready() || num_sorted == 0 {
+        return;
+    }
+
+    let particles = worker.read_vec::<FluidParticle>("particles");
+    let buffers = IndexBuffers::read(&worker);
+    // Same grid the update system hands to the shaders
+    let grid = SpatialGrid::new(&container, fluid_props.smoothing_radius);
+    let radius = fluid_props.smoothing_radius;
+
+    let num_particles = capacity.num_particles as usize;
+    let num_samples = NEIGHBOR_SEARCH_CHECK_SAMPLES.min(num_particles);
+    let first_boundary = capacity.get_first_boundary() as usize;
+    let sorted: Vec<usize> = (0..num_particles)
+        .chain(first_boundary..first_boundary + capacity.num_boundary as usize)
+        .collect();
+
+    let mut expected = 0;
+    let mut missed = 0;
+    for sample in 0..num_samples {
+        let particle_index = sample * num_particles / num_samples;
+        let origin = particles[particle_index].predicted_position.xyz();
+
+        let reference = sorted.iter()
+            .copied()
+            .filter(|&it| particles[it].predicted_position.xyz().distance(origin) <= radius);
+        let found = walk_neighbors(&grid, &particles, &buffers, num_sorted, origin, radius);
+        for neighbour_index in reference {
+            expected += 1;
+            if !found.contains(&neighbour_index) {
+                missed += 1;
+            }
+        }
+    }
+
+    check.miss_rate = missed as f32 / expected.max(1) as f32;
+}
+
+
+fn toggle_grid_overlay(
+    mut overlay: ResMut<GridOverlay>,
+    mut readback: ResMut<IndexReadback>,
+    check: Res<NeighborSearchCheck>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+) {
+    if keyboard_input.just_pressed(GRID_OVERLAY_KEY) {
+        overlay.enabled = !overlay.enabled;
+        readback.enabled = check.enabled || overlay.enabled;
+    }
+}
+
+
+fn draw_grid_overlay(
+    mut overlay: ResMut<GridOverlay>,
+    mut gizmos: Gizmos,
+    worker: Res<AppComputeWorker<FluidWorker>>,
+    readback: Res<IndexReadback>,
+    capacity: Res<FluidCapacity>,
+    fluid_props: Res<FluidStaticProps>,
+    container: Res<FluidContainer>,
+) {
+    let num_sorted = capacity.get_num_sorted();
+    if !overlay.enabled || !readback.is_staged() || !worker.ready() || num_sorted == 0 {
+        return;
+    }
+
+    // Same grid the update system hands to the shaders
+    let grid = SpatialGrid::new(&container, fluid_props.smoothing_radius);
+    let cell_size = grid.cell_size.x;
+    let origin = grid.origin.xyz();
+    let dims = grid.dims.xyz().as_ivec3();
+
+    let particles = worker.read_vec::<FluidParticle>("particles");
+    let particle_indicies = worker.read_vec::<u32>("particle_indicies");
+    let particle_cell_indicies = worker.read_vec::<u32>("particle_cell_indicies");
+    let cell_offsets = worker.read_vec::<u32>("cell_offsets");
+
+    // Occupancy per hash slot as the GPU sees it, colliding cells add up
+    let mut hash_counts: HashMap<u32, u32> = HashMap::default();
+    for &hash_index in particle_cell_indicies.iter().filter(|&&it| it < num_sorted) {
+        *hash_counts.entry(hash_index).or_default() += 1;
+    }
+
+    // The read-back positions were integrated after hashing, so the cells only approximate the GPU's
+    let mut cells: HashMap<IVec3, u32> = HashMap::default();
+    let mut mismatched = 0;
+    for (particle, &hash_index) in particles.iter().zip(particle_cell_indicies.iter()).take(capacity.num_particles as usize) {
+        let cell = ((particle.position.xyz() - origin) / cell_size).floor().as_ivec3().clamp(IVec3::ZERO, dims - 1);
+        cells.insert(cell, (cell.x + cell.y * dims.x + cell.z * dims.x * dims.y) as u32 % num_sorted);
+
+        if hash_index >= num_sorted {
+            continue;  // Emitted after the last hash pass
+        }
+        let offset = cell_offsets[hash_index as usize];
+        if offset >= num_sorted || particle_cell_indicies[particle_indicies[offset as usize] as usize] != hash_index {
+            mismatched += 1;
+        }
+    }
+
+    let max_count = cells.values().map(|hash_index| hash_counts.get(hash_index).copied().unwrap_or(0)).max().unwrap_or(1).max(1);
+    for (cell, hash_index) in cells.iter() {
+        let count = hash_counts.get(hash_index).copied().unwrap_or(0);
+        let color = if count == 0 {
+            GRID_OVERLAY_MISMATCH_COLOR
+        } else {
+            // Same cold to hot hues as the particle gradient
+            let t = count as f32 / max_count as f32;
+            Color::hsl((1. - t) * 180. + 20., 1., 0.5)
+        };
+        let center = origin + (cell.as_vec3() + 0.5) * cell_size;
+        gizmos.cuboid(Transform::from_translation(center).with_scale(Vec3::splat(cell_size)), color);
+    }
+    let size = dims.as_vec3() * cell_size;
+    gizmos.cuboid(Transform::from_translation(origin + size / 2.).with_scale(size), GRID_OVERLAY_BOUNDS_COLOR);
+
+    overlay.cell_size = cell_size;
+    overlay.non_empty_cells = cells.len();
+    overlay.mismatched = mismatched;
+}
+
+
+fn update_velocity_gizmos(mut velocity_gizmos: ResMut<VelocityGizmos>, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if keyboard_input.just_pressed(VELOCITY_GIZMOS_KEY) {
+        velocity_gizmos.enabled = !velocity_gizmos.enabled;
+    }
+    if keyboard_input.just_pressed(VELOCITY_GIZMOS_SCALE_UP_KEY) {
+        velocity_gizmos.scale *= VELOCITY_GIZMOS_SCALE_STEP;
+    }
+    if keyboard_input.just_pressed(VELOCITY_GIZMOS_SCALE_DOWN_KEY) {
+        velocity_gizmos.scale /= VELOCITY_GIZMOS_SCALE_STEP;
+    }
+}
+
+
+fn draw_velocity_gizmos(
+    mut gizmos: Gizmos,
+    velocity_gizmos: Res<VelocityGizmos>,
+    worker: Res<AppComputeWorker<FluidWorker>>,
+    capacity: Res<FluidCapacity>,
+) {
+    if !velocity_gizmos.enabled || !worker.ready() {
+        return;
+    }
+
+    let particles = worker.read_vec::<FluidParticle>("particles");
+    let live_particles = particles.iter().take(capacity.num_particles as usize);
+    for particle in live_particles.step_by(velocity_gizmos.stride.max(1)) {
+        let position = particle.position.xyz();
+        gizmos.line(position, position + particle.velocity.xyz() * velocity_gizmos.scale, VELOCITY_GIZMOS_COLOR);
+    }
+}
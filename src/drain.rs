@@ -0,0 +1,201 @@
+use bevy::prelude::*;
+use bevy_app_compute::prelude::*;
+
+use crate::schedule::InGameSet;
+use crate::fluid_container::FluidContainer;
+use crate::emitter::emit_particles;
+use crate::fluid_compute::{FluidCapacity, FluidParticle, FluidWorker};
+
+const DRAIN_TOGGLE_KEY: KeyCode = KeyCode::KeyD;
+const DRAIN_HALF_SIZE: Vec2 = Vec2::new(0.75, 0.75);
+const DRAIN_HEIGHT: f32 = 0.5;
+const DRAIN_COLOR: Color = Color::rgb(0.3, 0.9, 0.4);
+
+
+/// Zone on the container floor that removes the particles entering it
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct Drain {
+    pub enabled: bool,
+    /// Footprint in the container frame, XZ relative to the container center
+    pub rect: Rect,
+    /// Reach above the floor
+    pub height: f32,
+}
+
+
+impl Default for Drain {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            rect: Rect::from_center_half_size(Vec2::ZERO, DRAIN_HALF_SIZE),
+            height: DRAIN_HEIGHT,
+        }
+    }
+}
+
+
+impl Drain {
+    pub fn contains(&self, container: &FluidContainer, position: Vec3) -> bool {
+        let local = container.rotation.inverse() * (position - container.position);
+        self.rect.contains(local.xz()) && local.y < self.height - container.size.y / 2.
+    }
+
+    fn get_transform(&self, container: &FluidContainer) -> Transform {
+        let center = self.rect.center();
+        let local = Vec3::new(center.x, (self.height - container.size.y) / 2., center.y);
+        Transform {
+            translation: container.position + container.rotation * local,
+            rotation: container.rotation,
+            scale: Vec3::new(self.rect.width(), self.height, self.rect.height()),
+        }
+    }
+}
+
+
+pub struct DrainPlugin;
+
+
+impl Plugin for DrainPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<Drain>()
+            .add_systems(Update, toggle_drain.in_set(InGameSet::UserInput))
+            .add_systems(Update, (
+                drain_particles.after(emit_particles),
+                draw_drain,
+            ).in_set(InGameSet::EntityUpdates));
+    }
+}
+
+
+fn toggle_drain(mut drain: ResMut<Drain>, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if keyboard_input.just_pressed(DRAIN_TOGGLE_KEY) {
+        drain.enabled = !drain.enabled;
+    }
+}
+
+
+/// Swaps the matching items past the end, returns how many are kept at the front
+fn remove_swapped<T>(items: &mut [T], remove: impl Fn(&T) -> bool) -> usize {
+    let mut len = items.len();
+    let mut it = 0;
+    while it < len {
+        if remove(&items[it]) {
+            len -= 1;
+            items.swap(it, len);
+        } else {
+            it += 1;
+        }
+    }
+    len
+}
+
+
+/// Drained particles are swapped with the last live one, so the free slots stay at the end for the emitter
+fn drain_particles(
+    mut worker: ResMut<AppComputeWorker<FluidWorker>>,
+    mut capacity: ResMut<FluidCapacity>,
+    drain: Res<Drain>,
+    container: Res<FluidContainer>,
+) {
+    // Another write to the buffer this frame would be lost, read-backs lag a step behind
+    if !drain.enabled || !worker.ready() || capacity.is_changed() {
+        return;
+    }
+
+    let mut particles = worker.read_vec::<FluidParticle>("particles");
+    let num_particles = remove_swapped(
+        &mut particles[..capacity.num_particles as usize],
+        |particle| drain.contains(&container, particle.position.xyz()),
+    );
+    if num_particles == capacity.num_particles as usize {
+        return;
+    }
+    worker.write_slice("particles", &particles[..capacity.num_particles as usize]);
+    capacity.num_particles = num_particles as u32;
+    capacity.rejected = false;
+}
+
+
+fn draw_drain(mut gizmos: Gizmos, drain: Res<Drain>, container: Res<FluidContainer>) {
+    if drain.enabled {
+        gizmos.cuboid(drain.get_transform(&container), DRAIN_COLOR);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_container() -> FluidContainer {
+        FluidContainer {
+            position: Vec3::new(10., 2., -3.),
+            size: Vec3::new(4., 2., 4.),
+            ..default()
+        }
+    }
+
+    #[test]
+    fn contains_the_floor_under_the_footprint() {
+        let drain = Drain::default();
+        let container = make_container();
+        // The floor sits at y = 1, the drain reaches up to 1.5
+        assert!(drain.contains(&container, Vec3::new(10., 1.1, -3.)));
+        assert!(drain.contains(&container, Vec3::new(10.5, 1.4, -3.5)));
+    }
+
+    #[test]
+    fn skips_the_rest_of_the_container() {
+        let drain = Drain::default();
+        let container = make_container();
+        assert!(!drain.contains(&container, Vec3::new(10., 2., -3.)));
+        assert!(!drain.contains(&container, Vec3::new(11., 1.1, -3.)));
+        assert!(!drain.contains(&container, Vec3::new(10., 1.1, -4.)));
+        // The footprint is relative to the container, not the origin
+        assert!(!drain.contains(&container, Vec3::new(0., -0.9, 0.)));
+    }
+
+    #[test]
+    fn edges_of_the_drain() {
+        let drain = Drain::default();
+        let container = make_container();
+        assert!(drain.contains(&container, Vec3::new(10. + DRAIN_HALF_SIZE.x, 1.1, -3.)));
+        assert!(drain.contains(&container, Vec3::new(10., 1.1, -3. - DRAIN_HALF_SIZE.y)));
+        assert!(!drain.contains(&container, Vec3::new(10., 1. + DRAIN_HEIGHT, -3.)));
+    }
+
+    #[test]
+    fn follows_the_container_rotation() {
+        let drain = Drain::default();
+        let container = FluidContainer {
+            rotation: Quat::from_rotation_z(std::f32::consts::PI),
+            ..make_container()
+        };
+        // Upside down the floor is on top
+        assert!(drain.contains(&container, Vec3::new(10., 2.9, -3.)));
+        assert!(!drain.contains(&container, Vec3::new(10., 1.1, -3.)));
+    }
+
+    #[test]
+    fn removed_items_are_swapped_past_the_end() {
+        let mut items = [0, 1, 2, 3, 4, 5, 6];
+        let len = remove_swapped(&mut items, |it| it % 3 == 0);
+        assert_eq!(len, 4);
+        let mut kept = items[..len].to_vec();
+        kept.sort();
+        assert_eq!(kept, [1, 2, 4, 5]);
+        let mut removed = items[len..].to_vec();
+        removed.sort();
+        assert_eq!(removed, [0, 3, 6]);
+    }
+
+    #[test]
+    fn removing_nothing_or_everything() {
+        let mut items = [1, 2, 3];
+        assert_eq!(remove_swapped(&mut items, |_| false), 3);
+        assert_eq!(items, [1, 2, 3]);
+        assert_eq!(remove_swapped(&mut items, |_| true), 0);
+        assert_eq!(remove_swapped(&mut [0; 0], |_: &i32| true), 0);
+    }
+}
@@ -0,0 +1,138 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use bevy_app_compute::prelude::*;
+
+use crate::schedule::InGameSet;
+use crate::camera::Observer;
+use crate::fluid_container::FluidContainer;
+use crate::particle_color::ParticlePalette;
+use crate::fluid_compute::{spawn_particle_entities, FluidCapacity, FluidParticle, FluidWorker, ParticleMesh};
+
+const EMITTER_KEY: KeyCode = KeyCode::KeyH;
+const EMITTER_RATE: f32 = 600.;  // Particles per second
+const EMITTER_SPREAD: f32 = 0.4;
+const EMITTER_MAX_BURST: f32 = 256.;  // Caps the backlog after a stall
+const GOLDEN_ANGLE: f32 = PI * 0.763932;  // pi * (3 - sqrt(5))
+
+
+/// Pours particles in at the cursor while the key is held
+#[derive(Resource, Debug)]
+pub struct FluidEmitter {
+    /// Particles per second, independent of the frame rate
+    pub rate: f32,
+    /// Radius of the disc the particles are spread over, facing the camera
+    pub spread: f32,
+    /// Fraction of a particle carried over to the next frame
+    pending: f32,
+    emitted: u32,
+}
+
+
+impl Default for FluidEmitter {
+    fn default() -> Self {
+        Self {
+            rate: EMITTER_RATE,
+            spread: EMITTER_SPREAD,
+            pending: 0.,
+            emitted: 0,
+        }
+    }
+}
+
+
+impl FluidEmitter {
+    /// Sunflower pattern, consecutive particles never land on top of each other
+    fn get_offset(&self, it: u32, right: Vec3, up: Vec3) -> Vec3 {
+        let it = self.emitted.wrapping_add(it);
+        let radius = self.spread * ((it % 64) as f32 / 64.).sqrt();
+        let angle = it as f32 * GOLDEN_ANGLE;
+        (right * angle.cos() + up * angle.sin()) * radius
+    }
+}
+
+
+pub struct EmitterPlugin;
+
+
+impl Plugin for EmitterPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<FluidEmitter>()
+            .add_systems(Update, emit_particles.in_set(InGameSet::EntityUpdates));
+    }
+}
+
+
+/// Cursor ray hit on the camera-facing plane through the container center, kept inside the walls
+pub fn get_emitter_origin(
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+    cursor: Vec2,
+    container: &FluidContainer,
+) -> Option<Vec3> {
+    let ray = camera.viewport_to_world(camera_transform, cursor)?;
+    let distance = ray.intersect_plane(container.position, Plane3d::new(camera_transform.back()))?;
+    let point = ray.get_point(distance);
+
+    let half_size = (container.size / 2. - container.wall_margin).max(Vec3::ZERO);
+    let local = (container.rotation.inverse() * (point - container.position)).clamp(-half_size, half_size);
+    let mut point = container.position + container.rotation * local;
+    if container.mirror_x {
+        point.x = point.x.min(container.position.x - container.wall_margin);
+    }
+    Some(point)
+}
+
+
+pub fn emit_particles(
+    mut commands: Commands,
+    mut emitter: ResMut<FluidEmitter>,
+    mut worker: ResMut<AppComputeWorker<FluidWorker>>,
+    mut capacity: ResMut<FluidCapacity>,
+    particle_mesh: Option<Res<ParticleMesh>>,
+    palette: Res<ParticlePalette>,
+    container: Res<FluidContainer>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<Observer>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+) {
+    if !keyboard_input.pressed(EMITTER_KEY) {
+        emitter.pending = 0.;
+        return;
+    }
+    emitter.pending = (emitter.pending + emitter.rate * time.delta_seconds()).min(EMITTER_MAX_BURST);
+    if emitter.pending < 1. || !worker.ready() {
+        return;
+    }
+    let Some(particle_mesh) = particle_mesh else { return };
+    let Ok((camera, camera_transform)) = camera_query.get_single() else { return };
+    let Ok(window) = window_query.get_single() else { return };
+    let Some(cursor) = window.cursor_position() else { return };
+    let Some(origin) = get_emitter_origin(camera, camera_transform, cursor, &container) else { return };
+
+    let count = (emitter.pending as u32).min(capacity.get_free());
+    if count == 0 {
+        capacity.rejected = true;
+        return;
+    }
+    emitter.pending -= count as f32;
+
+    let right = camera_transform.right();
+    let up = camera_transform.up();
+    let positions: Vec<Vec3> = (0..count)
+        .map(|it| origin + emitter.get_offset(it, right, up))
+        .collect();
+    emitter.emitted = emitter.emitted.wrapping_add(count);
+
+    let first_id = capacity.num_particles as usize;
+    let mut particles = worker.read_vec::<FluidParticle>("particles");
+    let emitted = FluidParticle::make_vec_from_positions(positions.clone());
+    particles[first_id..first_id + emitted.len()].copy_from_slice(&emitted);
+    worker.write_slice("particles", &particles[..first_id + emitted.len()]);
+    capacity.try_add(count);
+
+    spawn_particle_entities(&mut commands, &particle_mesh.0, &palette.solid, &container, &positions, first_id);
+}
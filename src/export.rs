@@ -0,0 +1,102 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+use bevy::tasks::AsyncComputeTaskPool;
+use bevy_app_compute::prelude::*;
+
+use crate::schedule::InGameSet;
+use crate::fluid_compute::{FluidCapacity, FluidWorker, ParticleState};
+
+const EXPORT_KEY: KeyCode = KeyCode::F9;
+const EXPORT_CSV_HEADER: &str = "x,y,z,vx,vy,vz,density";
+
+
+/// Set on the key press, cleared once a ready worker could be read
+#[derive(Resource, Default, Debug)]
+struct ExportRequest {
+    pending: bool,
+}
+
+
+/// One row per particle: position, velocity, then density
+pub fn particles_to_csv(particles: &[ParticleState]) -> String {
+    let mut csv = String::with_capacity((particles.len() + 1) * 64);
+    csv.push_str(EXPORT_CSV_HEADER);
+    csv.push('\n');
+    for particle in particles {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{}\n",
+            particle.position.x, particle.position.y, particle.position.z,
+            particle.velocity.x, particle.velocity.y, particle.velocity.z,
+            particle.density,
+        ));
+    }
+    csv
+}
+
+
+pub struct ExportPlugin;
+
+
+impl Plugin for ExportPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<ExportRequest>()
+            .add_systems(Update, (request_export, export_particles).chain().in_set(InGameSet::EntityUpdates));
+    }
+}
+
+
+fn request_export(mut request: ResMut<ExportRequest>, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if keyboard_input.just_pressed(EXPORT_KEY) {
+        request.pending = true;
+    }
+}
+
+
+/// Snapshots the buffer on the main thread, formatting and the file write happen on the task pool
+fn export_particles(
+    mut request: ResMut<ExportRequest>,
+    worker: Res<AppComputeWorker<FluidWorker>>,
+    capacity: Res<FluidCapacity>,
+) {
+    // Deferred until the worker is ready
+    if !request.pending || !worker.ready() {
+        return;
+    }
+    request.pending = false;
+
+    let particles = ParticleState::read_all(&worker, &capacity);
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |it| it.as_secs());
+    let path = format!("particles_{}.csv", timestamp);
+    AsyncComputeTaskPool::get().spawn(async move {
+        match std::fs::write(&path, particles_to_csv(&particles)) {
+            Ok(()) => println!("Exported {} particles to {}", particles.len(), path),
+            Err(err) => println!("Particle export failed: {}", err),
+        }
+    }).detach();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starts_with_the_header() {
+        assert_eq!(particles_to_csv(&[]), "x,y,z,vx,vy,vz,density\n");
+    }
+
+    #[test]
+    fn writes_a_row_per_particle() {
+        let particle = ParticleState {
+            position: Vec3::new(1.5, -2., 0.25),
+            velocity: Vec3::new(0., 3., -0.5),
+            density: 998.5,
+            pressure: 7.,
+        };
+        let csv = particles_to_csv(&[particle, ParticleState::default()]);
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows, ["x,y,z,vx,vy,vz,density", "1.5,-2,0.25,0,3,-0.5,998.5", "0,0,0,0,0,0,0"]);
+    }
+}
@@ -1,9 +1,52 @@
 use bevy::prelude::*;
 
-const STARTING_BG_COLOR: Color = Color::rgb(0.1, 0., 0.15);
+use crate::schedule::InGameSet;
 
 const STARTING_LIGHT_COLOR: Color = Color::rgb(1., 1., 1.);
 const STARTING_LIGHT_BRIGHTNESS: f32 = 1000.;
+const THEME_CYCLE_KEY: KeyCode = KeyCode::KeyY;
+
+const THEMES: [Theme; 2] = [
+    Theme {
+        name: "dark",
+        background: Color::rgb(0.1, 0., 0.15),
+        particle_base: Color::CYAN,
+        container_line: Color::WHITE,
+    },
+    Theme {
+        name: "light",
+        background: Color::rgb(0.92, 0.93, 0.95),
+        particle_base: Color::rgb(0.1, 0.35, 0.8),
+        container_line: Color::rgb(0.15, 0.15, 0.2),
+    },
+];
+
+
+/// Scene colors, switching it restyles the running scene in place
+#[derive(Resource, Clone, Copy, PartialEq, Debug)]
+pub struct Theme {
+    pub name: &'static str,
+    pub background: Color,
+    /// Color of the first fluid
+    pub particle_base: Color,
+    pub container_line: Color,
+}
+
+
+impl Default for Theme {
+    fn default() -> Self {
+        THEMES[0]
+    }
+}
+
+
+impl Theme {
+    /// Next built-in theme, a custom one goes back to the first
+    pub fn next(&self) -> Self {
+        let index = THEMES.iter().position(|theme| theme.name == self.name).map_or(0, |index| index + 1);
+        THEMES[index % THEMES.len()]
+    }
+}
 
 
 pub struct FieldPlugin;
@@ -11,11 +54,29 @@ pub struct FieldPlugin;
 
 impl Plugin for FieldPlugin {
     fn build(&self, app: &mut App) {
+        let theme = Theme::default();
         app
-            .insert_resource(ClearColor(STARTING_BG_COLOR))
+            .insert_resource(theme)
+            .insert_resource(ClearColor(theme.background))
             .insert_resource(AmbientLight {
                 color: STARTING_LIGHT_COLOR,
                 brightness: STARTING_LIGHT_BRIGHTNESS,
-            });
+            })
+            .add_systems(Update, cycle_theme.in_set(InGameSet::UserInput))
+            .add_systems(Update, apply_theme_background);
+    }
+}
+
+
+fn cycle_theme(mut theme: ResMut<Theme>, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if keyboard_input.just_pressed(THEME_CYCLE_KEY) {
+        *theme = theme.next();
+    }
+}
+
+
+fn apply_theme_background(mut clear_color: ResMut<ClearColor>, theme: Res<Theme>) {
+    if theme.is_changed() {
+        clear_color.0 = theme.background;
     }
 }
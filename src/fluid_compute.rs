@@ -1,30 +1,77 @@
 use std::f32::consts::PI;
 use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 use bevy::prelude::*;
+use bevy::ecs::schedule::ScheduleLabel;
+use bevy::utils::HashMap;
+use bevy::app::AppExit;
 use bevy::core::Pod;
+use bevy::render::render_resource::Maintain;
+use bevy::render::renderer::RenderDevice;
 use bevy_app_compute::prelude::*;
 use bytemuck::Zeroable;
+use serde::{Deserialize, Serialize};
 
-use crate::helpers::cube_fluid;
+use crate::helpers::{
+    boundary_particles, cube_fluid, dam_break, fluid_from_image, grid_fluid_2d, hex_pack_fluid, random_fluid,
+};
+use crate::compute_tuning::{ComputeTuning, ComputeTuningPlugin};
+use crate::gpu_sort::{add_bitonic_sort_passes, add_counting_sort_passes, get_bit_sorter_stages, get_sort_length};
 use crate::state::GameState;
 use crate::schedule::{InGameSet, ShaderPhysicsSet};
-use crate::fluid_container::FluidContainer;
-use crate::gravity::Gravity;
-
-const NI_SIZE: usize = 64;  // FIXME: only works with powers of 2 now
-const NJ_SIZE: usize = 32;
-const NK_SIZE: usize = 32;
-const WORKGROUP_SIZE: u32 = 1024;
-
-const PARTICLE_RADIUS: f32 = 0.1;
+use crate::fluid_container::{FluidContainer, WallProps};
+use crate::gravity::{Gravity, GravityMode};
+use crate::paddle::Paddle;
+use crate::obstacles::Obstacles;
+use crate::force_toggles::ForceToggles;
+use crate::thermal::HeatSource;
+use crate::shake::WindowShake;
+use crate::rigid_ball::RigidBall;
+use crate::world_cursor::WorldCursor;
+use crate::particle_color::ParticlePalette;
+
+const FLUID_CUBE_SIZE: UVec3 = UVec3::new(64, 32, 32);
+const FLUID_EMIT_HEADROOM: u32 = 8192;  // Free slots for the emitter
+
+pub const PARTICLE_RADIUS: f32 = 0.1;
 const PARTICLE_COLLISION_DAMPING: f32 = 0.95;
 const PARTICLE_SMOOTHING_RADIUS: f32 = 0.25;
 const PARTICLE_TARGET_DENSITY: f32 = 10.;
 const PARTICLE_PRESSURE_SCALAR: f32 = 22.;
 const PARTICLE_NEAR_PRESSURE_SCALAR: f32 = 2.;
 const PARTICLE_VISCOSITY_STRENGTH: f32 = 0.1;
-const PARTICLE_LOOKAHEAD_SCALAR: f32 = 1. / 60.;
+const PARTICLE_COHESION_STRENGTH: f32 = 0.;
+const PARTICLE_MAX_SPEED: f32 = 50.;
+const PARTICLE_XSPH_STRENGTH: f32 = 0.;
+const PARTICLE_THERMAL_DIFFUSION: f32 = 1.;
+const PARTICLE_BUOYANCY: f32 = 0.2;  // Per degree above the ambient temperature
+const PARTICLE_AMBIENT_TEMPERATURE: f32 = 0.;
+const PARTICLE_SIM_HZ: f32 = 60.;
+const PARTICLE_LOOKAHEAD_TIME: f32 = 1. / 50.;
+const PARTICLE_AUTO_CALIBRATE_DENSITY: bool = false;
+const PARTICLE_AUTO_SMOOTHING_RADIUS: bool = false;
+const PARTICLE_TARGET_NEIGHBOR_COUNT: f32 = 40.;
+const SPLASH_MOMENTUM_THRESHOLD: f32 = 200.;
+const SPLASH_FIXED_POINT_SCALE: f32 = 100.;  // Must match the shader
+const DENSITY_PADDING: f32 = 0.00001;  // Must match the shader
+const NON_FINITE_CHECK_FRAMES: u32 = 30;  // Reads back every particle
+const CFL_ENABLED: bool = true;
+const CFL_COURANT_NUMBER: f32 = 0.4;  // Fraction of the smoothing radius a particle may travel per substep
+const CFL_MAX_SUBSTEPS: u32 = 8;
+const CFL_TOGGLE_KEY: KeyCode = KeyCode::KeyK;
+const BOUNDARY_PARTICLES_ENABLED: bool = false;
+const BOUNDARY_PARTICLES_TOGGLE_KEY: KeyCode = KeyCode::KeyN;
+const RESEED_KEY: KeyCode = KeyCode::Delete;  // Backspace already resets the tuning
+const SPAWN_IMAGE_PATH: &str = "assets/spawn.png";
+const SPAWN_IMAGE_LAYERS: usize = 4;  // Depth of the extruded image, in particles
+const SETTLE_ENERGY_THRESHOLD: f32 = 0.01;  // Mean squared speed
+const SETTLE_WINDOW_STEPS: u32 = 120;
+const COMPUTE_BACKEND_TIMEOUT: f32 = 10.;  // Seconds without a ready worker before it is reported as failed
+pub const FLUID_TYPES_MAX: usize = 2;  // Size of the uniform array
+const PARTICLE_MESH_SUBDIVISIONS: usize = 0;
+const PARTICLE_MESH_MAX_SUBDIVISIONS: usize = 5;
 
 
 #[derive(ShaderType, Pod, Zeroable, Clone, Copy)]
@@ -35,12 +82,15 @@ pub struct SmoothingKernel {
     pub pow3: f32,
     pub pow3_der: f32,
     pub spikey_pow3: f32,
+    pub cohesion: f32,
 }
 
 
-#[derive(Resource, ShaderType, Pod, Zeroable, Clone, Copy)]
+#[derive(Resource, ShaderType, Pod, Zeroable, Clone, Copy, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(default)]
 #[repr(C)]
 pub struct FluidStaticProps {
+    /// Simulated time per step, one over the simulation rate. Also the `Time::<Fixed>` period pacing the dispatches.
     pub delta_time: f32,
     pub collision_damping: f32,
     pub smoothing_radius: f32,
@@ -48,6 +98,20 @@ pub struct FluidStaticProps {
     pub pressure_scalar: f32,
     pub near_pressure_scalar: f32,
     pub viscosity_strength: f32,
+    /// How far ahead positions are predicted for the density and pressure passes, independent of the step.
+    /// Longer horizons react to compression earlier and damp more.
+    pub lookahead_time: f32,
+    /// Surface tension, pulls neighbours together so the fluid forms rounded blobs
+    pub cohesion_strength: f32,
+    /// Speeds above are clamped in the integrate pass, zero disables the clamp
+    pub max_speed: f32,
+    /// Fraction of the way each velocity is pulled toward the neighbour average per step, a gentler viscosity
+    pub xsph_strength: f32,
+    /// Rate at which neighbours even out their temperatures
+    pub thermal_diffusion: f32,
+    /// Fraction of gravity that lifts a particle per degree above the ambient temperature
+    pub buoyancy: f32,
+    pub ambient_temperature: f32,
 }
 
 
@@ -59,7 +123,84 @@ impl FluidStaticProps {
             pow3: 15. / (PI * self.smoothing_radius.powi(6)),
             pow3_der: 45. / (PI * self.smoothing_radius.powi(6)),
             spikey_pow3: 315. / (64. * PI * self.smoothing_radius.powi(9)),
+            cohesion: 32. / (PI * self.smoothing_radius.powi(9)),
+        }
+    }
+
+    /// Density of each point as the density pass computes it, weighted by the mass of the neighbour's fluid.
+    /// Missing fluid ids are the first fluid, like `FluidParticlesInitial::make_particles`.
+    pub fn get_densities(&self, points: &[Vec3], fluid_ids: &[u32], fluid_types: &FluidTypes) -> Vec<f32> {
+        let kernel = self.get_smoothing_kernel();
+        let radius = self.smoothing_radius;
+        let mut densities = vec![DENSITY_PADDING; points.len()];
+        walk_neighbor_cells(points, radius, |it, neighbour_it, dst| {
+            let mass = fluid_types.get(fluid_ids.get(neighbour_it).copied().unwrap_or(0)).mass;
+            let v = radius - dst;
+            densities[it] += mass * v * v * kernel.pow2;
+        });
+        densities
+    }
+
+    /// Mean density of the given packing, as the density pass would compute it
+    pub fn get_average_density(&self, points: &[Vec3], fluid_ids: &[u32], fluid_types: &FluidTypes) -> f32 {
+        if points.is_empty() {
+            return 0.;
+        }
+        self.get_densities(points, fluid_ids, fluid_types).iter().sum::<f32>() / points.len() as f32
+    }
+
+    /// Pressure of a particle from its density, against the target density of its own fluid.
+    /// Mirrors `get_pressure` in the shader.
+    pub fn get_pressure(&self, density: f32, fluid_type: &FluidType, is_boundary: bool) -> f32 {
+        let target_density = self.target_density * fluid_type.target_density;
+        let pressure = self.pressure_scalar * (density - target_density);
+        if is_boundary {
+            // Walls only push, a thin boundary layer is always below the target density
+            return pressure.max(0.);
+        }
+        pressure
+    }
+
+    /// Pressure of each point of the given packing, as the density pass would compute it
+    pub fn get_pressures(&self, points: &[Vec3], fluid_ids: &[u32], fluid_types: &FluidTypes) -> Vec<f32> {
+        self.get_densities(points, fluid_ids, fluid_types)
+            .into_iter()
+            .enumerate()
+            .map(|(it, density)| {
+                let fluid_type = fluid_types.get(fluid_ids.get(it).copied().unwrap_or(0));
+                self.get_pressure(density, fluid_type, false)
+            })
+            .collect()
+    }
+
+    /// Neighbours within the smoothing radius of each point, itself excluded, as the density pass counts them
+    pub fn get_neighbor_counts(&self, points: &[Vec3]) -> Vec<u32> {
+        let mut counts = vec![0; points.len()];
+        walk_neighbor_cells(points, self.smoothing_radius, |it, _, _| counts[it] += 1);
+        // Every point finds itself
+        counts.iter().map(|count| count - 1).collect()
+    }
+
+    /// Weight of `smoothing_kernel_cohesion` in the shader at `dst`, positive pulls the neighbour in
+    pub fn get_cohesion(&self, dst: f32) -> f32 {
+        let kernel = self.get_smoothing_kernel();
+        let radius = self.smoothing_radius;
+        let v = (radius - dst).powi(3) * dst.powi(3);
+        if 2. * dst > radius {
+            return v * kernel.cohesion;
+        }
+        (2. * v - radius.powi(6) / 64.) * kernel.cohesion
+    }
+
+    /// Cohesion acceleration a neighbour at `neighbour` adds to the particle at `origin`, like the pressure pass.
+    /// Nothing at or beyond the smoothing radius, nor from a neighbour in the same spot.
+    pub fn get_cohesion_force(&self, origin: Vec3, neighbour: Vec3) -> Vec3 {
+        let offset = neighbour - origin;
+        let dst = offset.length();
+        if dst <= 0. || dst > self.smoothing_radius {
+            return Vec3::ZERO;
         }
+        offset / dst * self.get_cohesion(dst) * self.cohesion_strength
     }
 }
 
@@ -67,445 +208,1741 @@ impl FluidStaticProps {
 impl Default for FluidStaticProps {
     fn default() -> Self {
         Self {
-            delta_time: PARTICLE_LOOKAHEAD_SCALAR,
+            delta_time: 1. / PARTICLE_SIM_HZ,
             collision_damping: PARTICLE_COLLISION_DAMPING,
             smoothing_radius: PARTICLE_SMOOTHING_RADIUS,
             target_density: PARTICLE_TARGET_DENSITY,
             pressure_scalar: PARTICLE_PRESSURE_SCALAR,
             near_pressure_scalar: PARTICLE_NEAR_PRESSURE_SCALAR,
             viscosity_strength: PARTICLE_VISCOSITY_STRENGTH,
+            lookahead_time: PARTICLE_LOOKAHEAD_TIME,
+            cohesion_strength: PARTICLE_COHESION_STRENGTH,
+            max_speed: PARTICLE_MAX_SPEED,
+            xsph_strength: PARTICLE_XSPH_STRENGTH,
+            thermal_diffusion: PARTICLE_THERMAL_DIFFUSION,
+            buoyancy: PARTICLE_BUOYANCY,
+            ambient_temperature: PARTICLE_AMBIENT_TEMPERATURE,
         }
     }
 }
 
 
-#[derive(Resource, Clone, Default)]
-pub struct FluidParticlesInitial {
-    pub positions: Vec<Vec3>,
+/// Calls `f` with the point index, the neighbour index and their distance for every neighbour within `radius`
+/// of a point, itself included. Walks the 27 cells around each point like the shaders do.
+fn walk_neighbor_cells(points: &[Vec3], radius: f32, mut f: impl FnMut(usize, usize, f32)) {
+    let get_cell = |point: Vec3| (point / radius).floor().as_ivec3();
+
+    let mut cells: HashMap<IVec3, Vec<usize>> = HashMap::default();
+    for (it, &point) in points.iter().enumerate() {
+        cells.entry(get_cell(point)).or_default().push(it);
+    }
+
+    for (it, &point) in points.iter().enumerate() {
+        let cell = get_cell(point);
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let Some(neighbours) = cells.get(&(cell + IVec3::new(x, y, z))) else { continue };
+                    for &neighbour_it in neighbours {
+                        let dst = point.distance(points[neighbour_it]);
+                        if dst <= radius {
+                            f(it, neighbour_it, dst);
+                        }
+                    }
+                }
+            }
+        }
+    }
 }
 
 
+/// Spatial hash grid laid over the container, one cell per smoothing radius
 #[derive(ShaderType, Pod, Zeroable, Clone, Copy)]
 #[repr(C)]
-pub struct BitSorter {
-    pub block: u32,
-    pub dim: u32,
+pub struct SpatialGrid {
+    pub origin: Vec4,
+    pub dims: UVec4,
+    /// X is the cell size, rebuilt every frame from the live smoothing radius
+    pub cell_size: Vec4,
+    /// X is the mirror plane position, Y is 1 when mirroring is enabled
+    pub mirror: Vec4,
+    /// X is the first boundary particle slot, Y is their count
+    pub boundary: UVec4,
 }
 
 
-impl BitSorter {
-    fn new(block: u32, dim: u32) -> Self {
+impl SpatialGrid {
+    pub fn new(container: &FluidContainer, cell_size: f32) -> Self {
+        let ext = container.get_bounding_ext(container.wall_margin);
+        let size = (ext.ext_max - ext.ext_min).xyz().max(Vec3::ZERO);
+        let dims = (size / cell_size).ceil().as_uvec3().max(UVec3::ONE);
         Self {
-            block,
-            dim,
+            origin: ext.ext_min,
+            dims: dims.extend(0),
+            cell_size: Vec4::new(cell_size, 0., 0., 0.),
+            mirror: Vec4::new(container.position.x, if container.mirror_x { 1. } else { 0. }, 0., 0.),
+            boundary: UVec4::ZERO,
         }
     }
+
+    pub fn with_boundary(mut self, capacity: &FluidCapacity) -> Self {
+        self.boundary = UVec4::new(capacity.get_first_boundary(), capacity.num_boundary, 0, 0);
+        self
+    }
 }
 
 
-#[derive(ShaderType, Pod, Zeroable, Clone, Copy, Default)]
-#[repr(C)]
-pub struct FluidParticle {
-    pub position: Vec4,
-    pub density: Vec2,
-    pub pressure: Vec2,
-    pub velocity: Vec4,
-    pub acceleration: Vec4,
-    pub predicted_position: Vec4,
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct FluidCalibration {
+    /// Set the target density to the average density of the initial packing when the worker is built
+    pub auto_calibrate_density: bool,
 }
 
 
-impl FluidParticle {
-    pub fn make_vec_from_positions(points: Vec<Vec3>) -> Vec<Self> {
-        let mut particles = Vec::with_capacity(points.len());
-        for point in points {
-            particles.push(Self {
-                position: point.extend(0.),
-                predicted_position: point.extend(0.),
-                ..default()
-            });
+impl Default for FluidCalibration {
+    fn default() -> Self {
+        Self {
+            auto_calibrate_density: PARTICLE_AUTO_CALIBRATE_DENSITY,
         }
-        particles
     }
 }
 
 
-struct BitSorterStage {
-    bit_sorter: BitSorter,
-    workgroups: [u32; 3],
-    uniform_name: String,
+#[derive(Clone, Debug)]
+pub enum FluidShape {
+    /// Block of particles, the size is in particles per axis
+    Cube(UVec3),
+    /// Single layer lattice in the XY plane, the size is in particles per axis
+    Sheet(UVec2),
+    /// Like `Sheet` with hexagonal packing, denser so it settles with less of a pressure kick. X is columns, Y is rows.
+    HexSheet(UVec2),
+    /// Column against the lower X wall, sized by the container
+    DamBreak,
 }
 
 
-#[derive(TypePath)]
-struct IntegrateShader;
-
-
-impl ComputeShader for IntegrateShader {
-    fn shader() -> ShaderRef {
-        "simulation.wgsl".into()
-    }
-
-    fn entry_point<'a>() -> &'a str {
-        "integrate"
+impl FluidShape {
+    pub fn spawn(&self, container: &FluidContainer) -> Vec<Vec3> {
+        match *self {
+            FluidShape::Cube(size) => cube_fluid(size.x as usize, size.y as usize, size.z as usize, PARTICLE_RADIUS),
+            FluidShape::Sheet(size) => grid_fluid_2d(size.x as usize, size.y as usize, PARTICLE_RADIUS)
+                .into_iter()
+                .map(|point| point.extend(0.))
+                .collect(),
+            FluidShape::HexSheet(size) => hex_pack_fluid(size.y as usize, size.x as usize, PARTICLE_RADIUS)
+                .into_iter()
+                .map(|point| point.extend(0.))
+                .collect(),
+            FluidShape::DamBreak => dam_break(container, PARTICLE_RADIUS),
+        }
     }
 }
 
 
-#[derive(TypePath)]
-struct UpdateDensityShader;
+/// Scene picked in the menu, respawns the particles in place of the built spawn
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum FluidScenario {
+    #[default]
+    Default,
+    DamBreak,
+    /// The default block with the heavier fluid in its lower half
+    TwoFluids,
+    /// Particles scattered over the whole container from `FluidRandomSeed`
+    Random,
+    /// Bright pixels of `FluidSpawnImage`, a few layers deep
+    Image,
+}
 
 
-impl ComputeShader for UpdateDensityShader {
-    fn shader() -> ShaderRef {
-        "simulation.wgsl".into()
+impl FluidScenario {
+    pub fn next(self) -> Self {
+        match self {
+            FluidScenario::Default => FluidScenario::DamBreak,
+            FluidScenario::DamBreak => FluidScenario::TwoFluids,
+            FluidScenario::TwoFluids => FluidScenario::Random,
+            FluidScenario::Random => FluidScenario::Image,
+            FluidScenario::Image => FluidScenario::Default,
+        }
     }
 
-    fn entry_point<'a>() -> &'a str {
-        "update_density"
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            FluidScenario::Default => "Default",
+            FluidScenario::DamBreak => "Dam break",
+            FluidScenario::TwoFluids => "Two fluids",
+            FluidScenario::Random => "Random",
+            FluidScenario::Image => "Image",
+        }
     }
 }
 
 
-#[derive(TypePath)]
-struct UpdatePressureForceShader;
+/// Seed of the random scenario, set from the config or `--seed`
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct FluidRandomSeed(pub u64);
 
 
-impl ComputeShader for UpdatePressureForceShader {
-    fn shader() -> ShaderRef {
-        "simulation.wgsl".into()
-    }
+/// Grayscale image the image scenario is drawn from, set from the config
+#[derive(Resource, Clone, PartialEq, Eq, Debug)]
+pub struct FluidSpawnImage(pub PathBuf);
 
-    fn entry_point<'a>() -> &'a str {
-        "update_pressure_force"
+
+impl Default for FluidSpawnImage {
+    fn default() -> Self {
+        Self(PathBuf::from(SPAWN_IMAGE_PATH))
     }
 }
 
 
-#[derive(TypePath)]
-struct HashParticlesShader;
+/// Particle count picked in the settings screen, replaces the built block when set
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct FluidSpawnCount(pub Option<u32>);
 
 
-impl ComputeShader for HashParticlesShader {
-    fn shader() -> ShaderRef {
-        "simulation.wgsl".into()
-    }
-
-    fn entry_point<'a>() -> &'a str {
-        "hash_particles"
-    }
+#[derive(Resource, Clone, Debug)]
+pub struct FluidSpawnConfig {
+    pub shape: FluidShape,
+    /// Capacity of the GPU buffers, the spawn is cut down to it. Defaults to the spawn size plus `emit_headroom`.
+    pub max_particles: Option<u32>,
+    pub emit_headroom: u32,
 }
 
 
-#[derive(TypePath)]
-struct BitonicSortShader;
+impl FluidSpawnConfig {
+    pub fn spawn(&self, container: &FluidContainer) -> Vec<Vec3> {
+        self.shape.spawn(container)
+    }
+}
 
 
-impl ComputeShader for BitonicSortShader {
-    fn shader() -> ShaderRef {
-        "bitonic_sort.wgsl".into()
+impl Default for FluidSpawnConfig {
+    fn default() -> Self {
+        Self {
+            shape: FluidShape::Cube(FLUID_CUBE_SIZE),
+            max_particles: None,
+            emit_headroom: FLUID_EMIT_HEADROOM,
+        }
     }
+}
 
-    fn entry_point<'a>() -> &'a str {
-        "bitonic_sort"
-    }
+
+/// How the particles are grouped by cell for the neighbour search, fixed when the worker is built.
+/// Both fill the same `cell_offsets`, the passes after them don't know which one ran.
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum NeighborSearch {
+    /// Bitonic sort of the cell indices, log² n passes over the padded length
+    #[default]
+    Bitonic,
+    /// Atomic per-cell counts, a prefix sum and a scatter, four passes whatever the count.
+    /// The prefix sum runs in a single workgroup, so small counts are better off with `Bitonic`.
+    CountingSort,
 }
 
 
-#[derive(TypePath)]
-struct CalculateCellOffsetsShader;
+/// Keeps the smoothing radius matched to the average particle spacing in the container
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct SmoothingRadiusScaling {
+    pub enabled: bool,
+    pub target_neighbor_count: f32,
+}
 
 
-impl ComputeShader for CalculateCellOffsetsShader {
-    fn shader() -> ShaderRef {
-        "bitonic_sort.wgsl".into()
+impl SmoothingRadiusScaling {
+    pub fn get_smoothing_radius(&self, container: &FluidContainer, num_particles: usize) -> f32 {
+        let spacing = (container.size.x * container.size.y * container.size.z / num_particles.max(1) as f32).cbrt();
+        // The smoothing sphere has to hold the target count of particles at that spacing
+        spacing * (3. * self.target_neighbor_count / (4. * PI)).cbrt()
     }
+}
 
-    fn entry_point<'a>() -> &'a str {
-        "calculate_cell_offsets"
+
+impl Default for SmoothingRadiusScaling {
+    fn default() -> Self {
+        Self {
+            enabled: PARTICLE_AUTO_SMOOTHING_RADIUS,
+            target_neighbor_count: PARTICLE_TARGET_NEIGHBOR_COUNT,
+        }
     }
 }
 
 
-fn get_batch_size(data_length: u32) -> u32 {
-    let mut batch_size = data_length / WORKGROUP_SIZE;
-    if data_length % WORKGROUP_SIZE > 0 {
-        batch_size += 1;
-    }
-    return batch_size;
+/// Sent when the wall impact momentum of a single step exceeds `SplashSettings::momentum_threshold`
+#[derive(Event, Clone, Copy, Debug)]
+pub struct SplashEvent {
+    pub momentum: f32,
 }
 
 
-pub struct FluidWorker;
+/// Sent once when the fluid comes to rest, see `SettleDetector`
+#[derive(Event, Clone, Copy, Debug)]
+pub struct FluidSettled {
+    pub mean_kinetic_energy: f32,
+}
 
 
-impl FluidWorker {
-    pub fn create_initial_index_buffer(data_length: u32) -> Vec<u32> {
-        let mut initial_indicies = Vec::with_capacity(data_length as usize);
-        for it in 0..data_length {
-            initial_indicies.push(it);
-        }
-        return initial_indicies;
-    }
+/// Sent when the particles are put back to a fresh spawn, whatever was tracked per particle is stale
+#[derive(Event, Clone, Copy, Debug)]
+pub struct FluidResetEvent;
 
-    fn get_bit_sorter_stages(data_length: u32, batch_size: u32) -> Vec<BitSorterStage> {
-        let input_length = match data_length.checked_next_power_of_two() {
-            Some(pot) => pot,
-            None => data_length,
-        };
-        let mut uniform_id = 1;
-        let mut dim = 2;
-        let mut block_stages = Vec::new();
-        while dim <= input_length {
-            let mut block = dim >> 1;
-            while block > 0 {
-                block_stages.push(BitSorterStage {
-                    bit_sorter: BitSorter::new(block, dim),
-                    workgroups: [batch_size, 1, 1],
-                    uniform_name: format!("bit_sorter_{}", uniform_id),
-                });
-                block >>= 1;
-                uniform_id += 1;
-            }
-            dim <<= 1;
-        }
-        return block_stages;
-    }
-}
 
+/// Rebuilds the worker with buffers and sort passes sized for `num_particles`, outside the game only
+#[derive(Event, Clone, Copy, Debug)]
+pub struct RebuildWorkerEvent {
+    pub num_particles: u32,
+}
 
-impl ComputeWorker for FluidWorker {
-    fn build(world: &mut World) -> AppComputeWorker<Self> {
-        // Get static shader resources
-        let fluid_props = world.resource::<FluidStaticProps>().clone();
-        let gravity = world.resource::<Gravity>().clone();
-        let container = world.resource::<FluidContainer>().clone();
 
-        // Init positions
-        let points = cube_fluid(NI_SIZE, NJ_SIZE, NK_SIZE, PARTICLE_RADIUS);
-        let num_particles = points.len() as u32;
+/// Counts consecutive steps with the mean squared speed under the threshold.
+/// Fires once per settling, rearmed when the energy rises again or the fluid is reset.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct SettleDetector {
+    pub energy_threshold: f32,
+    /// Steps the energy has to stay low
+    pub window: u32,
+    pub streak: u32,
+    pub settled: bool,
+}
 
-        // Init positions
-        let mut fluid_initials = world.resource_mut::<FluidParticlesInitial>();
-        fluid_initials.positions = points.clone();
 
-        // Init buffers
-        let initial_index_buffer = Self::create_initial_index_buffer(num_particles);
-        let initial_particle_buffer = FluidParticle::make_vec_from_positions(points);
+impl Default for SettleDetector {
+    fn default() -> Self {
+        Self {
+            energy_threshold: SETTLE_ENERGY_THRESHOLD,
+            window: SETTLE_WINDOW_STEPS,
+            streak: 0,
+            settled: false,
+        }
+    }
+}
 
-        // Init worker
-        let batch_size = get_batch_size(num_particles);
-        let mut builder = AppComputeWorkerBuilder::new(world);
-        builder
-            .add_uniform("num_particles", &num_particles)
-            .add_uniform("fluid_props", &fluid_props)
-            .add_uniform("fluid_container", &container.get_ext(PARTICLE_RADIUS))
-            .add_uniform("gravity", &gravity)
-            .add_staging("particles", &initial_particle_buffer)
-            .add_uniform("smoothing_kernel", &fluid_props.get_smoothing_kernel())
-            .add_rw_storage("particle_indicies", &initial_index_buffer)
-            .add_rw_storage("particle_cell_indicies", &initial_index_buffer)
-            .add_rw_storage("cell_offsets", &initial_index_buffer)
-            .add_pass::<HashParticlesShader>([batch_size, 1, 1], &[
-                "num_particles",
-                "fluid_props",
-                "particles",
-                "particle_indicies",
-                "particle_cell_indicies",
-                "cell_offsets",
-            ]);
 
-        // Bitonic sort passes
-        // Init bit sorter stages
-        let bit_sorter_stages = Self::get_bit_sorter_stages(num_particles, batch_size);
-        println!("Bit sort passes: {}", bit_sorter_stages.len());
-        for stage in bit_sorter_stages {
-            builder.add_uniform(&stage.uniform_name, &stage.bit_sorter)
-                .add_pass::<BitonicSortShader>(stage.workgroups, &[
-                    "num_particles",
-                    "particle_indicies",
-                    "particle_cell_indicies",
-                    &stage.uniform_name,
-                ]);
+impl SettleDetector {
+    /// True on the step the streak reaches the window
+    pub fn observe(&mut self, mean_kinetic_energy: f32) -> bool {
+        if !mean_kinetic_energy.is_finite() || mean_kinetic_energy >= self.energy_threshold {
+            self.reset();
+            return false;
+        }
+        self.streak = self.streak.saturating_add(1);
+        if self.settled || self.streak < self.window {
+            return false;
         }
+        self.settled = true;
+        true
+    }
 
-        builder
-            .add_pass::<CalculateCellOffsetsShader>([batch_size, 1, 1], &[
-                "num_particles",
-                "particle_indicies",
-                "particle_cell_indicies",
-                "cell_offsets",
-            ])
-            .add_pass::<UpdateDensityShader>([batch_size, 1, 1], &[
-                "num_particles",
-                "fluid_props",
-                "particles",
-                "particle_indicies",
-                "particle_cell_indicies",
-                "cell_offsets",
-                "smoothing_kernel",
-            ])
-            .add_pass::<UpdatePressureForceShader>([batch_size, 1, 1], &[
-                "num_particles",
-                "fluid_props",
-                "particles",
-                "particle_indicies",
-                "particle_cell_indicies",
-                "cell_offsets",
-                "smoothing_kernel",
-            ])
-            .add_pass::<IntegrateShader>([batch_size, 1, 1], &[
-                "num_particles",
-                "fluid_props",
-                "particles",
-                "fluid_container",
-                "gravity",
-            ])
-            .build()
+    pub fn reset(&mut self) {
+        self.streak = 0;
+        self.settled = false;
     }
 }
 
 
-pub struct FluidComputeWorkerPlugin<W: ComputeWorker> {
-    _phantom: PhantomData<W>,
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct SplashSettings {
+    pub momentum_threshold: f32,
 }
 
 
-impl<W: ComputeWorker> Default for FluidComputeWorkerPlugin<W> {
+impl Default for SplashSettings {
     fn default() -> Self {
         Self {
-            _phantom: Default::default(),
+            momentum_threshold: SPLASH_MOMENTUM_THRESHOLD,
         }
     }
 }
 
 
-impl<W: ComputeWorker> Plugin for FluidComputeWorkerPlugin<W> {
-    fn build(&self, app: &mut App) {
-        app.insert_resource(Time::<Fixed>::from_seconds(PARTICLE_LOOKAHEAD_SCALAR.into()));
+/// Live particle count against the capacity the GPU buffers were sized for
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct FluidCapacity {
+    pub max_particles: u32,
+    pub num_particles: u32,
+    /// Wall particles kept at the end of the buffers, they take part in the sort but never move
+    pub num_boundary: u32,
+    /// Set once an addition was refused for lack of room
+    pub rejected: bool,
+}
+
+
+impl FluidCapacity {
+    /// Reserves room for `count` more particles, refuses anything past the buffer capacity
+    pub fn try_add(&mut self, count: u32) -> bool {
+        if count > self.get_free() {
+            self.rejected = true;
+            return false;
+        }
+        self.num_particles += count;
+        true
     }
 
-    fn finish(&self, app: &mut App) {
-        let worker = W::build(&mut app.world);
+    pub fn get_free(&self) -> u32 {
+        self.max_particles - self.num_boundary - self.num_particles
+    }
 
-        app
-            .insert_resource(worker)
-            .add_systems(Update, AppComputeWorker::<W>::extract_pipelines)
-            .add_systems(PostUpdate, (
-                AppComputeWorker::<W>::unmap_all.in_set(ShaderPhysicsSet::Prepare),
-                AppComputeWorker::<W>::run.in_set(ShaderPhysicsSet::Pass)
-            ));
+    pub fn get_first_boundary(&self) -> u32 {
+        self.max_particles - self.num_boundary
+    }
+
+    /// Live and boundary particles, the sorted keys past them are padding
+    pub fn get_num_sorted(&self) -> u32 {
+        self.num_particles + self.num_boundary
+    }
+
+    /// Replaces the live count, drops the boundary layer when they overlap and returns whether it did
+    pub fn restore(&mut self, num_particles: u32) -> bool {
+        self.num_particles = num_particles;
+        if num_particles > self.get_first_boundary() {
+            self.num_boundary = 0;
+            return true;
+        }
+        false
     }
 }
 
 
-pub struct FluidComputePlugin;
+/// Fixed particles along the walls push the fluid away through the pressure pass, on top of the hard walls
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct BoundaryParticles {
+    pub enabled: bool,
+}
 
 
-impl Plugin for FluidComputePlugin {
-    fn build(&self, app: &mut App) {
-        app
-            .init_resource::<FluidStaticProps>()
-            .init_resource::<FluidParticlesInitial>()
-            .add_plugins(AppComputePlugin)
-            .add_plugins(FluidComputeWorkerPlugin::<FluidWorker>::default());
+impl Default for BoundaryParticles {
+    fn default() -> Self {
+        Self {
+            enabled: BOUNDARY_PARTICLES_ENABLED,
+        }
     }
 }
 
 
-#[derive(Component, Debug)]
-struct FluidParticleLabel(usize);
+/// Stages the neighbour search index buffers so debug checks can read them back on the CPU.
+/// Off by default, the worker is rebuilt with or without the staging when it changes.
+#[derive(Resource, Default, Debug)]
+pub struct IndexReadback {
+    pub enabled: bool,
+    /// Whether the current worker was built with the staging
+    staged: bool,
+}
 
 
-#[derive(Component, Default, Debug)]
-struct Velocity(Vec3);
+impl IndexReadback {
+    pub fn is_staged(&self) -> bool {
+        self.staged
+    }
+}
 
 
-pub struct FluidPlugin;
+/// Particles carried over a rebuild, written once the new worker is ready
+#[derive(Resource, Debug)]
+struct PendingParticles(Vec<FluidParticle>);
 
 
-impl Plugin for FluidPlugin {
-    fn build(&self, app: &mut App) {
-        app
-            .add_plugins(FluidComputePlugin)
-            .add_systems(OnExit(GameState::Menu), setup)
-            .add_systems(Update, update.in_set(InGameSet::EntityUpdates))
-            .add_systems(Update, despawn_liquid.in_set(InGameSet::DespawnEntities));
+#[derive(Resource, Clone, Default)]
+pub struct FluidParticlesInitial {
+    pub positions: Vec<Vec3>,
+    /// Index into `FluidTypes` per position, empty when everything is the first fluid
+    pub fluid_ids: Vec<u32>,
+}
+
+
+impl FluidParticlesInitial {
+    pub fn make_particles(&self) -> Vec<FluidParticle> {
+        let mut particles = FluidParticle::make_vec_from_positions(self.positions.clone());
+        for (particle, &fluid_id) in particles.iter_mut().zip(self.fluid_ids.iter()) {
+            particle.set_fluid_id(fluid_id);
+        }
+        particles
     }
 }
 
 
+/// Per-fluid material, particles of different fluids still push each other
+#[derive(Clone, Copy, Debug)]
+pub struct FluidType {
+    pub mass: f32,
+    /// Relative to `FluidStaticProps::target_density`, so tuning that moves every fluid
+    pub target_density: f32,
+    pub color: Color,
+}
+
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct FluidTypes {
+    pub items: [FluidType; FLUID_TYPES_MAX],
+}
+
+
+impl Default for FluidTypes {
+    fn default() -> Self {
+        Self {
+            items: [
+                FluidType {
+                    mass: 1.,
+                    target_density: 1.,
+                    color: Color::CYAN,
+                },
+                // Packs at the same spacing as the first one, but sinks below it
+                FluidType {
+                    mass: 2.,
+                    target_density: 2.,
+                    color: Color::rgb(0.95, 0.6, 0.2),
+                },
+            ],
+        }
+    }
+}
+
+
+impl FluidTypes {
+    /// Clamped into the array like `get_fluid_type` in the shader
+    pub fn get(&self, fluid_id: u32) -> &FluidType {
+        &self.items[(fluid_id as usize).min(FLUID_TYPES_MAX - 1)]
+    }
+
+    /// Mass in x, target density scale in y
+    pub fn get_ext(&self) -> FluidTypesExt {
+        FluidTypesExt {
+            items: self.items.map(|it| Vec4::new(it.mass, it.target_density, 0., 0.)),
+        }
+    }
+}
+
+
+#[derive(ShaderType, Pod, Zeroable, Clone, Copy)]
+#[repr(C)]
+pub struct FluidTypesExt {
+    pub items: [Vec4; FLUID_TYPES_MAX],
+}
+
+
+#[derive(ShaderType, Pod, Zeroable, Clone, Copy, Default)]
+#[repr(C)]
+pub struct FluidParticle {
+    pub position: Vec4,
+    pub density: Vec2,
+    pub pressure: Vec2,
+    pub velocity: Vec4,
+    pub acceleration: Vec4,
+    pub predicted_position: Vec4,
+    /// X is the temperature, Y the diffused value the pressure pass leaves for integrate to swap in
+    pub temperature: Vec4,
+    /// X is the neighbour count within the smoothing radius, filled by the density pass
+    pub neighbors: Vec4,
+}
+
+
+impl FluidParticle {
+    pub fn make_vec_from_positions(points: Vec<Vec3>) -> Vec<Self> {
+        let mut particles = Vec::with_capacity(points.len());
+        for point in points {
+            particles.push(Self {
+                position: point.extend(0.),
+                predicted_position: point.extend(0.),
+                ..default()
+            });
+        }
+        particles
+    }
+
+    /// Kept in the position w, which the passes carry along untouched
+    pub fn get_fluid_id(&self) -> u32 {
+        (self.position.w as u32).min(FLUID_TYPES_MAX as u32 - 1)
+    }
+
+    pub fn set_fluid_id(&mut self, fluid_id: u32) {
+        self.position.w = fluid_id as f32;
+        self.predicted_position.w = fluid_id as f32;
+    }
+}
+
+
+/// Layout independent snapshot of a particle, for code reading the simulation output
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct ParticleState {
+    pub position: Vec3,
+    pub velocity: Vec3,
+    pub density: f32,
+    pub pressure: f32,
+}
+
+
+impl From<&FluidParticle> for ParticleState {
+    fn from(particle: &FluidParticle) -> Self {
+        Self {
+            position: particle.position.xyz(),
+            velocity: particle.velocity.xyz(),
+            density: particle.density.x,
+            pressure: particle.pressure.x,
+        }
+    }
+}
+
+
+impl ParticleState {
+    /// Current state of every live particle, in spawn order. Only meaningful when the worker is ready.
+    pub fn read_all(worker: &AppComputeWorker<FluidWorker>, capacity: &FluidCapacity) -> Vec<Self> {
+        let particles = worker.read_vec::<FluidParticle>("particles");
+        particles[..capacity.num_particles as usize].iter().map(Self::from).collect()
+    }
+}
+
+
+/// Aggregates over all particles, refreshed every frame
+#[derive(Resource, Clone, Copy, Default, Debug)]
+pub struct FluidStats {
+    pub center_of_mass: Vec3,
+    pub bounds_min: Vec3,
+    pub bounds_max: Vec3,
+    pub max_speed: f32,
+    /// Mean squared speed, kinetic energy per unit mass up to the factor of a half
+    pub mean_kinetic_energy: f32,
+}
+
+
+impl FluidStats {
+    pub fn from_particles(particles: &[FluidParticle]) -> Self {
+        if particles.is_empty() {
+            return Self::default();
+        }
+
+        let mut position_sum = Vec3::ZERO;
+        let mut bounds_min = Vec3::splat(f32::MAX);
+        let mut bounds_max = Vec3::splat(f32::MIN);
+        let mut max_speed = 0_f32;
+        let mut speed_squared_sum = 0.;
+        for particle in particles {
+            let position = particle.position.xyz();
+            position_sum += position;
+            bounds_min = bounds_min.min(position);
+            bounds_max = bounds_max.max(position);
+            let speed_squared = particle.velocity.xyz().length_squared();
+            max_speed = max_speed.max(speed_squared.sqrt());
+            speed_squared_sum += speed_squared;
+        }
+
+        Self {
+            center_of_mass: position_sum / particles.len() as f32,
+            bounds_min,
+            bounds_max,
+            max_speed,
+            mean_kinetic_energy: speed_squared_sum / particles.len() as f32,
+        }
+    }
+}
+
+
+/// Splits the integration of a step so the fastest particle moves a bounded distance per substep.
+/// Only the integrate pass is substepped, the density and pressure passes run once per step,
+/// so every substep applies the same pressure and viscosity acceleration to the moving particles.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct CflSettings {
+    pub enabled: bool,
+    pub courant_number: f32,
+    pub max_substeps: u32,
+}
+
+
+impl Default for CflSettings {
+    fn default() -> Self {
+        Self {
+            enabled: CFL_ENABLED,
+            courant_number: CFL_COURANT_NUMBER,
+            max_substeps: CFL_MAX_SUBSTEPS,
+        }
+    }
+}
+
+
+impl CflSettings {
+    /// Substeps that keep `max_speed` within the Courant number of the smoothing radius, 1 when disabled
+    pub fn get_substeps(&self, max_speed: f32, fluid_props: &FluidStaticProps) -> u32 {
+        if !self.enabled || !max_speed.is_finite() {
+            return 1;
+        }
+        let max_distance = self.courant_number * fluid_props.smoothing_radius;
+        let substeps = (max_speed * fluid_props.delta_time / max_distance).ceil() as u32;
+        substeps.clamp(1, self.max_substeps.max(1))
+    }
+}
+
+
+#[derive(Clone, Debug)]
+pub struct FluidPass {
+    pub name: String,
+    pub workgroups: [u32; 3],
+}
+
+
+/// Ordered list of the compute passes the worker dispatches every step
+#[derive(Resource, Clone, Default, Debug)]
+pub struct FluidPassSchedule {
+    pub passes: Vec<FluidPass>,
+}
+
+
+impl FluidPassSchedule {
+    fn push(&mut self, name: impl Into<String>, workgroups: [u32; 3]) {
+        self.passes.push(FluidPass {
+            name: name.into(),
+            workgroups,
+        });
+    }
+}
+
+
+#[derive(TypePath)]
+struct IntegrateShader;
+
+
+impl ComputeShader for IntegrateShader {
+    fn shader() -> ShaderRef {
+        "simulation.wgsl".into()
+    }
+
+    fn entry_point<'a>() -> &'a str {
+        "integrate"
+    }
+}
+
+
+#[derive(TypePath)]
+struct UpdateDensityShader;
+
+
+impl ComputeShader for UpdateDensityShader {
+    fn shader() -> ShaderRef {
+        "simulation.wgsl".into()
+    }
+
+    fn entry_point<'a>() -> &'a str {
+        "update_density"
+    }
+}
+
+
+#[derive(TypePath)]
+struct UpdatePressureForceShader;
+
+
+impl ComputeShader for UpdatePressureForceShader {
+    fn shader() -> ShaderRef {
+        "simulation.wgsl".into()
+    }
+
+    fn entry_point<'a>() -> &'a str {
+        "update_pressure_force"
+    }
+}
+
+
+#[derive(TypePath)]
+struct HashParticlesShader;
+
+
+impl ComputeShader for HashParticlesShader {
+    fn shader() -> ShaderRef {
+        "simulation.wgsl".into()
+    }
+
+    fn entry_point<'a>() -> &'a str {
+        "hash_particles"
+    }
+}
+
+
+#[derive(TypePath)]
+struct CalculateCellOffsetsShader;
+
+
+impl ComputeShader for CalculateCellOffsetsShader {
+    fn shader() -> ShaderRef {
+        "bitonic_sort.wgsl".into()
+    }
+
+    fn entry_point<'a>() -> &'a str {
+        "calculate_cell_offsets"
+    }
+}
+
+
+pub struct FluidWorker;
+
+
+impl FluidWorker {
+    pub fn create_initial_index_buffer(data_length: u32) -> Vec<u32> {
+        let mut initial_indicies = Vec::with_capacity(data_length as usize);
+        for it in 0..data_length {
+            initial_indicies.push(it);
+        }
+        return initial_indicies;
+    }
+}
+
+
+impl ComputeWorker for FluidWorker {
+    fn build(world: &mut World) -> AppComputeWorker<Self> {
+        // Init positions
+        let mut points = world.resource::<FluidSpawnConfig>().spawn(world.resource::<FluidContainer>());
+        world.resource::<FluidContainer>().retain_simulated(&mut points);
+        let spawn_config = world.resource::<FluidSpawnConfig>();
+        // Room for the boundary layer of the initial container, whether it starts enabled or not
+        let num_boundary = boundary_particles(world.resource::<FluidContainer>(), PARTICLE_RADIUS).len() as u32;
+        let max_particles = spawn_config.max_particles
+            .unwrap_or(points.len() as u32 + spawn_config.emit_headroom + num_boundary);
+        if points.len() > max_particles as usize {
+            println!("Spawn of {} particles exceeds the capacity of {}, truncating", points.len(), max_particles);
+            points.truncate(max_particles as usize);
+        }
+        let num_particles = points.len() as u32;
+        world.insert_resource(FluidCapacity {
+            max_particles,
+            num_particles,
+            num_boundary: 0,
+            rejected: false,
+        });
+
+        // Start the fluid near equilibrium
+        if world.resource::<FluidCalibration>().auto_calibrate_density {
+            // The fluid ids come with the scenario, everything is the first fluid until then
+            let fluid_types = *world.resource::<FluidTypes>();
+            let mut fluid_props = world.resource_mut::<FluidStaticProps>();
+            let target_density = fluid_props.get_average_density(&points, &[], &fluid_types);
+            fluid_props.target_density = target_density;
+            let neighbor_counts = fluid_props.get_neighbor_counts(&points);
+            let avg_count = neighbor_counts.iter().sum::<u32>() as f32 / neighbor_counts.len().max(1) as f32;
+            // The interior sits above the average, too much of it blows the block apart on the first step
+            let peak_pressure = fluid_props.get_pressures(&points, &[], &fluid_types).into_iter().fold(0., f32::max);
+            println!(
+                "Calibrated target density: {:.3}, {:.1} neighbours on average, {:.2} peak pressure",
+                target_density, avg_count, peak_pressure,
+            );
+        }
+
+        // Get static shader resources
+        let fluid_props = world.resource::<FluidStaticProps>().clone();
+        let gravity = world.resource::<GravityMode>().apply(world.resource::<Gravity>());
+        let container = world.resource::<FluidContainer>().clone();
+        let walls = *world.resource::<WallProps>();
+        let paddle = world.resource::<Paddle>().clone();
+        let obstacles = world.resource::<Obstacles>().get_ext();
+        let fluid_types = world.resource::<FluidTypes>().get_ext();
+        let heat_source = world.resource::<HeatSource>().get_ext();
+        let rigid_ball = world.resource::<RigidBall>().get_ext();
+        let world_cursor = world.resource::<WorldCursor>().get_ext();
+
+        // Init positions
+        let mut fluid_initials = world.resource_mut::<FluidParticlesInitial>();
+        fluid_initials.positions = points.clone();
+
+        // Init buffers, sized for the full capacity. The sort keys are padded to a power of two.
+        let sort_length = get_sort_length(max_particles);
+        let initial_index_buffer = Self::create_initial_index_buffer(sort_length);
+        let initial_cell_offsets = Self::create_initial_index_buffer(max_particles);
+        let mut initial_particle_buffer = FluidParticle::make_vec_from_positions(points);
+        initial_particle_buffer.resize(max_particles as usize, FluidParticle::default());
+
+        // Init worker
+        let tuning = *world.resource::<ComputeTuning>();
+        let neighbor_search = *world.resource::<NeighborSearch>();
+        let index_readback = world.get_resource_mut::<IndexReadback>().map_or(false, |mut readback| {
+            readback.staged = readback.enabled;
+            readback.staged
+        });
+        tuning.validate(world.resource::<RenderDevice>().limits().max_compute_invocations_per_workgroup);
+        // The hash and the sort walk the padded keys, the other passes stop at the capacity
+        let sort_batch_size = tuning.get_batch_size(sort_length);
+        let batch_size = tuning.get_batch_size(max_particles);
+        let mut schedule = FluidPassSchedule::default();
+        schedule.push("hash_particles", [sort_batch_size, 1, 1]);
+        let mut builder = AppComputeWorkerBuilder::new(world);
+        builder
+            .add_uniform("num_particles", &num_particles)
+            .add_uniform("num_sorted", &num_particles)
+            .add_uniform("sort_length", &sort_length)
+            .add_uniform("fluid_props", &fluid_props)
+            .add_uniform("fluid_container", &container.get_simulation_ext(fluid_props.collision_damping, &walls))
+            .add_uniform("gravity", &gravity)
+            .add_uniform("paddle", &paddle.get_ext())
+            .add_uniform("obstacles", &obstacles)
+            .add_uniform("substeps", &1u32)
+            .add_uniform("fluid_types", &fluid_types)
+            .add_uniform("heat_source", &heat_source)
+            .add_uniform("rigid_ball", &rigid_ball)
+            .add_uniform("world_cursor", &world_cursor)
+            .add_staging("wall_impact", &0u32)
+            .add_staging("ball_impulse", &IVec4::ZERO)
+            .add_staging("particles", &initial_particle_buffer)
+            .add_uniform("smoothing_kernel", &fluid_props.get_smoothing_kernel())
+            .add_uniform("spatial_grid", &SpatialGrid::new(&container, fluid_props.smoothing_radius));
+        // Read back every frame once staged, so only while a debug check needs them
+        if index_readback {
+            builder
+                .add_staging("particle_indicies", &initial_index_buffer)
+                .add_staging("particle_cell_indicies", &initial_index_buffer)
+                .add_staging("cell_offsets", &initial_cell_offsets);
+        } else {
+            builder
+                .add_rw_storage("particle_indicies", &initial_index_buffer)
+                .add_rw_storage("particle_cell_indicies", &initial_index_buffer)
+                .add_rw_storage("cell_offsets", &initial_cell_offsets);
+        }
+        builder
+            .add_pass::<HashParticlesShader>([sort_batch_size, 1, 1], &[
+                "num_particles",
+                "fluid_props",
+                "particles",
+                "particle_indicies",
+                "particle_cell_indicies",
+                "cell_offsets",
+                "spatial_grid",
+            ]);
+
+        match neighbor_search {
+            NeighborSearch::Bitonic => {
+                // Init bit sorter stages
+                let bit_sorter_stages = get_bit_sorter_stages(max_particles, sort_batch_size, "bit_sorter");
+                println!("Bit sort passes: {}", bit_sorter_stages.len());
+                for stage in bit_sorter_stages.iter() {
+                    schedule.push(
+                        format!("bitonic_sort (block: {}, dim: {})", stage.bit_sorter.block, stage.bit_sorter.dim),
+                        stage.workgroups,
+                    );
+                }
+                add_bitonic_sort_passes(
+                    &mut builder,
+                    &bit_sorter_stages,
+                    "sort_length",
+                    "particle_indicies",
+                    "particle_cell_indicies",
+                );
+            },
+            NeighborSearch::CountingSort => {
+                // Cell hashes stay below the live and boundary count, so below the capacity
+                let passes = add_counting_sort_passes(
+                    &mut builder,
+                    &tuning,
+                    sort_length,
+                    max_particles,
+                    "particle_indicies",
+                    "particle_cell_indicies",
+                );
+                for (name, workgroups) in passes {
+                    schedule.push(name, workgroups);
+                }
+            },
+        }
+
+        for name in ["calculate_cell_offsets", "update_density", "update_pressure_force", "integrate"] {
+            schedule.push(name, [batch_size, 1, 1]);
+        }
+
+        let worker = builder
+            .add_pass::<CalculateCellOffsetsShader>([batch_size, 1, 1], &[
+                "num_sorted",
+                "particle_indicies",
+                "particle_cell_indicies",
+                "cell_offsets",
+            ])
+            .add_pass::<UpdateDensityShader>([batch_size, 1, 1], &[
+                "num_particles",
+                "fluid_props",
+                "particles",
+                "particle_indicies",
+                "particle_cell_indicies",
+                "cell_offsets",
+                "spatial_grid",
+                "smoothing_kernel",
+                "fluid_types",
+            ])
+            .add_pass::<UpdatePressureForceShader>([batch_size, 1, 1], &[
+                "num_particles",
+                "fluid_props",
+                "particles",
+                "particle_indicies",
+                "particle_cell_indicies",
+                "cell_offsets",
+                "spatial_grid",
+                "smoothing_kernel",
+                "fluid_types",
+            ])
+            .add_pass::<IntegrateShader>([batch_size, 1, 1], &[
+                "num_particles",
+                "fluid_props",
+                "particles",
+                "fluid_container",
+                "gravity",
+                "paddle",
+                "wall_impact",
+                "obstacles",
+                "substeps",
+                "heat_source",
+                "rigid_ball",
+                "ball_impulse",
+                "world_cursor",
+            ])
+            .build();
+
+        world.insert_resource(schedule);
+        worker
+    }
+}
+
+
+/// Whether the compute worker ever became ready, a shader or backend failure otherwise leaves the fluid frozen
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum ComputeBackendStatus {
+    #[default]
+    Initializing,
+    Ready,
+    /// Not ready within the timeout
+    Failed,
+}
+
+
+impl ComputeBackendStatus {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            ComputeBackendStatus::Initializing => "initializing",
+            ComputeBackendStatus::Ready => "ready",
+            ComputeBackendStatus::Failed => "failed",
+        }
+    }
+}
+
+
+pub struct FluidComputeWorkerPlugin<W: ComputeWorker> {
+    _phantom: PhantomData<W>,
+}
+
+
+impl<W: ComputeWorker> Default for FluidComputeWorkerPlugin<W> {
+    fn default() -> Self {
+        Self {
+            _phantom: Default::default(),
+        }
+    }
+}
+
+
+impl<W: ComputeWorker> Plugin for FluidComputeWorkerPlugin<W> {
+    fn build(&self, app: &mut App) {
+        // The step length comes from the props, set from the config before this plugin is added
+        let delta_time = app.world.get_resource::<FluidStaticProps>().map_or(1. / PARTICLE_SIM_HZ, |props| props.delta_time);
+        app.insert_resource(Time::<Fixed>::from_seconds(delta_time.into()));
+    }
+
+    fn finish(&self, app: &mut App) {
+        let worker = W::build(&mut app.world);
+
+        app
+            .insert_resource(worker)
+            .add_systems(Update, AppComputeWorker::<W>::extract_pipelines)
+            .add_systems(PostUpdate, (
+                AppComputeWorker::<W>::unmap_all.in_set(ShaderPhysicsSet::Prepare),
+                AppComputeWorker::<W>::run.in_set(ShaderPhysicsSet::Pass)
+            ))
+            .add_systems(Last, shutdown_worker::<W>);
+    }
+}
+
+
+fn shutdown_worker<W: ComputeWorker>(
+    mut commands: Commands,
+    mut exit_events: EventReader<AppExit>,
+    render_device: Res<RenderDevice>,
+) {
+    if exit_events.is_empty() {
+        return;
+    }
+    exit_events.clear();
+
+    // Wait for the in-flight dispatches before the buffers are dropped
+    render_device.poll(Maintain::Wait);
+    commands.remove_resource::<AppComputeWorker<W>>();
+}
+
+
+pub struct FluidComputePlugin;
+
+
+impl Plugin for FluidComputePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<FluidStaticProps>()
+            .init_resource::<FluidCalibration>()
+            .init_resource::<SmoothingRadiusScaling>()
+            .init_resource::<SplashSettings>()
+            .init_resource::<FluidStats>()
+            .init_resource::<SettleDetector>()
+            .init_resource::<CflSettings>()
+            .init_resource::<FluidTypes>()
+            .init_resource::<BoundaryParticles>()
+            .init_resource::<IndexReadback>()
+            .init_resource::<ComputeBackendStatus>()
+            .add_event::<SplashEvent>()
+            .add_event::<RebuildWorkerEvent>()
+            .add_event::<FluidResetEvent>()
+            .add_event::<FluidSettled>()
+            .init_resource::<FluidParticlesInitial>()
+            .add_plugins(ComputeTuningPlugin)
+            .add_plugins(AppComputePlugin)
+            .add_plugins(FluidComputeWorkerPlugin::<FluidWorker>::default())
+            .add_systems(Update, sync_fixed_timestep.run_if(resource_changed::<FluidStaticProps>))
+            .add_systems(Update, watch_compute_backend);
+    }
+}
+
+
+/// Rebuilds drop back to initializing, the timeout starts over
+fn watch_compute_backend(
+    mut status: ResMut<ComputeBackendStatus>,
+    mut waited: Local<f32>,
+    worker: Res<AppComputeWorker<FluidWorker>>,
+    time: Res<Time>,
+) {
+    if worker.ready() {
+        *waited = 0.;
+        status.set_if_neq(ComputeBackendStatus::Ready);
+        return;
+    }
+
+    *waited += time.delta_seconds();
+    if *status == ComputeBackendStatus::Ready {
+        *status = ComputeBackendStatus::Initializing;
+    } else if *status == ComputeBackendStatus::Initializing && *waited > COMPUTE_BACKEND_TIMEOUT {
+        *status = ComputeBackendStatus::Failed;
+        println!(
+            "Warning: the compute worker isn't ready after {}s, the fluid won't move. \
+            Likely a shader that failed to compile or a graphics backend without compute shader support.",
+            COMPUTE_BACKEND_TIMEOUT,
+        );
+    }
+}
+
+
+/// Presets and the HUD change the step length, the fixed clock dispatching the steps follows
+fn sync_fixed_timestep(mut time: ResMut<Time<Fixed>>, fluid_props: Res<FluidStaticProps>) {
+    let period = time.timestep().as_secs_f32();
+    if fluid_props.delta_time > 0. && (period - fluid_props.delta_time).abs() > f32::EPSILON {
+        time.set_timestep_seconds(fluid_props.delta_time.into());
+    }
+}
+
+
+/// Icosphere subdivisions of the shared particle mesh, trades smoothness for triangle count
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct ParticleMeshSettings {
+    pub subdivisions: usize,
+    /// Drawn size only, the collisions and the wall padding keep `PARTICLE_RADIUS`
+    pub render_radius: f32,
+}
+
+
+impl ParticleMeshSettings {
+    pub fn build_mesh(&self) -> Mesh {
+        Sphere::new(self.render_radius).mesh().ico(self.subdivisions).unwrap()
+    }
+}
+
+
+impl Default for ParticleMeshSettings {
+    fn default() -> Self {
+        Self {
+            subdivisions: PARTICLE_MESH_SUBDIVISIONS,
+            render_radius: PARTICLE_RADIUS,
+        }
+    }
+}
+
+
+#[derive(Resource, Debug)]
+pub struct ParticleMesh(pub Handle<Mesh>);
+
+
+/// Index of the particle in the GPU buffers
+#[derive(Component, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct FluidParticleLabel(pub usize);
+
+
+#[derive(Component, Default, Debug)]
+struct Velocity(Vec3);
+
+
+/// Rendered reflection of a simulated particle across the container mirror plane
+#[derive(Component, Debug)]
+struct MirroredParticle;
+
+
+type AppHook = Mutex<Option<Box<dyn FnOnce(&mut App) + Send>>>;
+
+
+#[derive(Default)]
+pub struct FluidPlugin {
+    spawn: FluidSpawnConfig,
+    neighbor_search: NeighborSearch,
+    hooks: Vec<AppHook>,
+}
+
+
+impl FluidPlugin {
+    pub fn builder() -> FluidPluginBuilder {
+        FluidPluginBuilder::default()
+    }
+}
+
+
+/// Configures the fluid plugin and lets callers hook their own systems into the simulation step.
+///
+/// Systems that read or write the GPU buffers should run in `InGameSet::EntityUpdates`, which
+/// happens before the buffers are unmapped (`ShaderPhysicsSet::Prepare`) and dispatched
+/// (`ShaderPhysicsSet::Pass`) in `PostUpdate`:
+///
+/// ```ignore
+/// fn drag(mut worker: ResMut<AppComputeWorker<FluidWorker>>) {
+///     if !worker.ready() {
+///         return;
+///     }
+///     let mut particles = worker.read_vec::<FluidParticle>("particles");
+///     for particle in particles.iter_mut() {
+///         particle.velocity *= 0.99;
+///     }
+///     worker.write_slice("particles", &particles);
+/// }
+///
+/// App::new().add_plugins(
+///     FluidPlugin::builder()
+///         .shape(FluidShape::Cube(UVec3::new(32, 16, 16)))
+///         .add_systems(Update, drag.in_set(InGameSet::EntityUpdates))
+///         .build()
+/// );
+/// ```
+#[derive(Default)]
+pub struct FluidPluginBuilder {
+    plugin: FluidPlugin,
+}
+
+
+impl FluidPluginBuilder {
+    pub fn shape(mut self, shape: FluidShape) -> Self {
+        self.plugin.spawn.shape = shape;
+        self
+    }
+
+    pub fn max_particles(mut self, max_particles: u32) -> Self {
+        self.plugin.spawn.max_particles = Some(max_particles);
+        self
+    }
+
+    pub fn neighbor_search(mut self, neighbor_search: NeighborSearch) -> Self {
+        self.plugin.neighbor_search = neighbor_search;
+        self
+    }
+
+    pub fn add_systems<M: 'static>(
+        mut self,
+        schedule: impl ScheduleLabel,
+        systems: impl IntoSystemConfigs<M> + Send + 'static,
+    ) -> Self {
+        self.plugin.hooks.push(Mutex::new(Some(Box::new(move |app: &mut App| {
+            app.add_systems(schedule, systems);
+        }))));
+        self
+    }
+
+    pub fn build(self) -> FluidPlugin {
+        self.plugin
+    }
+}
+
+
+impl Plugin for FluidPlugin {
+    fn build(&self, app: &mut App) {
+        for hook in self.hooks.iter() {
+            if let Some(hook) = hook.lock().unwrap().take() {
+                hook(app);
+            }
+        }
+
+        app
+            .insert_resource(self.spawn.clone())
+            .insert_resource(self.neighbor_search)
+            .add_plugins(FluidComputePlugin)
+            .init_resource::<ParticleMeshSettings>()
+            .init_resource::<FluidScenario>()
+            .init_resource::<FluidSpawnCount>()
+            .init_resource::<FluidRandomSeed>()
+            .init_resource::<FluidSpawnImage>()
+            .add_systems(OnTransition { from: GameState::Menu, to: GameState::InGame }, (apply_scenario, setup).chain())
+            .add_systems(Update, (
+                update_particle_mesh_settings.in_set(InGameSet::UserInput),
+                toggle_cfl.in_set(InGameSet::UserInput),
+                toggle_boundary_particles.in_set(InGameSet::UserInput),
+                sync_boundary_particles.after(toggle_boundary_particles).in_set(InGameSet::UserInput),
+                scale_smoothing_radius.before(update).in_set(InGameSet::EntityUpdates),
+                rebuild_index_readback.before(update).in_set(InGameSet::EntityUpdates),
+                // The read-back still holds the spawn of the new worker until the carried particles land
+                update.run_if(not(resource_exists::<PendingParticles>)).in_set(InGameSet::EntityUpdates),
+                restore_pending_particles.after(update).in_set(InGameSet::EntityUpdates),
+                detect_splash.before(update).in_set(InGameSet::EntityUpdates),
+                update_fluid_stats.in_set(InGameSet::EntityUpdates),
+                detect_settling.after(update_fluid_stats).in_set(InGameSet::EntityUpdates),
+                recover_non_finite.after(update).in_set(InGameSet::EntityUpdates),
+                sync_particle_count.after(update).in_set(InGameSet::EntityUpdates),
+                despawn_removed_particles.after(update).in_set(InGameSet::EntityUpdates),
+                update_particle_mesh.in_set(InGameSet::EntityUpdates),
+            ))
+            .add_systems(Update, (despawn_liquid, reseed_liquid).in_set(InGameSet::DespawnEntities))
+            .add_systems(Last, rebuild_worker.run_if(in_state(GameState::Menu).or_else(in_state(GameState::Settings))))
+            .add_systems(OnTransition { from: GameState::Paused, to: GameState::Menu }, teardown_liquid);
+    }
+}
+
+
+/// The buffer sizes and the bitonic pass count are baked into the worker, so a new capacity needs a new worker
+fn rebuild_worker(world: &mut World) {
+    let Some(event) = world.resource_mut::<Events<RebuildWorkerEvent>>().drain().last() else { return };
+
+    // The old buffers have to outlive the dispatches still reading them
+    world.resource::<RenderDevice>().poll(Maintain::Wait);
+
+    let num_boundary = boundary_particles(world.resource::<FluidContainer>(), PARTICLE_RADIUS).len() as u32;
+    let mut spawn_config = world.resource_mut::<FluidSpawnConfig>();
+    spawn_config.max_particles = Some(event.num_particles + spawn_config.emit_headroom + num_boundary);
+    let worker = FluidWorker::build(world);
+    world.insert_resource(worker);
+
+    // The new capacity starts without the boundary layer
+    world.resource_mut::<BoundaryParticles>().set_changed();
+    println!("Rebuilt the fluid worker for a capacity of {}", world.resource::<FluidCapacity>().max_particles);
+}
+
+
+/// Rebuilds the worker in game when the index staging is toggled, the particles and the capacity carry over
+fn rebuild_index_readback(world: &mut World) {
+    let readback = world.resource::<IndexReadback>();
+    if readback.enabled == readback.staged || !world.resource::<AppComputeWorker<FluidWorker>>().ready() {
+        return;
+    }
+
+    // Boundary layer included, it stays where the capacity says
+    let particles = world.resource::<AppComputeWorker<FluidWorker>>().read_vec::<FluidParticle>("particles");
+    let capacity = *world.resource::<FluidCapacity>();
+    let fluid_initials = world.resource::<FluidParticlesInitial>().clone();
+    let target_density = world.resource::<FluidStaticProps>().target_density;
+
+    // The old buffers have to outlive the dispatches still reading them
+    world.resource::<RenderDevice>().poll(Maintain::Wait);
+
+    let max_particles = world.resource_mut::<FluidSpawnConfig>().max_particles.replace(capacity.max_particles);
+    let worker = FluidWorker::build(world);
+    world.resource_mut::<FluidSpawnConfig>().max_particles = max_particles;
+
+    // Undo what the build derives from the spawn
+    world.insert_resource(worker);
+    world.insert_resource(capacity);
+    world.insert_resource(fluid_initials);
+    world.resource_mut::<FluidStaticProps>().target_density = target_density;
+    world.insert_resource(PendingParticles(particles));
+}
+
+
+fn restore_pending_particles(
+    mut commands: Commands,
+    mut worker: ResMut<AppComputeWorker<FluidWorker>>,
+    pending: Option<Res<PendingParticles>>,
+    capacity: Res<FluidCapacity>,
+) {
+    let Some(pending) = pending else { return };
+    if !worker.ready() {
+        return;
+    }
+    worker.write_slice("particles", &pending.0);
+    // The build wrote the spawn count
+    worker.write("num_particles", &capacity.num_particles);
+    worker.write("num_sorted", &capacity.get_num_sorted());
+    commands.remove_resource::<PendingParticles>();
+}
+
+
+/// Swaps the built spawn for the scenario's, the buffers keep their capacity
+fn apply_scenario(
+    mut worker: ResMut<AppComputeWorker<FluidWorker>>,
+    mut fluid_initials: ResMut<FluidParticlesInitial>,
+    mut capacity: ResMut<FluidCapacity>,
+    scenario: Res<FluidScenario>,
+    spawn_count: Res<FluidSpawnCount>,
+    spawn_config: Res<FluidSpawnConfig>,
+    seed: Res<FluidRandomSeed>,
+    spawn_image: Res<FluidSpawnImage>,
+    container: Res<FluidContainer>,
+    mut reset_events: EventWriter<FluidResetEvent>,
+) {
+    // The initials may hold an earlier scenario when coming back from the menu
+    let mut points = match (*scenario, spawn_count.0) {
+        (FluidScenario::DamBreak, _) => FluidShape::DamBreak.spawn(&container),
+        (FluidScenario::Image, _) => {
+            let points = get_image_points(&spawn_image.0, &container);
+            if points.is_empty() {
+                println!("Image scenario has no particles, falling back to the default block");
+                spawn_config.spawn(&container)
+            } else {
+                points
+            }
+        },
+        (FluidScenario::Random, count) => {
+            let count = count.map_or_else(|| spawn_config.spawn(&container).len(), |count| count as usize);
+            get_random_points(&container, count, seed.0)
+        },
+        (_, Some(count)) => get_block_points(count as usize),
+        (_, None) => spawn_config.spawn(&container),
+    };
+    container.retain_simulated(&mut points);
+    let room = capacity.get_first_boundary();
+    if points.len() > room as usize {
+        println!("Scenario of {} particles exceeds the capacity of {}, truncating", points.len(), room);
+        points.truncate(room as usize);
+    }
+    capacity.num_particles = points.len() as u32;
+
+    fluid_initials.fluid_ids = match *scenario {
+        FluidScenario::TwoFluids => get_layered_fluid_ids(&points),
+        _ => Vec::new(),
+    };
+    fluid_initials.positions = points;
+    // Up to the boundary layer, which stays in place
+    let mut particles = fluid_initials.make_particles();
+    particles.resize(room as usize, FluidParticle::default());
+    worker.write_slice("particles", &particles);
+    reset_events.send(FluidResetEvent);
+}
+
+
+/// Block in the default 2:1:1 proportions, the last slice is left partial to hit the count
+fn get_block_points(count: usize) -> Vec<Vec3> {
+    let size = ((count as f32 / 2.).cbrt().ceil() as usize).max(1);
+    let mut points = cube_fluid(size * 2, size, size, PARTICLE_RADIUS);
+    points.truncate(count);
+    points
+}
+
+
+/// Scattered inside the walls of the rotated container
+fn get_random_points(container: &FluidContainer, count: usize, seed: u64) -> Vec<Vec3> {
+    let ext = container.get_ext(container.wall_margin + PARTICLE_RADIUS);
+    let points = random_fluid(count, ext.ext_min.xyz(), ext.ext_max.xyz().max(ext.ext_min.xyz()), seed);
+    points.into_iter()
+        .map(|point| container.position + container.rotation * (point - container.position))
+        .collect()
+}
+
+
+/// Image extruded along Z around the container center, in the rotated container
+fn get_image_points(path: &Path, container: &FluidContainer) -> Vec<Vec3> {
+    let pixels = fluid_from_image(path, container, PARTICLE_RADIUS);
+    let mut points = Vec::with_capacity(pixels.len() * SPAWN_IMAGE_LAYERS);
+    for layer in 0..SPAWN_IMAGE_LAYERS {
+        let z = container.position.z + (layer as f32 - (SPAWN_IMAGE_LAYERS - 1) as f32 / 2.) * PARTICLE_RADIUS * 2.;
+        for pixel in pixels.iter() {
+            let point = pixel.extend(z) - container.position;
+            points.push(container.position + container.rotation * point);
+        }
+    }
+    points
+}
+
+
+/// Second fluid below the middle height of the points, the first one above
+fn get_layered_fluid_ids(points: &[Vec3]) -> Vec<u32> {
+    let min_y = points.iter().map(|point| point.y).fold(f32::MAX, f32::min);
+    let max_y = points.iter().map(|point| point.y).fold(f32::MIN, f32::max);
+    let middle_y = (min_y + max_y) / 2.;
+    points.iter().map(|point| if point.y < middle_y { 1 } else { 0 }).collect()
+}
+
+
 fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
     fluid_initials: Res<FluidParticlesInitial>,
+    mesh_settings: Res<ParticleMeshSettings>,
+    container: Res<FluidContainer>,
+    palette: Res<ParticlePalette>,
+) {
+    let shape = meshes.add(mesh_settings.build_mesh());
+    commands.insert_resource(ParticleMesh(shape.clone()));
+    spawn_particle_entities(&mut commands, &shape, &palette.solid, &container, &fluid_initials.positions, 0);
+}
+
+
+/// Render entities for the buffer slots starting at `first_id`, plus their reflections for a mirrored container
+pub fn spawn_particle_entities(
+    commands: &mut Commands,
+    mesh: &Handle<Mesh>,
+    material: &Handle<StandardMaterial>,
+    container: &FluidContainer,
+    positions: &[Vec3],
+    first_id: usize,
 ) {
-    let shape = meshes.add(Sphere::new(PARTICLE_RADIUS).mesh().ico(0).unwrap());
-    let material = materials.add(StandardMaterial {
-        base_color: Color::CYAN,
-        ..default()
-    });
     let mut particle_bundles = Vec::new();
-    let mut particle_id: usize = 0;
-    for &point in &fluid_initials.positions {
+    for (it, &point) in positions.iter().enumerate() {
         particle_bundles.push((
             PbrBundle {
-                mesh: shape.clone(),
+                mesh: mesh.clone(),
                 material: material.clone(),
                 transform: Transform::from_translation(point),
                 ..default()
             },
             Velocity::default(),
-            FluidParticleLabel(particle_id),
+            FluidParticleLabel(first_id + it),
         ));
-        particle_id += 1;
     }
     commands.spawn_batch(particle_bundles);
+
+    if !container.mirror_x {
+        return;
+    }
+    let mut mirrored_bundles = Vec::new();
+    for (it, &point) in positions.iter().enumerate() {
+        mirrored_bundles.push((
+            PbrBundle {
+                mesh: mesh.clone(),
+                material: material.clone(),
+                transform: Transform::from_translation(container.mirror_position(point)),
+                ..default()
+            },
+            FluidParticleLabel(first_id + it),
+            MirroredParticle,
+        ));
+    }
+    commands.spawn_batch(mirrored_bundles);
+}
+
+
+fn scale_smoothing_radius(
+    mut fluid_props: ResMut<FluidStaticProps>,
+    scaling: Res<SmoothingRadiusScaling>,
+    container: Res<FluidContainer>,
+    fluid_initials: Res<FluidParticlesInitial>,
+) {
+    if !scaling.enabled || !(scaling.is_changed() || container.is_changed() || fluid_initials.is_changed()) {
+        return;
+    }
+    fluid_props.smoothing_radius = scaling.get_smoothing_radius(&container, fluid_initials.positions.len());
 }
 
 
 fn update(
-    mut query: Query<(&mut Transform, &FluidParticleLabel)>,
+    mut query: Query<(&mut Transform, &FluidParticleLabel, Has<MirroredParticle>)>,
     mut worker: ResMut<AppComputeWorker<FluidWorker>>,
     fluid_props: Res<FluidStaticProps>,
     gravity: Res<Gravity>,
+    gravity_mode: Res<GravityMode>,
+    container: Res<FluidContainer>,
+    walls: Res<WallProps>,
+    paddle: Res<Paddle>,
+    obstacles: Res<Obstacles>,
+    force_toggles: Res<ForceToggles>,
+    cfl: Res<CflSettings>,
+    stats: Res<FluidStats>,
+    fluid_types: Res<FluidTypes>,
+    capacity: Res<FluidCapacity>,
+    heat_source: Res<HeatSource>,
+    shake: Res<WindowShake>,
 ) {
     if !worker.ready() {
         return;
     }
 
     let particles = worker.read_vec::<FluidParticle>("particles");
-    worker.write("fluid_props", fluid_props.as_ref());
+    worker.write("fluid_props", &force_toggles.apply(&fluid_props));
     worker.write("smoothing_kernel", &fluid_props.get_smoothing_kernel());
-    worker.write("gravity", gravity.as_ref());
-
-    query.par_iter_mut().for_each(|(mut transform, particle)| {
-        transform.translation = particles[particle.0].position.xyz();
+    worker.write("gravity", &shake.apply(&force_toggles.apply_gravity(&gravity_mode.apply(&gravity))));
+    worker.write("fluid_container", &container.get_simulation_ext(fluid_props.collision_damping, &walls));
+    worker.write("spatial_grid", &SpatialGrid::new(&container, fluid_props.smoothing_radius).with_boundary(&capacity));
+    worker.write("paddle", &paddle.get_ext());
+    worker.write("obstacles", &obstacles.get_ext());
+    worker.write("substeps", &cfl.get_substeps(stats.max_speed, &fluid_props));
+    worker.write("fluid_types", &fluid_types.get_ext());
+    worker.write("heat_source", &heat_source.get_ext());
+
+    query.par_iter_mut().for_each(|(mut transform, particle, mirrored)| {
+        let position = particles[particle.0].position.xyz();
+        transform.translation = if mirrored { container.mirror_position(position) } else { position };
     });
 }
 
 
-// fn update_color(
-//     color_query: Query<(&Handle<ColorMaterial>, &Velocity), With<FluidParticleLabel>>,
-//     mut materials: ResMut<Assets<ColorMaterial>>,
-// ) {
-//     // Color gradient depending on the velocity HSL: 20 <= H <= 200, S = 100, L = 50
-//     for (material_handle, velocity) in color_query.iter() {
-//         let Some(material) = materials.get_mut(material_handle) else { continue };
-//         let magnitude = velocity.0.length_squared();
-//         if magnitude < 40. {
-//             let h = (1. - magnitude / 40.) * 180. + 20.;
-//             material.color = Color::hsl(h, 1., 0.5);
-//         }
-//     }
-// }
+fn toggle_cfl(mut cfl: ResMut<CflSettings>, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if keyboard_input.just_pressed(CFL_TOGGLE_KEY) {
+        cfl.enabled = !cfl.enabled;
+    }
+}
+
+
+fn update_fluid_stats(
+    mut stats: ResMut<FluidStats>,
+    worker: Res<AppComputeWorker<FluidWorker>>,
+    capacity: Res<FluidCapacity>,
+) {
+    if !worker.ready() {
+        return;
+    }
+    let particles = worker.read_vec::<FluidParticle>("particles");
+    *stats = FluidStats::from_particles(&particles[..capacity.num_particles as usize]);
+}
+
+
+/// One observation per finished step, the stats are only refreshed while the worker is ready
+fn detect_settling(
+    mut detector: ResMut<SettleDetector>,
+    mut settled_events: EventWriter<FluidSettled>,
+    mut reset_events: EventReader<FluidResetEvent>,
+    worker: Res<AppComputeWorker<FluidWorker>>,
+    capacity: Res<FluidCapacity>,
+    stats: Res<FluidStats>,
+) {
+    if !reset_events.is_empty() {
+        reset_events.clear();
+        detector.reset();
+    }
+    if !worker.ready() || capacity.num_particles == 0 {
+        return;
+    }
+    if detector.observe(stats.mean_kinetic_energy) {
+        println!("Fluid settled, mean squared speed {:.4}", stats.mean_kinetic_energy);
+        settled_events.send(FluidSettled { mean_kinetic_energy: stats.mean_kinetic_energy });
+    }
+}
+
+
+fn toggle_boundary_particles(mut boundary: ResMut<BoundaryParticles>, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if keyboard_input.just_pressed(BOUNDARY_PARTICLES_TOGGLE_KEY) {
+        boundary.enabled = !boundary.enabled;
+    }
+}
+
+
+/// Rebuilds the wall layer in the buffer tail. Runs before the emitter and the drain,
+/// their prefix writes later in the frame land on top of this one.
+fn sync_boundary_particles(
+    mut worker: ResMut<AppComputeWorker<FluidWorker>>,
+    mut boundary: ResMut<BoundaryParticles>,
+    mut capacity: ResMut<FluidCapacity>,
+    container: Res<FluidContainer>,
+) {
+    if !(boundary.is_changed() || container.is_changed()) || !worker.ready() {
+        return;
+    }
+    if !boundary.enabled && capacity.num_boundary == 0 {
+        return;
+    }
+
+    let points = if boundary.enabled { boundary_particles(&container, PARTICLE_RADIUS) } else { Vec::new() };
+    let room = capacity.max_particles - capacity.num_particles;
+    if points.len() > room as usize {
+        println!("Boundary of {} particles doesn't fit the {} free slots, disabling it", points.len(), room);
+        boundary.enabled = false;
+        capacity.num_boundary = 0;
+        return;
+    }
+
+    capacity.num_boundary = points.len() as u32;
+    let first_boundary = capacity.get_first_boundary() as usize;
+    let mut particles = worker.read_vec::<FluidParticle>("particles");
+    particles[first_boundary..].copy_from_slice(&FluidParticle::make_vec_from_positions(points));
+    worker.write_slice("particles", &particles);
+}
+
+
+fn sync_particle_count(mut worker: ResMut<AppComputeWorker<FluidWorker>>, capacity: Res<FluidCapacity>) {
+    if !capacity.is_changed() || !worker.ready() {
+        return;
+    }
+    worker.write("num_particles", &capacity.num_particles);
+    worker.write("num_sorted", &capacity.get_num_sorted());
+}
+
+
+/// Drops the render entities of slots past the live count
+fn despawn_removed_particles(
+    mut commands: Commands,
+    query: Query<(Entity, &FluidParticleLabel)>,
+    capacity: Res<FluidCapacity>,
+) {
+    if !capacity.is_changed() {
+        return;
+    }
+    for (entity, particle) in query.iter() {
+        if particle.0 >= capacity.num_particles as usize {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+
+fn detect_splash(
+    mut worker: ResMut<AppComputeWorker<FluidWorker>>,
+    mut splash_events: EventWriter<SplashEvent>,
+    splash_settings: Res<SplashSettings>,
+) {
+    if !worker.ready() {
+        return;
+    }
+
+    let wall_impact = worker.read::<u32>("wall_impact") as f32 / SPLASH_FIXED_POINT_SCALE;
+    if wall_impact > splash_settings.momentum_threshold {
+        splash_events.send(SplashEvent { momentum: wall_impact });
+    }
+    // Reset the accumulator for the next step
+    worker.write("wall_impact", &0u32);
+}
+
+
+fn update_particle_mesh_settings(
+    mut mesh_settings: ResMut<ParticleMeshSettings>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+) {
+    if keyboard_input.just_pressed(KeyCode::BracketLeft) && mesh_settings.subdivisions > 0 {
+        mesh_settings.subdivisions -= 1;
+    } else if keyboard_input.just_pressed(KeyCode::BracketRight)
+        && mesh_settings.subdivisions < PARTICLE_MESH_MAX_SUBDIVISIONS {
+        mesh_settings.subdivisions += 1;
+    }
+}
+
+
+fn update_particle_mesh(
+    mut meshes: ResMut<Assets<Mesh>>,
+    mesh_settings: Res<ParticleMeshSettings>,
+    particle_mesh: Option<Res<ParticleMesh>>,
+    fluid_initials: Res<FluidParticlesInitial>,
+) {
+    if !mesh_settings.is_changed() {
+        return;
+    }
+    let Some(particle_mesh) = particle_mesh else { return };
+
+    // All particles share the handle, so replacing the asset updates every instance
+    let mesh = mesh_settings.build_mesh();
+    let num_triangles = mesh.indices().map_or(0, |indices| indices.len() / 3);
+    println!(
+        "Particle mesh: {} subdivisions, {} triangles per particle, {} in total",
+        mesh_settings.subdivisions,
+        num_triangles,
+        num_triangles * fluid_initials.positions.len(),
+    );
+    meshes.insert(particle_mesh.0.id(), mesh);
+}
 
 
 fn despawn_liquid(
     mut worker: ResMut<AppComputeWorker<FluidWorker>>,
     mut next_state: ResMut<NextState<GameState>>,
     fluid_initials: Res<FluidParticlesInitial>,
+    mut capacity: ResMut<FluidCapacity>,
+    mut reset_events: EventWriter<FluidResetEvent>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
 ) {
     if !keyboard_input.just_pressed(KeyCode::Space) || !worker.ready() {
@@ -513,13 +1950,539 @@ fn despawn_liquid(
     }
 
     next_state.set(GameState::GameOver);
+    reset_buffers(&mut worker, &fluid_initials, &mut capacity);
+    reset_events.send(FluidResetEvent);
+}
+
+
+/// Restarts the current scenario in place, the tuning and the render entities are kept
+fn reseed_liquid(
+    mut worker: ResMut<AppComputeWorker<FluidWorker>>,
+    fluid_initials: Res<FluidParticlesInitial>,
+    mut capacity: ResMut<FluidCapacity>,
+    mut reset_events: EventWriter<FluidResetEvent>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+) {
+    if !keyboard_input.just_pressed(RESEED_KEY) || !worker.ready() {
+        return;
+    }
+    reset_buffers(&mut worker, &fluid_initials, &mut capacity);
+    reset_events.send(FluidResetEvent);
+}
+
+
+/// Leaving for the menu: the buffers go back to the spawn and the render entities are rebuilt on the next start
+fn teardown_liquid(
+    mut commands: Commands,
+    mut worker: ResMut<AppComputeWorker<FluidWorker>>,
+    mut capacity: ResMut<FluidCapacity>,
+    fluid_initials: Res<FluidParticlesInitial>,
+    mut reset_events: EventWriter<FluidResetEvent>,
+    query: Query<Entity, With<FluidParticleLabel>>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn();
+    }
+    reset_buffers(&mut worker, &fluid_initials, &mut capacity);
+    reset_events.send(FluidResetEvent);
+}
+
+
+/// Puts the particles back where they spawned, emitted particles are dropped
+fn reset_buffers(worker: &mut AppComputeWorker<FluidWorker>, fluid_initials: &FluidParticlesInitial, capacity: &mut FluidCapacity) {
+    restore_particles(worker, capacity, &fluid_initials.make_particles());
+}
+
 
-    let num_particles = fluid_initials.positions.len() as u32;
-    let initial_index_buffer = FluidWorker::create_initial_index_buffer(num_particles);
-    let initial_particle_buffer = FluidParticle::make_vec_from_positions(fluid_initials.positions.clone());
+/// Replaces the live particles, `particles` must fit below the capacity. The boundary layer is dropped when it overlaps.
+pub fn restore_particles(worker: &mut AppComputeWorker<FluidWorker>, capacity: &mut FluidCapacity, particles: &[FluidParticle]) {
+    if capacity.restore(particles.len() as u32) {
+        println!("Boundary particles overlap the restored particles, dropping them");
+    }
+
+    // The whole key buffer is reset, a partial write would leave duplicate keys in the padding
+    let initial_index_buffer = FluidWorker::create_initial_index_buffer(get_sort_length(capacity.max_particles));
 
-    worker.write_slice("particles", &initial_particle_buffer);
+    worker.write_slice("particles", particles);
     worker.write_slice("particle_indicies", &initial_index_buffer);
     worker.write_slice("particle_cell_indicies", &initial_index_buffer);
-    worker.write_slice("cell_offsets", &initial_index_buffer);
+    worker.write_slice("cell_offsets", &initial_index_buffer[..capacity.max_particles as usize]);
+}
+
+
+/// Non-finite velocities become zero, the rest is limited to `max_speed` unless it is zero
+pub fn clamp_speed(velocity: Vec3, max_speed: f32) -> Vec3 {
+    if !velocity.is_finite() {
+        return Vec3::ZERO;
+    }
+    if max_speed > 0. {
+        velocity.clamp_length_max(max_speed)
+    } else {
+        velocity
+    }
+}
+
+
+/// What the live particles needed after a non-finite check
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NonFiniteRecovery {
+    Finite,
+    /// Velocities of that many particles were non-finite, every velocity got clamped in place
+    Clamped(usize),
+    /// Positions of that many particles were non-finite, they can't be recovered without a reset
+    Reset(usize),
+}
+
+
+/// Clamps the velocities when some are non-finite, leaves the particles alone when a position is
+pub fn recover_particles(particles: &mut [FluidParticle], max_speed: f32) -> NonFiniteRecovery {
+    let bad_positions = particles.iter().filter(|it| !it.position.is_finite()).count();
+    if bad_positions > 0 {
+        return NonFiniteRecovery::Reset(bad_positions);
+    }
+    let bad_velocities = particles.iter().filter(|it| !it.velocity.is_finite()).count();
+    if bad_velocities == 0 {
+        return NonFiniteRecovery::Finite;
+    }
+    for particle in particles.iter_mut() {
+        let velocity = clamp_speed(particle.velocity.truncate(), max_speed);
+        particle.velocity = velocity.extend(0.);
+    }
+    NonFiniteRecovery::Clamped(bad_velocities)
+}
+
+
+/// Once the solver diverges: bad velocities are clamped, bad positions reset the whole fluid
+fn recover_non_finite(
+    mut worker: ResMut<AppComputeWorker<FluidWorker>>,
+    mut frames: Local<u32>,
+    fluid_props: Res<FluidStaticProps>,
+    fluid_initials: Res<FluidParticlesInitial>,
+    mut capacity: ResMut<FluidCapacity>,
+    mut reset_events: EventWriter<FluidResetEvent>,
+) {
+    if *frames > 0 {
+        *frames -= 1;
+        return;
+    }
+    if !worker.ready() || capacity.num_particles == 0 {
+        return;
+    }
+    *frames = NON_FINITE_CHECK_FRAMES;
+
+    let mut particles = worker.read_vec::<FluidParticle>("particles");
+    let live_particles = &mut particles[..capacity.num_particles as usize];
+    match recover_particles(live_particles, fluid_props.max_speed) {
+        NonFiniteRecovery::Finite => (),
+        NonFiniteRecovery::Clamped(bad_velocities) => {
+            println!("Warning: {} particles have non-finite velocities, clamping", bad_velocities);
+            // The boundary tail may have been rebuilt earlier this frame
+            worker.write_slice("particles", live_particles);
+        },
+        NonFiniteRecovery::Reset(bad_positions) => {
+            println!("Warning: {} particles have non-finite positions, resetting the fluid", bad_positions);
+            reset_buffers(&mut worker, &fluid_initials, &mut capacity);
+            reset_events.send(FluidResetEvent);
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_detector() -> SettleDetector {
+        SettleDetector {
+            energy_threshold: 0.1,
+            window: 3,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn settles_once_after_the_window() {
+        let mut detector = make_detector();
+        // Halves every step, 0.0625 at index 4 is the first one below the threshold
+        let energies: Vec<f32> = (0..12).map(|it| 0.5_f32.powi(it)).collect();
+        let fired: Vec<usize> = energies.iter()
+            .enumerate()
+            .filter(|(_, &energy)| detector.observe(energy))
+            .map(|(it, _)| it)
+            .collect();
+        assert_eq!(fired, vec![6]);
+        assert!(detector.settled);
+    }
+
+    #[test]
+    fn stays_quiet_while_settled() {
+        let mut detector = make_detector();
+        let fired = (0..100).filter(|_| detector.observe(0.)).count();
+        assert_eq!(fired, 1);
+    }
+
+    #[test]
+    fn rearms_after_a_spike() {
+        let mut detector = make_detector();
+        assert!(!detector.observe(0.));
+        assert!(!detector.observe(0.));
+        assert!(detector.observe(0.));
+
+        assert!(!detector.observe(5.));
+        assert_eq!(detector.streak, 0);
+        assert!(!detector.settled);
+        assert!(!detector.observe(0.));
+        assert!(!detector.observe(0.));
+        assert!(detector.observe(0.));
+    }
+
+    #[test]
+    fn rearms_after_a_reset() {
+        let mut detector = make_detector();
+        for _ in 0..3 {
+            detector.observe(0.);
+        }
+        assert!(detector.settled);
+
+        detector.reset();
+        assert!(!detector.observe(0.));
+        assert!(!detector.observe(0.));
+        assert!(detector.observe(0.));
+    }
+
+    #[test]
+    fn non_finite_energy_breaks_the_streak() {
+        let mut detector = make_detector();
+        detector.observe(0.);
+        detector.observe(0.);
+        assert!(!detector.observe(f32::NAN));
+        assert_eq!(detector.streak, 0);
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-5, "{a} != {b}");
+    }
+
+    #[test]
+    fn slow_velocities_pass_through() {
+        let velocity = Vec3::new(1., -2., 0.5);
+        assert_eq!(clamp_speed(velocity, 10.), velocity);
+    }
+
+    #[test]
+    fn fast_velocities_are_capped_along_their_direction() {
+        let velocity = Vec3::new(30., -40., 0.);
+        let clamped = clamp_speed(velocity, 10.);
+        assert!((clamped.length() - 10.).abs() < 1e-4, "{clamped}");
+        assert_close(clamped.normalize(), velocity.normalize());
+    }
+
+    #[test]
+    fn zero_cap_leaves_the_speed_alone() {
+        let velocity = Vec3::new(30., -40., 0.);
+        assert_eq!(clamp_speed(velocity, 0.), velocity);
+    }
+
+    #[test]
+    fn zero_and_non_finite_velocities() {
+        assert_eq!(clamp_speed(Vec3::ZERO, 10.), Vec3::ZERO);
+        assert_eq!(clamp_speed(Vec3::new(f32::NAN, 1., 0.), 10.), Vec3::ZERO);
+        assert_eq!(clamp_speed(Vec3::new(0., f32::INFINITY, 0.), 0.), Vec3::ZERO);
+    }
+
+    fn make_particles() -> Vec<FluidParticle> {
+        let mut particles = FluidParticle::make_vec_from_positions(vec![Vec3::ZERO, Vec3::X, Vec3::Y]);
+        particles[0].velocity = Vec4::new(1., 0., 0., 0.);
+        particles[2].velocity = Vec4::new(0., -50., 0., 0.);
+        particles
+    }
+
+    fn is_finite(particles: &[FluidParticle]) -> bool {
+        particles.iter().all(|it| it.position.is_finite() && it.velocity.is_finite())
+    }
+
+    #[test]
+    fn finite_particles_are_left_alone() {
+        let mut particles = make_particles();
+        assert_eq!(recover_particles(&mut particles, 10.), NonFiniteRecovery::Finite);
+        // Not clamped unless something went wrong
+        assert_eq!(particles[2].velocity.y, -50.);
+    }
+
+    #[test]
+    fn non_finite_velocities_come_back_finite() {
+        let mut particles = make_particles();
+        particles[1].velocity = Vec4::new(f32::NAN, 0., f32::INFINITY, 0.);
+        assert_eq!(recover_particles(&mut particles, 10.), NonFiniteRecovery::Clamped(1));
+        assert!(is_finite(&particles));
+        assert_eq!(particles[1].velocity, Vec4::ZERO);
+        assert_eq!(particles[0].velocity, Vec4::new(1., 0., 0., 0.));
+        assert!((particles[2].velocity.length() - 10.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn non_finite_positions_need_a_reset() {
+        let mut particles = make_particles();
+        particles[0].position.x = f32::INFINITY;
+        particles[1].velocity.x = f32::NAN;
+        assert_eq!(recover_particles(&mut particles, 10.), NonFiniteRecovery::Reset(1));
+    }
+
+    fn make_capacity() -> FluidCapacity {
+        FluidCapacity {
+            max_particles: 100,
+            num_particles: 60,
+            num_boundary: 20,
+            rejected: false,
+        }
+    }
+
+    #[test]
+    fn reset_rebuilds_the_spawned_particles() {
+        let fluid_initials = FluidParticlesInitial {
+            positions: vec![Vec3::new(1., 2., 3.), Vec3::new(-1., 0., 0.5), Vec3::ZERO],
+            fluid_ids: vec![0, 1],
+        };
+        let particles = fluid_initials.make_particles();
+        assert_eq!(particles.len(), 3);
+        for (particle, position) in particles.iter().zip(&fluid_initials.positions) {
+            assert_eq!(particle.position.xyz(), *position);
+            assert_eq!(particle.predicted_position.xyz(), *position);
+            assert_eq!(particle.velocity, Vec4::ZERO);
+        }
+        assert_eq!(particles[0].get_fluid_id(), 0);
+        assert_eq!(particles[1].get_fluid_id(), 1);
+        // Past the ids everything is the first fluid
+        assert_eq!(particles[2].get_fluid_id(), 0);
+    }
+
+    #[test]
+    fn reset_replaces_a_diverged_fluid_with_a_finite_one() {
+        let fluid_initials = FluidParticlesInitial { positions: vec![Vec3::X, Vec3::Y], ..default() };
+        let mut particles = fluid_initials.make_particles();
+        particles[1].position.y = f32::NAN;
+        assert_eq!(recover_particles(&mut particles, 10.), NonFiniteRecovery::Reset(1));
+        assert_eq!(recover_particles(&mut fluid_initials.make_particles(), 10.), NonFiniteRecovery::Finite);
+    }
+
+    #[test]
+    fn restore_keeps_the_boundary_below_it() {
+        let mut capacity = make_capacity();
+        assert!(!capacity.restore(30));
+        assert_eq!(capacity.num_particles, 30);
+        assert_eq!(capacity.num_boundary, 20);
+        assert!(!capacity.restore(80));
+        assert_eq!(capacity.get_free(), 0);
+    }
+
+    #[test]
+    fn restore_drops_an_overlapped_boundary() {
+        let mut capacity = make_capacity();
+        assert!(capacity.restore(81));
+        assert_eq!(capacity.num_particles, 81);
+        assert_eq!(capacity.num_boundary, 0);
+        assert_eq!(capacity.get_num_sorted(), 81);
+        assert_eq!(capacity.get_free(), 19);
+    }
+
+    fn make_timestep_app(delta_time: f32) -> App {
+        let mut app = App::new();
+        app
+            .insert_resource(Time::<Fixed>::from_seconds(1. / 60.))
+            .insert_resource(FluidStaticProps { delta_time, ..default() })
+            .add_systems(Update, sync_fixed_timestep.run_if(resource_changed::<FluidStaticProps>));
+        app
+    }
+
+    fn get_period(app: &App) -> f64 {
+        app.world.resource::<Time<Fixed>>().timestep().as_secs_f64()
+    }
+
+    #[test]
+    fn sim_rate_sets_the_fixed_period() {
+        let mut app = make_timestep_app(1. / 120.);
+        app.update();
+        assert!((get_period(&app) - 1. / 120.).abs() < 1e-6, "{}", get_period(&app));
+
+        app.world.resource_mut::<FluidStaticProps>().delta_time = 1. / 30.;
+        app.update();
+        assert!((get_period(&app) - 1. / 30.).abs() < 1e-6, "{}", get_period(&app));
+    }
+
+    #[test]
+    fn non_positive_step_keeps_the_period() {
+        let mut app = make_timestep_app(0.);
+        app.update();
+        assert!((get_period(&app) - 1. / 60.).abs() < 1e-6, "{}", get_period(&app));
+    }
+
+    fn make_cohesion_props() -> FluidStaticProps {
+        FluidStaticProps { smoothing_radius: 0.4, ..default() }
+    }
+
+    #[test]
+    fn cohesion_pulls_at_mid_range() {
+        let fluid_props = make_cohesion_props();
+        for fraction in [0.3, 0.5, 0.75, 0.9] {
+            let cohesion = fluid_props.get_cohesion(fraction * fluid_props.smoothing_radius);
+            assert!(cohesion > 0., "{fraction}: {cohesion}");
+        }
+    }
+
+    #[test]
+    fn cohesion_pushes_close_in() {
+        let fluid_props = make_cohesion_props();
+        for fraction in [0., 0.1, 0.25] {
+            let cohesion = fluid_props.get_cohesion(fraction * fluid_props.smoothing_radius);
+            assert!(cohesion < 0., "{fraction}: {cohesion}");
+        }
+    }
+
+    #[test]
+    fn cohesion_vanishes_at_the_radius() {
+        let fluid_props = make_cohesion_props();
+        assert_eq!(fluid_props.get_cohesion(fluid_props.smoothing_radius), 0.);
+    }
+
+    #[test]
+    fn cohesion_branches_meet_at_half_the_radius() {
+        let fluid_props = make_cohesion_props();
+        let half = fluid_props.smoothing_radius / 2.;
+        let below = fluid_props.get_cohesion(half);
+        let above = fluid_props.get_cohesion(half + 1e-4);
+        assert!((below - above).abs() < below.abs() * 1e-2, "{below} vs {above}");
+    }
+
+    fn count_neighbors_brute_force(points: &[Vec3], radius: f32) -> Vec<u32> {
+        points.iter()
+            .map(|point| points.iter().filter(|other| point.distance(**other) <= radius).count() as u32 - 1)
+            .collect()
+    }
+
+    #[test]
+    fn neighbor_counts_match_a_brute_force_count() {
+        let fluid_props = FluidStaticProps { smoothing_radius: 0.35, ..default() };
+        let mut points = crate::helpers::cube_fluid(6, 5, 4, 0.1);
+        // Off the lattice as well, across cell borders
+        points.extend(crate::helpers::random_fluid(50, Vec3::splat(-0.6), Vec3::splat(0.6), 3));
+        assert_eq!(fluid_props.get_neighbor_counts(&points), count_neighbors_brute_force(&points, 0.35));
+    }
+
+    #[test]
+    fn neighbor_counts_on_a_lattice() {
+        // A diameter of 0.2 apart, the radius reaches the 6 face neighbours only
+        let fluid_props = FluidStaticProps { smoothing_radius: 0.25, ..default() };
+        let points = crate::helpers::cube_fluid(3, 3, 3, 0.1);
+        let counts = fluid_props.get_neighbor_counts(&points);
+        assert_eq!(counts[13], 6);  // Center
+        assert_eq!(counts[0], 3);  // Corner
+        assert_eq!(fluid_props.get_neighbor_counts(&points[..1]), [0]);
+        assert!(fluid_props.get_neighbor_counts(&[]).is_empty());
+    }
+
+    #[test]
+    fn densities_weigh_each_neighbour_by_its_fluid_mass() {
+        let fluid_props = FluidStaticProps { smoothing_radius: 0.25, ..default() };
+        let fluid_types = FluidTypes::default();
+        let points = [Vec3::ZERO, Vec3::X * 0.1, Vec3::X * 0.2];
+        let densities = fluid_props.get_densities(&points, &[0, 1, 0], &fluid_types);
+        let kernel = fluid_props.get_smoothing_kernel();
+        let weight = |dst: f32| (0.25 - dst).powi(2) * kernel.pow2;
+        let expected = DENSITY_PADDING + weight(0.) + 2. * weight(0.1) + weight(0.2);
+        assert!((densities[0] - expected).abs() < expected * 1e-4, "{} vs {expected}", densities[0]);
+        assert!((densities[0] - densities[2]).abs() < expected * 1e-4);
+        // Missing ids are the first fluid
+        assert!(fluid_props.get_densities(&points, &[], &fluid_types)[0] < densities[0]);
+    }
+
+    #[test]
+    fn pressure_uses_the_rest_density_of_each_fluid() {
+        let fluid_props = FluidStaticProps { smoothing_radius: 0.25, target_density: 10., pressure_scalar: 2., ..default() };
+        let fluid_types = FluidTypes::default();
+        let points = [Vec3::ZERO, Vec3::X * 0.1, Vec3::X * 0.2];
+        let fluid_ids = [0, 1, 0];
+        let densities = fluid_props.get_densities(&points, &fluid_ids, &fluid_types);
+        let pressures = fluid_props.get_pressures(&points, &fluid_ids, &fluid_types);
+        for (it, &fluid_id) in fluid_ids.iter().enumerate() {
+            let target_density = fluid_props.target_density * fluid_types.items[fluid_id as usize].target_density;
+            let expected = fluid_props.pressure_scalar * (densities[it] - target_density);
+            assert!((pressures[it] - expected).abs() < 1e-3, "{it}: {} vs {expected}", pressures[it]);
+        }
+        // Twice the rest density of the first fluid, so the second one is further below its own
+        let first_rest = fluid_props.get_pressure(densities[1], fluid_types.get(0), false);
+        assert!((first_rest - pressures[1] - fluid_props.pressure_scalar * fluid_props.target_density).abs() < 1e-3);
+        // Out of range ids are the last fluid, like the shader clamps them
+        assert_eq!(fluid_props.get_pressures(&points, &[0, 5, 0], &fluid_types), pressures);
+    }
+
+    #[test]
+    fn walls_only_push() {
+        let fluid_props = FluidStaticProps { target_density: 10., pressure_scalar: 2., ..default() };
+        let fluid_type = FluidTypes::default().items[0];
+        assert_eq!(fluid_props.get_pressure(4., &fluid_type, true), 0.);
+        assert!(fluid_props.get_pressure(4., &fluid_type, false) < 0.);
+        assert_eq!(fluid_props.get_pressure(15., &fluid_type, true), fluid_props.get_pressure(15., &fluid_type, false));
+    }
+
+    #[test]
+    fn cohesion_force_points_toward_the_neighbour() {
+        let fluid_props = FluidStaticProps { cohesion_strength: 2., ..make_cohesion_props() };
+        let origin = Vec3::new(1., -2., 0.5);
+        let offset = Vec3::new(1., 2., -2.).normalize() * 0.6 * fluid_props.smoothing_radius;
+        let force = fluid_props.get_cohesion_force(origin, origin + offset);
+        assert!(force.length() > 0.);
+        assert!(force.normalize().dot(offset.normalize()) > 1. - 1e-5, "{force}");
+        // Equal and opposite on the neighbour
+        assert_close(fluid_props.get_cohesion_force(origin + offset, origin), -force);
+        // Scales with the strength
+        let weaker = FluidStaticProps { cohesion_strength: 1., ..fluid_props.clone() };
+        assert_close(weaker.get_cohesion_force(origin, origin + offset) * 2., force);
+    }
+
+    #[test]
+    fn cohesion_force_vanishes_at_and_beyond_the_radius() {
+        let fluid_props = FluidStaticProps { cohesion_strength: 2., ..make_cohesion_props() };
+        let origin = Vec3::new(1., -2., 0.5);
+        for fraction in [1., 1.01, 2.] {
+            let neighbour = origin + Vec3::Y * fraction * fluid_props.smoothing_radius;
+            assert_eq!(fluid_props.get_cohesion_force(origin, neighbour), Vec3::ZERO, "{fraction}");
+        }
+        assert_eq!(fluid_props.get_cohesion_force(origin, origin), Vec3::ZERO);
+        // Off when the strength is
+        let off = FluidStaticProps { cohesion_strength: 0., ..fluid_props };
+        assert_eq!(off.get_cohesion_force(origin, origin + Vec3::Y * 0.2), Vec3::ZERO);
+    }
+
+    #[test]
+    fn substeps_bound_the_distance_per_substep() {
+        let cfl = CflSettings { enabled: true, ..default() };
+        let fluid_props = FluidStaticProps { smoothing_radius: 0.25, delta_time: 0.01, ..default() };
+        // Up to 0.4 of the radius per substep, a speed of 10 over the whole step
+        assert_eq!(cfl.get_substeps(0., &fluid_props), 1);
+        assert_eq!(cfl.get_substeps(9., &fluid_props), 1);
+        assert_eq!(cfl.get_substeps(15., &fluid_props), 2);
+        assert_eq!(cfl.get_substeps(25., &fluid_props), 3);
+        for max_speed in [1., 12., 33., 47.5] {
+            let expected = (max_speed * 0.01 / (CFL_COURANT_NUMBER * 0.25)).ceil() as u32;
+            assert_eq!(cfl.get_substeps(max_speed, &fluid_props), expected.max(1), "{max_speed}");
+        }
+    }
+
+    #[test]
+    fn substeps_are_capped() {
+        let cfl = CflSettings { enabled: true, ..default() };
+        let fluid_props = FluidStaticProps { smoothing_radius: 0.25, delta_time: 0.01, ..default() };
+        assert_eq!(cfl.get_substeps(1000., &fluid_props), CFL_MAX_SUBSTEPS);
+        assert_eq!(CFL_MAX_SUBSTEPS, 8);
+        assert_eq!(cfl.get_substeps(f32::INFINITY, &fluid_props), 1);
+        assert_eq!(cfl.get_substeps(f32::NAN, &fluid_props), 1);
+    }
+
+    #[test]
+    fn disabled_cfl_takes_a_single_substep() {
+        let cfl = CflSettings { enabled: false, ..default() };
+        let fluid_props = FluidStaticProps { smoothing_radius: 0.25, delta_time: 0.01, ..default() };
+        assert_eq!(cfl.get_substeps(1000., &fluid_props), 1);
+        assert_eq!(cfl.get_substeps(15., &fluid_props), 1);
+    }
 }
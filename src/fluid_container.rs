@@ -4,10 +4,25 @@ use bevy_app_compute::prelude::*;
 use bytemuck::Zeroable;
 
 use crate::schedule::InGameSet;
+use crate::field::Theme;
 
 const FLUID_CONTAINER_SIZE: Vec3 = Vec3::new(16., 9., 9.);
 const FLUID_CONTAINER_POSITION: Vec3 = Vec3::ZERO;
 const FLUID_CONTAINER_ROTATOR_RADIUS: f32 = 2.;
+const FLUID_CONTAINER_WALL_MARGIN: f32 = 0.1;
+const FLUID_CONTAINER_TRAMPOLINE_RESTITUTION: f32 = 1.2;
+const FLUID_CONTAINER_MAX_RESTITUTION: f32 = 1.5;  // Anything above diverges within a few bounces
+const FLUID_CONTAINER_MAX_BOUNCE_SPEED: f32 = 12.;
+const FLUID_CONTAINER_TRAMPOLINE_TOGGLE_KEY: KeyCode = KeyCode::KeyT;
+const FLUID_CONTAINER_RENDER_MODE_KEY: KeyCode = KeyCode::KeyG;
+const FLUID_CONTAINER_GIZMO_TOGGLE_KEY: KeyCode = KeyCode::F2;  // G already switches the render mode
+const FLUID_CONTAINER_ROTATE_LEFT_KEY: KeyCode = KeyCode::KeyJ;
+const FLUID_CONTAINER_ROTATE_RIGHT_KEY: KeyCode = KeyCode::KeyL;
+const FLUID_CONTAINER_BOUNDARY_MODE_KEY: KeyCode = KeyCode::Slash;
+const FLUID_CONTAINER_FLOOR_FRICTION_KEY: KeyCode = KeyCode::End;
+const FLUID_CONTAINER_FLOOR_FRICTIONS: [f32; 4] = [0., 0.1, 0.5, 1.];  // Cycled by the key
+const FLUID_CONTAINER_ROTATION_SPEED: f32 = 0.5;  // Radians per second around Z
+const FLUID_CONTAINER_BASIN_COLOR: Color = Color::rgba(0.8, 0.9, 1., 0.15);
 
 
 #[derive(Default, Reflect, GizmoConfigGroup)]
@@ -19,6 +34,114 @@ pub struct FluidContainerGizmo;
 pub struct FluidContainerExt {
     pub ext_min: Vec4,
     pub ext_max: Vec4,
+    /// Per axis restitution of the lower walls
+    pub restitution_min: Vec4,
+    /// Per axis restitution of the upper walls
+    pub restitution_max: Vec4,
+    /// Per axis fraction of the tangential velocity the lower walls take on contact
+    pub friction_min: Vec4,
+    /// Per axis fraction of the tangential velocity the upper walls take on contact
+    pub friction_max: Vec4,
+    /// Outgoing speed a restitution above 1 can boost a bounce up to, in x
+    pub bounce_limit: Vec4,
+    /// One on the axes whose walls wrap around instead of bouncing
+    pub wrap: Vec4,
+    /// Pivot of the rotation, the extents above are in the unrotated frame around it
+    pub center: Vec4,
+    pub rotation: Mat4,
+}
+
+
+impl FluidContainerExt {
+    /// Outgoing speed away from a wall, restitution above 1 only boosts up to the bounce limit.
+    /// Mirrors `get_bounce_speed` in the shader.
+    pub fn get_bounce_speed(&self, speed: f32, restitution: f32) -> f32 {
+        let outgoing = speed.abs() * restitution;
+        if restitution > 1. {
+            return outgoing.min(speed.abs().max(self.bounce_limit.x));
+        }
+        outgoing
+    }
+
+    /// Wraps or bounces off the two walls of `axis`, in container space. Returns the impact speed.
+    /// Mirrors `collide_container_axis` in the shader.
+    pub fn collide_axis(&self, axis: usize, position: &mut Vec3, velocity: &mut Vec3) -> f32 {
+        let (ext_min, ext_max) = (self.ext_min[axis], self.ext_max[axis]);
+        let normal = Vec3::AXES[axis];
+        if self.wrap[axis] != 0. {
+            let size = ext_max - ext_min;
+            if position[axis] < ext_min {
+                position[axis] += size;
+            } else if position[axis] > ext_max {
+                position[axis] -= size;
+            }
+            return 0.;
+        }
+
+        let impact = velocity[axis].abs();
+        if position[axis] < ext_min {
+            velocity[axis] = self.get_bounce_speed(velocity[axis], self.restitution_min[axis]);
+            *velocity = get_wall_friction_velocity(*velocity, normal, self.friction_min[axis]);
+            position[axis] = ext_min;
+        } else if position[axis] > ext_max {
+            velocity[axis] = -self.get_bounce_speed(velocity[axis], self.restitution_max[axis]);
+            *velocity = get_wall_friction_velocity(*velocity, normal, self.friction_max[axis]);
+            position[axis] = ext_max;
+        } else {
+            return 0.;
+        }
+        impact
+    }
+
+    /// Resolves the wall collisions of a world space particle, rotated into container space where the walls
+    /// are axis aligned and back. Returns the impact speed. Mirrors `collide_container` in the shader.
+    pub fn collide(&self, position: &mut Vec3, velocity: &mut Vec3) -> f32 {
+        let rotation = Mat3::from_mat4(self.rotation);
+        let inverse_rotation = rotation.transpose();
+        let center = self.center.xyz();
+        let mut local = center + inverse_rotation * (*position - center);
+        let mut local_velocity = inverse_rotation * *velocity;
+
+        let impact = (0..3).map(|axis| self.collide_axis(axis, &mut local, &mut local_velocity)).sum();
+
+        *position = center + rotation * (local - center);
+        *velocity = rotation * local_velocity;
+        impact
+    }
+}
+
+
+/// Takes `friction` of the velocity along a wall, `axis` is the wall normal.
+/// Mirrors `get_wall_friction_velocity` in the shader.
+pub fn get_wall_friction_velocity(velocity: Vec3, axis: Vec3, friction: f32) -> Vec3 {
+    let normal_velocity = axis * velocity.dot(axis);
+    normal_velocity + (velocity - normal_velocity) * (1. - friction)
+}
+
+
+/// Per wall bounce and friction, the front and back walls keep the collision damping
+#[derive(Resource, Clone, Copy, Default, Debug)]
+pub struct WallProps {
+    /// Restitution of each wall, `None` falls back to the collision damping
+    pub left: Option<f32>,
+    pub right: Option<f32>,
+    pub bottom: Option<f32>,
+    pub top: Option<f32>,
+    /// Fraction of the velocity along the floor removed on contact, 1 makes a sticky floor
+    pub floor_friction: f32,
+    /// Same for the side walls and the ceiling
+    pub wall_friction: f32,
+}
+
+
+/// What the side walls do to the particles reaching them
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum BoundaryMode {
+    #[default]
+    Reflect,
+    /// Particles leaving through a side wall come back in through the opposite one with the same velocity,
+    /// the floor and the ceiling still bounce. The neighbour search stays local, so there is a seam
+    Wrap,
 }
 
 
@@ -26,6 +149,17 @@ pub struct FluidContainerExt {
 pub struct FluidContainer {
     pub position: Vec3,
     pub size: Vec3,
+    /// Orientation around the position, the mirrored simulation keeps the container upright
+    pub rotation: Quat,
+    /// Distance kept between the particle centers and the container walls
+    pub wall_margin: f32,
+    /// Simulate only the half below the center on X and mirror it, for left-right symmetric setups
+    pub mirror_x: bool,
+    /// Floor restitution overriding the collision damping, may exceed 1 for a trampoline
+    pub floor_restitution: Option<f32>,
+    /// Speed a bounce off the trampoline stops gaining energy at
+    pub max_bounce_speed: f32,
+    pub boundary_mode: BoundaryMode,
 }
 
 
@@ -34,23 +168,119 @@ impl Default for FluidContainer {
         Self {
             position: FLUID_CONTAINER_POSITION,
             size: FLUID_CONTAINER_SIZE,
+            rotation: Quat::IDENTITY,
+            wall_margin: FLUID_CONTAINER_WALL_MARGIN,
+            mirror_x: false,
+            floor_restitution: None,
+            max_bounce_speed: FLUID_CONTAINER_MAX_BOUNCE_SPEED,
+            boundary_mode: BoundaryMode::default(),
         }
     }
 }
 
 impl FluidContainer {
-    pub fn get_ext(&self, padding: f32) -> FluidContainerExt {
+    /// Wall planes in the unrotated frame, `position ± size / 2`
+    pub fn get_extents(&self) -> (Vec3, Vec3) {
         let half_size = self.size / 2.;
-        let ext_min = (self.position - half_size + padding).extend(0.);
-        let ext_max = (self.position + half_size - padding).extend(0.);
+        (self.position - half_size, self.position + half_size)
+    }
+
+    /// Extents shrunk by `padding` on every side, as uploaded to the shaders
+    pub fn get_ext(&self, padding: f32) -> FluidContainerExt {
+        let (extents_min, extents_max) = self.get_extents();
+        let ext_min = (extents_min + padding).extend(0.);
+        let ext_max = (extents_max - padding).extend(0.);
         FluidContainerExt {
             ext_min,
             ext_max,
+            restitution_min: Vec4::ZERO,
+            restitution_max: Vec4::ZERO,
+            friction_min: Vec4::ZERO,
+            friction_max: Vec4::ZERO,
+            bounce_limit: Vec4::ZERO,
+            wrap: Vec4::ZERO,
+            center: self.position.extend(0.),
+            rotation: Mat4::from_quat(self.rotation),
+        }
+    }
+
+    /// Axis aligned extents enclosing the rotated container
+    pub fn get_bounding_ext(&self, padding: f32) -> FluidContainerExt {
+        let half_size = Mat3::from_quat(self.rotation).abs() * (self.size / 2. - padding).max(Vec3::ZERO);
+        let mut ext = self.get_ext(padding);
+        ext.ext_min = (self.position - half_size).extend(0.);
+        ext.ext_max = (self.position + half_size).extend(0.);
+        ext
+    }
+
+    pub fn get_transform(&self) -> Transform {
+        Transform::from_translation(self.position)
+            .with_rotation(self.rotation)
+            .with_scale(self.size)
+    }
+
+    /// Extents the particles are kept in, the mirror plane replaces the upper X wall.
+    /// Walls bounce with `collision_damping` unless overridden by `walls` or the trampoline
+    pub fn get_simulation_ext(&self, collision_damping: f32, walls: &WallProps) -> FluidContainerExt {
+        let mut ext = self.get_ext(self.wall_margin);
+        let restitution = |wall: Option<f32>| wall.unwrap_or(collision_damping).clamp(0., FLUID_CONTAINER_MAX_RESTITUTION);
+        ext.restitution_min = Vec4::new(restitution(walls.left), restitution(walls.bottom), collision_damping, 0.);
+        ext.restitution_max = Vec4::new(restitution(walls.right), restitution(walls.top), collision_damping, 0.);
+        let wall_friction = walls.wall_friction.clamp(0., 1.);
+        ext.friction_min = Vec4::new(wall_friction, walls.floor_friction.clamp(0., 1.), wall_friction, 0.);
+        ext.friction_max = Vec4::new(wall_friction, wall_friction, wall_friction, 0.);
+        if let Some(restitution) = self.floor_restitution {
+            ext.restitution_min.y = restitution.clamp(0., FLUID_CONTAINER_MAX_RESTITUTION);
+        }
+        ext.bounce_limit.x = self.max_bounce_speed;
+        if self.boundary_mode == BoundaryMode::Wrap {
+            ext.wrap = Vec4::new(1., 0., 1., 0.);
         }
+        if self.mirror_x {
+            ext.rotation = Mat4::IDENTITY;
+            ext.ext_max.x = self.position.x;
+            // The mirror plane can't be wrapped through
+            ext.wrap.x = 0.;
+        }
+        ext
+    }
+
+    /// Drops the points the mirror stands in for, warns when the spawn is not symmetric
+    pub fn retain_simulated(&self, points: &mut Vec<Vec3>) {
+        if !self.mirror_x {
+            return;
+        }
+        let center = self.position.x;
+        let num_lower = points.iter().filter(|point| point.x < center).count();
+        let num_upper = points.iter().filter(|point| point.x > center).count();
+        if num_lower != num_upper {
+            println!(
+                "Mirrored simulation: the spawn is not symmetric around x = {} ({} vs {} particles)",
+                center, num_lower, num_upper,
+            );
+        }
+        points.retain(|point| point.x < center);
+    }
+
+    pub fn mirror_position(&self, position: Vec3) -> Vec3 {
+        Vec3::new(2. * self.position.x - position.x, position.y, position.z)
     }
 }
 
 
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum RenderMode {
+    #[default]
+    Wireframe,
+    /// Semi-transparent floor and walls, open at the top
+    Basin,
+}
+
+
+#[derive(Component)]
+struct FluidContainerBasin;
+
+
 #[derive(Resource, Clone)]
 pub struct FluidContainerRotator {
     pub position: Vec3,
@@ -76,9 +306,19 @@ impl Plugin for GizmoPlugin {
         app
             .init_gizmo_group::<FluidContainerGizmo>()
             .init_resource::<FluidContainer>()
+            .init_resource::<WallProps>()
             .init_resource::<FluidContainerRotator>()
-            .add_systems(Startup, setup_gizmo_config)
-            .add_systems(Update, draw_gizmos.in_set(InGameSet::EntityUpdates));
+            .init_resource::<RenderMode>()
+            .add_systems(Startup, (setup_gizmo_config, setup_basin))
+            .add_systems(Update, (
+                toggle_trampoline,
+                toggle_boundary_mode,
+                cycle_floor_friction,
+                toggle_render_mode,
+                toggle_gizmos,
+                rotate_container,
+            ).in_set(InGameSet::UserInput))
+            .add_systems(Update, (draw_gizmos, update_basin).in_set(InGameSet::EntityUpdates));
     }
 }
 
@@ -90,14 +330,405 @@ fn setup_gizmo_config(mut config_store: ResMut<GizmoConfigStore>) {
 }
 
 
+/// Unit cube walls, scaled to the container size by the parent
+fn setup_basin(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    container: Res<FluidContainer>,
+    render_mode: Res<RenderMode>,
+) {
+    let mesh = meshes.add(Rectangle::new(1., 1.));
+    let material = materials.add(StandardMaterial {
+        base_color: FLUID_CONTAINER_BASIN_COLOR,
+        alpha_mode: AlphaMode::Blend,
+        cull_mode: None,
+        double_sided: true,
+        ..default()
+    });
+    let walls = [
+        Transform::from_xyz(0., -0.5, 0.).with_rotation(Quat::from_rotation_x(-std::f32::consts::FRAC_PI_2)),
+        Transform::from_xyz(-0.5, 0., 0.).with_rotation(Quat::from_rotation_y(std::f32::consts::FRAC_PI_2)),
+        Transform::from_xyz(0.5, 0., 0.).with_rotation(Quat::from_rotation_y(-std::f32::consts::FRAC_PI_2)),
+        Transform::from_xyz(0., 0., -0.5),
+        Transform::from_xyz(0., 0., 0.5).with_rotation(Quat::from_rotation_y(std::f32::consts::PI)),
+    ];
+
+    commands
+        .spawn((
+            SpatialBundle {
+                transform: container.get_transform(),
+                visibility: get_basin_visibility(*render_mode),
+                ..default()
+            },
+            FluidContainerBasin,
+        ))
+        .with_children(|parent| {
+            for transform in walls {
+                parent.spawn(PbrBundle {
+                    mesh: mesh.clone(),
+                    material: material.clone(),
+                    transform,
+                    ..default()
+                });
+            }
+        });
+}
+
+
+fn get_basin_visibility(render_mode: RenderMode) -> Visibility {
+    match render_mode {
+        RenderMode::Wireframe => Visibility::Hidden,
+        RenderMode::Basin => Visibility::Inherited,
+    }
+}
+
+
+fn update_basin(
+    mut query: Query<(&mut Transform, &mut Visibility), With<FluidContainerBasin>>,
+    container: Res<FluidContainer>,
+    render_mode: Res<RenderMode>,
+) {
+    if !container.is_changed() && !render_mode.is_changed() {
+        return;
+    }
+    let Ok((mut transform, mut visibility)) = query.get_single_mut() else {
+        return;
+    };
+    *transform = container.get_transform();
+    *visibility = get_basin_visibility(*render_mode);
+}
+
+
+fn toggle_render_mode(mut render_mode: ResMut<RenderMode>, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if !keyboard_input.just_pressed(FLUID_CONTAINER_RENDER_MODE_KEY) {
+        return;
+    }
+    *render_mode = match *render_mode {
+        RenderMode::Wireframe => RenderMode::Basin,
+        RenderMode::Basin => RenderMode::Wireframe,
+    };
+}
+
+
+fn toggle_gizmos(mut config_store: ResMut<GizmoConfigStore>, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if keyboard_input.just_pressed(FLUID_CONTAINER_GIZMO_TOGGLE_KEY) {
+        let (config, _) = config_store.config_mut::<FluidContainerGizmo>();
+        config.enabled = !config.enabled;
+    }
+}
+
+
+fn rotate_container(
+    mut container: ResMut<FluidContainer>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+) {
+    let mut direction = 0.;
+    if keyboard_input.pressed(FLUID_CONTAINER_ROTATE_LEFT_KEY) {
+        direction += 1.;
+    }
+    if keyboard_input.pressed(FLUID_CONTAINER_ROTATE_RIGHT_KEY) {
+        direction -= 1.;
+    }
+    if direction == 0. {
+        return;
+    }
+    let angle = direction * FLUID_CONTAINER_ROTATION_SPEED * time.delta_seconds();
+    container.rotation = (Quat::from_rotation_z(angle) * container.rotation).normalize();
+}
+
+
+fn toggle_trampoline(mut container: ResMut<FluidContainer>, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if !keyboard_input.just_pressed(FLUID_CONTAINER_TRAMPOLINE_TOGGLE_KEY) {
+        return;
+    }
+    container.floor_restitution = match container.floor_restitution {
+        Some(_) => None,
+        None => Some(FLUID_CONTAINER_TRAMPOLINE_RESTITUTION),
+    };
+}
+
+
+fn toggle_boundary_mode(mut container: ResMut<FluidContainer>, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if !keyboard_input.just_pressed(FLUID_CONTAINER_BOUNDARY_MODE_KEY) {
+        return;
+    }
+    container.boundary_mode = match container.boundary_mode {
+        BoundaryMode::Reflect => BoundaryMode::Wrap,
+        BoundaryMode::Wrap => BoundaryMode::Reflect,
+    };
+}
+
+
+fn cycle_floor_friction(mut walls: ResMut<WallProps>, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if !keyboard_input.just_pressed(FLUID_CONTAINER_FLOOR_FRICTION_KEY) {
+        return;
+    }
+    walls.floor_friction = get_next_floor_friction(walls.floor_friction);
+}
+
+
+/// Preset after the current friction, clamped to [0, 1] and counted as the first preset at or above it
+fn get_next_floor_friction(floor_friction: f32) -> f32 {
+    let floor_friction = floor_friction.clamp(0., 1.);
+    let index = FLUID_CONTAINER_FLOOR_FRICTIONS.iter()
+        .position(|friction| *friction >= floor_friction)
+        .unwrap_or(0);
+    FLUID_CONTAINER_FLOOR_FRICTIONS[(index + 1) % FLUID_CONTAINER_FLOOR_FRICTIONS.len()]
+}
+
+
 fn draw_gizmos(
     mut fluid_container_gizmos: Gizmos<FluidContainerGizmo>,
     container: Res<FluidContainer>,
     rotator: Res<FluidContainerRotator>,
+    render_mode: Res<RenderMode>,
+    theme: Res<Theme>,
 ) {
-    let transform = Transform::from_translation(container.position).with_scale(container.size);
-    fluid_container_gizmos.cuboid(transform, Color::WHITE);
+    if *render_mode == RenderMode::Wireframe {
+        fluid_container_gizmos.cuboid(container.get_transform(), theme.container_line);
+    }
     fluid_container_gizmos.circle(rotator.position, Direction3d::X, rotator.radius, Color::RED);
     fluid_container_gizmos.circle(rotator.position, Direction3d::Y, rotator.radius, Color::GREEN);
     fluid_container_gizmos.circle(rotator.position, Direction3d::Z, rotator.radius, Color::BLUE);
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_container() -> FluidContainer {
+        FluidContainer {
+            position: Vec3::new(1., -2., 3.),
+            size: Vec3::new(4., 2., 6.),
+            ..default()
+        }
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-5, "{a} != {b}");
+    }
+
+    #[test]
+    fn extents_are_the_half_size_around_the_position() {
+        let (ext_min, ext_max) = make_container().get_extents();
+        assert_close(ext_min, Vec3::new(-1., -3., 0.));
+        assert_close(ext_max, Vec3::new(3., -1., 6.));
+    }
+
+    #[test]
+    fn padding_shrinks_every_side() {
+        let ext = make_container().get_ext(0.25);
+        assert_close(ext.ext_min.xyz(), Vec3::new(-0.75, -2.75, 0.25));
+        assert_close(ext.ext_max.xyz(), Vec3::new(2.75, -1.25, 5.75));
+        assert_close(ext.center.xyz(), Vec3::new(1., -2., 3.));
+    }
+
+    #[test]
+    fn bounding_box_swaps_axes_at_a_right_angle() {
+        let container = FluidContainer {
+            rotation: Quat::from_rotation_z(std::f32::consts::FRAC_PI_2),
+            ..make_container()
+        };
+        let ext = container.get_bounding_ext(0.);
+        assert_close(ext.ext_min.xyz(), Vec3::new(0., -4., 0.));
+        assert_close(ext.ext_max.xyz(), Vec3::new(2., 0., 6.));
+        // The padding applies before the rotation
+        let ext = container.get_bounding_ext(0.5);
+        assert_close(ext.ext_min.xyz(), Vec3::new(0.5, -3.5, 0.5));
+        assert_close(ext.ext_max.xyz(), Vec3::new(1.5, -0.5, 5.5));
+    }
+
+    #[test]
+    fn bounding_box_encloses_the_rotated_corners() {
+        let container = FluidContainer {
+            rotation: Quat::from_rotation_z(std::f32::consts::FRAC_PI_4) * Quat::from_rotation_x(0.3),
+            ..make_container()
+        };
+        let ext = container.get_bounding_ext(0.);
+        let half_size = container.size / 2.;
+        for i in 0..8 {
+            let sign = Vec3::new(
+                if i & 1 == 0 { -1. } else { 1. },
+                if i & 2 == 0 { -1. } else { 1. },
+                if i & 4 == 0 { -1. } else { 1. },
+            );
+            let corner = container.position + container.rotation * (sign * half_size);
+            assert!(corner.cmpge(ext.ext_min.xyz() - 1e-4).all(), "{corner}");
+            assert!(corner.cmple(ext.ext_max.xyz() + 1e-4).all(), "{corner}");
+        }
+    }
+
+    #[test]
+    fn mirrored_simulation_drops_the_rotation() {
+        let container = FluidContainer {
+            rotation: Quat::from_rotation_z(std::f32::consts::FRAC_PI_4),
+            mirror_x: true,
+            ..make_container()
+        };
+        let ext = container.get_simulation_ext(0.5, &WallProps::default());
+        assert_eq!(ext.rotation, Mat4::IDENTITY);
+        assert_eq!(ext.ext_max.x, container.position.x);
+        let ext = FluidContainer { mirror_x: false, ..container.clone() }.get_simulation_ext(0.5, &WallProps::default());
+        assert_eq!(ext.rotation, Mat4::from_quat(container.rotation));
+    }
+
+    #[test]
+    fn wrap_mode_wraps_x_and_z() {
+        let container = FluidContainer { boundary_mode: BoundaryMode::Wrap, ..make_container() };
+        let ext = container.get_simulation_ext(0.5, &WallProps::default());
+        assert_eq!(ext.wrap, Vec4::new(1., 0., 1., 0.));
+        let ext = make_container().get_simulation_ext(0.5, &WallProps::default());
+        assert_eq!(ext.wrap, Vec4::ZERO);
+    }
+
+    #[test]
+    fn mirror_plane_is_not_wrapped() {
+        let container = FluidContainer {
+            boundary_mode: BoundaryMode::Wrap,
+            mirror_x: true,
+            ..make_container()
+        };
+        let ext = container.get_simulation_ext(0.5, &WallProps::default());
+        assert_eq!(ext.wrap, Vec4::new(0., 0., 1., 0.));
+    }
+
+    #[test]
+    fn floor_friction_cycles_through_the_presets() {
+        let mut friction = 0.;
+        let mut seen = Vec::new();
+        for _ in 0..FLUID_CONTAINER_FLOOR_FRICTIONS.len() {
+            friction = get_next_floor_friction(friction);
+            seen.push(friction);
+        }
+        assert_eq!(seen, [0.1, 0.5, 1., 0.]);
+    }
+
+    #[test]
+    fn floor_friction_out_of_range_restarts_the_cycle() {
+        assert_eq!(get_next_floor_friction(3.), 0.);
+        assert_eq!(get_next_floor_friction(-1.), 0.1);
+    }
+
+    #[test]
+    fn floor_friction_is_clamped() {
+        let container = make_container();
+        let walls = WallProps { floor_friction: 2., wall_friction: -1., ..default() };
+        let ext = container.get_simulation_ext(0.5, &walls);
+        assert_eq!(ext.friction_min.y, 1.);
+        assert_eq!(ext.friction_min.x, 0.);
+        let walls = WallProps { floor_friction: -0.5, ..default() };
+        assert_eq!(container.get_simulation_ext(0.5, &walls).friction_min.y, 0.);
+    }
+
+    fn make_wrapped_ext() -> FluidContainerExt {
+        FluidContainer { boundary_mode: BoundaryMode::Wrap, ..make_container() }.get_simulation_ext(0.5, &WallProps::default())
+    }
+
+    #[test]
+    fn wrapped_side_walls_bring_the_particle_back_on_the_other_side() {
+        let ext = make_wrapped_ext();
+        let velocity = Vec3::new(3., -1., 2.);
+        for axis in [0, 2] {
+            let (ext_min, ext_max) = (ext.ext_min[axis], ext.ext_max[axis]);
+            let overshoot = 0.05;
+
+            // Past the upper wall, in again near the lower one with the same velocity
+            let mut position = Vec3::new(1., -2., 3.);
+            position[axis] = ext_max + overshoot;
+            let mut wrapped_velocity = velocity;
+            assert_eq!(ext.collide_axis(axis, &mut position, &mut wrapped_velocity), 0.);
+            assert!((position[axis] - (ext_min + overshoot)).abs() < 1e-5, "{axis}: {position}");
+            assert_eq!(wrapped_velocity, velocity);
+
+            // And the other way around
+            position[axis] = ext_min - overshoot;
+            ext.collide_axis(axis, &mut position, &mut wrapped_velocity);
+            assert!((position[axis] - (ext_max - overshoot)).abs() < 1e-5, "{axis}: {position}");
+
+            // Inside is left alone
+            let mut inside = Vec3::new(1., -2., 3.);
+            ext.collide_axis(axis, &mut inside, &mut wrapped_velocity);
+            assert_eq!(inside, Vec3::new(1., -2., 3.));
+        }
+    }
+
+    #[test]
+    fn wrapped_floor_still_bounces() {
+        let ext = make_wrapped_ext();
+        let mut position = Vec3::new(1., ext.ext_min.y - 0.05, 3.);
+        let mut velocity = Vec3::new(0., -4., 0.);
+        assert_eq!(ext.collide_axis(1, &mut position, &mut velocity), 4.);
+        assert_eq!(position.y, ext.ext_min.y);
+        assert!(velocity.y > 0.);
+    }
+
+    #[test]
+    fn wall_friction_scales_the_tangential_velocity_only() {
+        let velocity = Vec3::new(2., -3., 4.);
+        let along = get_wall_friction_velocity(velocity, Vec3::Y, 0.25);
+        assert_close(along, Vec3::new(1.5, -3., 3.));
+        // A sticky wall keeps the normal component only, a slippery one everything
+        assert_close(get_wall_friction_velocity(velocity, Vec3::X, 1.), Vec3::new(2., 0., 0.));
+        assert_close(get_wall_friction_velocity(velocity, Vec3::Z, 0.), velocity);
+    }
+
+    #[test]
+    fn floor_bounces_the_normal_and_rubs_the_tangential_velocity() {
+        let walls = WallProps { floor_friction: 0.25, ..default() };
+        let ext = make_container().get_simulation_ext(0.5, &walls);
+        let mut position = Vec3::new(1., ext.ext_min.y - 0.05, 3.);
+        let mut velocity = Vec3::new(2., -4., -1.);
+        ext.collide_axis(1, &mut position, &mut velocity);
+        assert_close(velocity, Vec3::new(2. * 0.75, 4. * 0.5, -1. * 0.75));
+        // The ceiling keeps the wall friction, none by default
+        let mut position = Vec3::new(1., ext.ext_max.y + 0.05, 3.);
+        let mut velocity = Vec3::new(2., 4., -1.);
+        ext.collide_axis(1, &mut position, &mut velocity);
+        assert_close(velocity, Vec3::new(2., -4. * 0.5, -1.));
+    }
+
+    #[test]
+    fn trampoline_bounce_is_limited() {
+        let container = FluidContainer { floor_restitution: Some(1.2), max_bounce_speed: 12., ..make_container() };
+        let ext = container.get_simulation_ext(0.5, &WallProps::default());
+        // Boosted while below the limit, never above it, and never slowed down by it
+        assert!((ext.get_bounce_speed(-5., 1.2) - 6.).abs() < 1e-5);
+        assert_eq!(ext.get_bounce_speed(-11., 1.2), 12.);
+        assert_eq!(ext.get_bounce_speed(-20., 1.2), 20.);
+        assert_eq!(ext.get_bounce_speed(-20., 0.5), 10.);
+    }
+
+    #[test]
+    fn rotated_container_pushes_back_along_its_own_axes() {
+        let container = FluidContainer { rotation: Quat::from_rotation_z(std::f32::consts::FRAC_PI_4), ..make_container() };
+        let ext = container.get_simulation_ext(0.5, &WallProps::default());
+        let to_world = |local: Vec3| container.position + container.rotation * local;
+
+        // Past the local right wall, which is tilted 45 degrees in the world
+        let half_x = ext.ext_max.x - container.position.x;
+        let outside = to_world(Vec3::new(half_x + 0.3, 0.2, -0.5));
+        let mut position = outside;
+        let mut velocity = container.rotation * Vec3::new(4., 1., 0.);
+        let impact = ext.collide(&mut position, &mut velocity);
+        assert!((impact - 4.).abs() < 1e-5, "{impact}");
+        assert_close(position, to_world(Vec3::new(half_x, 0.2, -0.5)));
+        // Straight back along the local X axis, not the world one
+        let push = (position - outside).normalize();
+        assert!(push.dot(container.rotation * Vec3::NEG_X) > 1. - 1e-5, "{push}");
+        assert_close(velocity, container.rotation * Vec3::new(-2., 1., 0.));
+    }
+
+    #[test]
+    fn rotated_corner_is_clamped_on_every_axis() {
+        let container = FluidContainer { rotation: Quat::from_rotation_z(std::f32::consts::FRAC_PI_4), ..make_container() };
+        let ext = container.get_simulation_ext(0.5, &WallProps::default());
+        let mut position = container.position + container.rotation * Vec3::splat(-10.);
+        let mut velocity = Vec3::ZERO;
+        ext.collide(&mut position, &mut velocity);
+        let local = container.position + container.rotation.inverse() * (position - container.position);
+        assert_close(local, ext.ext_min.xyz());
+    }
+}
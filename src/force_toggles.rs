@@ -0,0 +1,78 @@
+use bevy::prelude::*;
+
+use crate::schedule::InGameSet;
+use crate::gravity::Gravity;
+use crate::fluid_compute::FluidStaticProps;
+
+
+/// Switches individual force contributions off without losing their tuned values
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct ForceToggles {
+    pub pressure: bool,
+    pub near_pressure: bool,
+    pub viscosity: bool,
+    pub gravity: bool,
+}
+
+
+impl Default for ForceToggles {
+    fn default() -> Self {
+        Self {
+            pressure: true,
+            near_pressure: true,
+            viscosity: true,
+            gravity: true,
+        }
+    }
+}
+
+
+impl ForceToggles {
+    /// Props as seen by the shader, disabled forces get a zero scalar
+    pub fn apply(&self, fluid_props: &FluidStaticProps) -> FluidStaticProps {
+        let mut fluid_props = *fluid_props;
+        if !self.pressure {
+            fluid_props.pressure_scalar = 0.;
+        }
+        if !self.near_pressure {
+            fluid_props.near_pressure_scalar = 0.;
+        }
+        if !self.viscosity {
+            fluid_props.viscosity_strength = 0.;
+        }
+        fluid_props
+    }
+
+    pub fn apply_gravity(&self, gravity: &Gravity) -> Gravity {
+        if self.gravity { *gravity } else { Gravity::new(Vec4::ZERO) }
+    }
+}
+
+
+pub struct ForceTogglesPlugin;
+
+
+impl Plugin for ForceTogglesPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<ForceToggles>()
+            .add_systems(Update, update_force_toggles.in_set(InGameSet::UserInput));
+    }
+}
+
+
+fn update_force_toggles(mut toggles: ResMut<ForceToggles>, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    // Shift + F6-F8 saves presets
+    if keyboard_input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        return;
+    }
+    if keyboard_input.just_pressed(KeyCode::F5) {
+        toggles.pressure = !toggles.pressure;
+    } else if keyboard_input.just_pressed(KeyCode::F6) {
+        toggles.near_pressure = !toggles.near_pressure;
+    } else if keyboard_input.just_pressed(KeyCode::F7) {
+        toggles.viscosity = !toggles.viscosity;
+    } else if keyboard_input.just_pressed(KeyCode::F8) {
+        toggles.gravity = !toggles.gravity;
+    }
+}
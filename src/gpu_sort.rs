@@ -0,0 +1,349 @@
+use bevy::prelude::*;
+use bevy::core::Pod;
+use bevy_app_compute::prelude::*;
+use bytemuck::Zeroable;
+
+use crate::compute_tuning::ComputeTuning;
+
+/// Value of the padding slots, sorts after every real cell index
+pub const SORT_SENTINEL: u32 = u32::MAX;
+const COUNTING_SORT_COUNTS: &str = "counting_sort_counts";
+const COUNTING_SORT_RANKS: &str = "counting_sort_ranks";
+
+
+#[derive(ShaderType, Pod, Zeroable, Clone, Copy)]
+#[repr(C)]
+pub struct BitSorter {
+    pub block: u32,
+    pub dim: u32,
+}
+
+
+impl BitSorter {
+    fn new(block: u32, dim: u32) -> Self {
+        Self {
+            block,
+            dim,
+        }
+    }
+}
+
+
+pub struct BitSorterStage {
+    pub bit_sorter: BitSorter,
+    pub workgroups: [u32; 3],
+    pub uniform_name: String,
+}
+
+
+#[derive(TypePath)]
+struct BitonicSortShader;
+
+
+impl ComputeShader for BitonicSortShader {
+    fn shader() -> ShaderRef {
+        "bitonic_sort.wgsl".into()
+    }
+
+    fn entry_point<'a>() -> &'a str {
+        "bitonic_sort"
+    }
+}
+
+
+#[derive(TypePath)]
+struct ClearCellCountsShader;
+
+
+impl ComputeShader for ClearCellCountsShader {
+    fn shader() -> ShaderRef {
+        "counting_sort.wgsl".into()
+    }
+
+    fn entry_point<'a>() -> &'a str {
+        "clear_cell_counts"
+    }
+}
+
+
+#[derive(TypePath)]
+struct CountCellsShader;
+
+
+impl ComputeShader for CountCellsShader {
+    fn shader() -> ShaderRef {
+        "counting_sort.wgsl".into()
+    }
+
+    fn entry_point<'a>() -> &'a str {
+        "count_cells"
+    }
+}
+
+
+#[derive(TypePath)]
+struct PrefixSumCellsShader;
+
+
+impl ComputeShader for PrefixSumCellsShader {
+    fn shader() -> ShaderRef {
+        "counting_sort.wgsl".into()
+    }
+
+    fn entry_point<'a>() -> &'a str {
+        "prefix_sum_cells"
+    }
+}
+
+
+#[derive(TypePath)]
+struct ScatterParticlesShader;
+
+
+impl ComputeShader for ScatterParticlesShader {
+    fn shader() -> ShaderRef {
+        "counting_sort.wgsl".into()
+    }
+
+    fn entry_point<'a>() -> &'a str {
+        "scatter_particles"
+    }
+}
+
+
+/// Length the key buffers are padded to, the bitonic network needs a power of two
+pub fn get_sort_length(data_length: u32) -> u32 {
+    match data_length.checked_next_power_of_two() {
+        Some(pot) => pot,
+        None => data_length,
+    }
+}
+
+
+/// One stage per (dim, block) pair of the bitonic network over `get_sort_length(data_length)`.
+/// Every stage gets its own uniform, named `{uniform_prefix}_{id}`.
+pub fn get_bit_sorter_stages(data_length: u32, batch_size: u32, uniform_prefix: &str) -> Vec<BitSorterStage> {
+    let input_length = get_sort_length(data_length);
+    let mut uniform_id = 1;
+    let mut dim = 2;
+    let mut block_stages = Vec::new();
+    while dim <= input_length {
+        let mut block = dim >> 1;
+        while block > 0 {
+            block_stages.push(BitSorterStage {
+                bit_sorter: BitSorter::new(block, dim),
+                workgroups: [batch_size, 1, 1],
+                uniform_name: format!("{}_{}", uniform_prefix, uniform_id),
+            });
+            block >>= 1;
+            uniform_id += 1;
+        }
+        dim <<= 1;
+    }
+    return block_stages;
+}
+
+
+/// Adds the passes sorting the `keys` buffer by `values[key]`, ascending.
+/// `length` names the `u32` uniform holding the padded number of keys, see `get_sort_length`.
+/// Padding keys must map to `SORT_SENTINEL` so they end up behind the real ones.
+pub fn add_bitonic_sort_passes<W: ComputeWorker>(
+    builder: &mut AppComputeWorkerBuilder<W>,
+    stages: &[BitSorterStage],
+    length: &str,
+    keys: &str,
+    values: &str,
+) {
+    for stage in stages {
+        builder.add_uniform(&stage.uniform_name, &stage.bit_sorter)
+            .add_pass::<BitonicSortShader>(stage.workgroups, &[
+                length,
+                keys,
+                values,
+                &stage.uniform_name,
+            ]);
+    }
+}
+
+
+/// Adds the passes ordering the `keys` buffer by `values[key]` like `add_bitonic_sort_passes`, but with
+/// per-value counts and a prefix sum, in four passes whatever the length. `keys` and `values` hold
+/// `sort_length` entries. Values from `num_values` up, the padding sentinel included, share a last
+/// bucket behind the others. Returns the pass names and workgroups, in dispatch order.
+pub fn add_counting_sort_passes<W: ComputeWorker>(
+    builder: &mut AppComputeWorkerBuilder<W>,
+    tuning: &ComputeTuning,
+    sort_length: u32,
+    num_values: u32,
+    keys: &str,
+    values: &str,
+) -> Vec<(&'static str, [u32; 3])> {
+    let num_buckets = num_values + 1;
+    let passes = vec![
+        ("clear_cell_counts", [tuning.get_batch_size(num_buckets), 1, 1]),
+        ("count_cells", [tuning.get_batch_size(sort_length), 1, 1]),
+        // A single workgroup walks the buckets in chunks
+        ("prefix_sum_cells", [1, 1, 1]),
+        ("scatter_particles", [tuning.get_batch_size(sort_length), 1, 1]),
+    ];
+
+    builder
+        .add_storage(COUNTING_SORT_COUNTS, &vec![0u32; num_buckets as usize])
+        .add_storage(COUNTING_SORT_RANKS, &vec![0u32; sort_length as usize])
+        .add_pass::<ClearCellCountsShader>(passes[0].1, &[COUNTING_SORT_COUNTS])
+        .add_pass::<CountCellsShader>(passes[1].1, &[values, COUNTING_SORT_COUNTS, COUNTING_SORT_RANKS])
+        .add_pass::<PrefixSumCellsShader>(passes[2].1, &[COUNTING_SORT_COUNTS])
+        .add_pass::<ScatterParticlesShader>(passes[3].1, &[values, COUNTING_SORT_COUNTS, COUNTING_SORT_RANKS, keys]);
+    passes
+}
+
+
+/// CPU mirror of the `bitonic_sort` shader, applies the stages one after another
+#[cfg(test)]
+fn sort_cpu(stages: &[BitSorterStage], keys: &mut [u32], values: &[u32]) {
+    let data_length = keys.len();
+    for stage in stages {
+        let BitSorter { block, dim } = stage.bit_sorter;
+        for i in 0..data_length {
+            let j = i ^ block as usize;
+            if j < i || j >= data_length {
+                continue;
+            }
+
+            let descending = i & dim as usize != 0;
+            let key_i = keys[i];
+            let key_j = keys[j];
+            let value_i = values[key_i as usize];
+            let value_j = values[key_j as usize];
+            let swap = if descending { value_i < value_j } else { value_i > value_j };
+            if swap {
+                keys[i] = key_j;
+                keys[j] = key_i;
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Powers of two and not, the others exercise the padding
+    const BITONIC_CHECK_LENGTHS: [u32; 6] = [1, 2, 3, 1024, 1025, 2500];
+
+    /// Shuffled cell indices with duplicates, padded with the sentinel like `particle_cell_indicies`
+    fn make_values(data_length: u32) -> Vec<u32> {
+        let mut seed: u32 = 12345;
+        (0..get_sort_length(data_length)).map(|it| {
+            if it >= data_length {
+                return SORT_SENTINEL;
+            }
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);  // LCG
+            seed % data_length
+        }).collect()
+    }
+
+    /// Sorts the keys of `make_values` on the CPU and returns them
+    fn sort_keys(data_length: u32) -> (Vec<u32>, Vec<u32>) {
+        let values = make_values(data_length);
+        let mut keys: Vec<u32> = (0..get_sort_length(data_length)).collect();
+        let stages = get_bit_sorter_stages(data_length, 1, "bitonic_check");
+        sort_cpu(&stages, &mut keys, &values);
+        (keys, values)
+    }
+
+    /// Stages of a bitonic network over `n = 2^k` keys, k * (k + 1) / 2
+    fn expected_num_stages(sort_length: u32) -> usize {
+        let k = sort_length.trailing_zeros() as usize;
+        k * (k + 1) / 2
+    }
+
+    #[test]
+    fn bitonic_schedule_sorts() {
+        for length in BITONIC_CHECK_LENGTHS {
+            let sort_length = get_sort_length(length);
+            let (keys, values) = sort_keys(length);
+
+            // The keys have to stay a permutation, a lost or doubled index would drop or repeat a particle
+            let mut seen = vec![false; sort_length as usize];
+            for &key in keys.iter() {
+                assert!(key < sort_length, "{} keys: key {} out of range", length, key);
+                assert!(!std::mem::replace(&mut seen[key as usize], true), "{} keys: key {} repeated", length, key);
+            }
+
+            let live_keys = &keys[..length as usize];
+            assert!(
+                live_keys.windows(2).all(|pair| values[pair[0] as usize] <= values[pair[1] as usize]),
+                "{} keys: out of order",
+                length,
+            );
+            assert!(live_keys.iter().all(|&key| key < length), "{} keys: padding sorted before a live one", length);
+
+            let stages = get_bit_sorter_stages(length, 1, "bitonic_check");
+            assert_eq!(stages.len(), expected_num_stages(sort_length), "{} keys", length);
+        }
+    }
+
+    #[test]
+    fn sorts_2500_particles_monotonically() {
+        let (keys, values) = sort_keys(2500);
+        let sorted: Vec<u32> = keys[..2500].iter().map(|&key| values[key as usize]).collect();
+        assert!(sorted.windows(2).all(|pair| pair[0] <= pair[1]));
+        // Every padding key sorts behind the live ones
+        assert!(keys[2500..].iter().all(|&key| values[key as usize] == SORT_SENTINEL));
+    }
+
+    #[test]
+    fn sort_length_pads_to_a_power_of_two() {
+        assert_eq!(get_sort_length(1), 1);
+        assert_eq!(get_sort_length(2), 2);
+        assert_eq!(get_sort_length(3), 4);
+        assert_eq!(get_sort_length(1024), 1024);
+        assert_eq!(get_sort_length(1025), 2048);
+        assert_eq!(get_sort_length(2500), 4096);
+    }
+
+    #[test]
+    fn single_key_needs_no_stages() {
+        assert!(get_bit_sorter_stages(1, 1, "sort").is_empty());
+    }
+
+    #[test]
+    fn stages_walk_the_network_in_order() {
+        // Three keys pad to four: dim 2 with block 1, then dim 4 with blocks 2 and 1
+        let stages = get_bit_sorter_stages(3, 7, "sort");
+        let steps: Vec<(u32, u32)> = stages.iter().map(|stage| (stage.bit_sorter.dim, stage.bit_sorter.block)).collect();
+        assert_eq!(steps, vec![(2, 1), (4, 2), (4, 1)]);
+
+        let names: Vec<&str> = stages.iter().map(|stage| stage.uniform_name.as_str()).collect();
+        assert_eq!(names, vec!["sort_1", "sort_2", "sort_3"]);
+        assert!(stages.iter().all(|stage| stage.workgroups == [7, 1, 1]));
+    }
+
+    #[test]
+    fn non_power_of_two_lengths_sort_like_the_padded_one() {
+        for length in [3, 1025, 2500] {
+            let padded = get_bit_sorter_stages(get_sort_length(length), 1, "sort");
+            let stages = get_bit_sorter_stages(length, 1, "sort");
+            assert_eq!(stages.len(), padded.len(), "{} keys", length);
+        }
+    }
+
+    #[test]
+    fn stage_count_follows_the_capacity_and_batch_size() {
+        // Capacities of a rebuild, each with the workgroup sizes a device might prefer
+        for capacity in [1000, 4096, 5000, 65536 + 8192] {
+            let sort_length = get_sort_length(capacity);
+            for workgroup_size in [64, 256, 1024] {
+                let tuning = ComputeTuning { workgroup_size };
+                let batch_size = tuning.get_batch_size(sort_length);
+                let stages = get_bit_sorter_stages(capacity, batch_size, "bit_sorter");
+                assert_eq!(stages.len(), expected_num_stages(sort_length), "capacity {}", capacity);
+                assert!(stages.iter().all(|stage| stage.workgroups == [sort_length.div_ceil(workgroup_size), 1, 1]));
+            }
+        }
+        // 2^13 keys take 13 * 14 / 2 stages
+        assert_eq!(get_bit_sorter_stages(5000, 1, "bit_sorter").len(), 91);
+    }
+}
@@ -3,18 +3,28 @@ use bevy::core::Pod;
 use bevy_app_compute::prelude::*;
 use bytemuck::Zeroable;
 
+use crate::schedule::InGameSet;
+use crate::fluid_container::FluidContainer;
+
 const GRAVITY_FORCE: f32 = 9.8;
+const GRAVITY_MODE_KEY: KeyCode = KeyCode::KeyV;
+const GRAVITY_ROTATE_KEYS: (KeyCode, KeyCode) = (KeyCode::Minus, KeyCode::Equal);  // Brackets change the particle mesh
+const GRAVITY_ROTATE_RATE: f32 = 1.;  // Radians per second
+const GRAVITY_ARROW_SCALE: f32 = 0.1;  // Arrow length per unit of acceleration
+const GRAVITY_ARROW_COLOR: Color = Color::rgb(0.95, 0.85, 0.2);
 
 
 #[derive(Resource, ShaderType, Pod, Zeroable, Clone, Copy)]
 #[repr(C)]
 pub struct Gravity {
     pub value: Vec4,
+    /// Pull towards xyz with strength w, replaces `value` when w is not zero
+    pub radial: Vec4,
 }
 
 
 impl Gravity {
-    pub fn new(value: Vec4) -> Self { Self { value } }
+    pub fn new(value: Vec4) -> Self { Self { value, radial: Vec4::ZERO } }
 
     pub fn set_zero(&mut self) {
         self.value = Vec4::ZERO;
@@ -23,6 +33,28 @@ impl Gravity {
     pub fn set_default(&mut self) {
         self.value = Vec4::new(0., -GRAVITY_FORCE, 0., 0.);
     }
+
+    pub fn magnitude(&self) -> f32 {
+        self.value.xyz().length()
+    }
+
+    /// Keeps the direction, straight down when there was none
+    pub fn set_magnitude(&mut self, magnitude: f32) {
+        let direction = self.value.xyz().try_normalize().unwrap_or(Vec3::NEG_Y);
+        self.value = (direction * magnitude).extend(0.);
+    }
+
+    /// Tilt in the XY plane, radians counter-clockwise from straight down
+    pub fn angle(&self) -> f32 {
+        self.value.x.atan2(-self.value.y)
+    }
+
+    /// Rotates the XY part to `theta`, the magnitude is kept
+    pub fn set_angle(&mut self, theta: f32) {
+        let planar = self.value.xy().length();
+        self.value.x = planar * theta.sin();
+        self.value.y = -planar * theta.cos();
+    }
 }
 
 
@@ -33,11 +65,97 @@ impl Default for Gravity {
 }
 
 
+#[derive(Resource, Clone, Copy, PartialEq, Default, Debug)]
+pub enum GravityMode {
+    /// Constant acceleration, the `Gravity` value
+    #[default]
+    Uniform,
+    /// Every particle is pulled towards the center, for planet-like demos
+    Radial { center: Vec3, strength: f32 },
+}
+
+
+impl GravityMode {
+    /// Gravity as seen by the shader
+    pub fn apply(&self, gravity: &Gravity) -> Gravity {
+        match *self {
+            GravityMode::Uniform => Gravity::new(gravity.value),
+            GravityMode::Radial { center, strength } => Gravity {
+                value: gravity.value,
+                radial: center.extend(strength),
+            },
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            GravityMode::Uniform => "uniform",
+            GravityMode::Radial { .. } => "radial",
+        }
+    }
+}
+
+
 pub struct GravityPlugin;
 
 
 impl Plugin for GravityPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<Gravity>();
+        app
+            .init_resource::<Gravity>()
+            .init_resource::<GravityMode>()
+            .add_systems(Update, (cycle_gravity_mode, rotate_gravity).in_set(InGameSet::UserInput))
+            .add_systems(Update, draw_gravity_arrow.in_set(InGameSet::EntityUpdates));
+    }
+}
+
+
+/// Radial gravity pulls towards the world origin with the current gravity magnitude
+fn cycle_gravity_mode(
+    mut mode: ResMut<GravityMode>,
+    gravity: Res<Gravity>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+) {
+    if !keyboard_input.just_pressed(GRAVITY_MODE_KEY) {
+        return;
+    }
+    *mode = match *mode {
+        GravityMode::Uniform => GravityMode::Radial {
+            center: Vec3::ZERO,
+            strength: gravity.value.length(),
+        },
+        GravityMode::Radial { .. } => GravityMode::Uniform,
+    };
+}
+
+
+/// Held keys tilt the gravity like tipping the container
+fn rotate_gravity(mut gravity: ResMut<Gravity>, keyboard_input: Res<ButtonInput<KeyCode>>, time: Res<Time>) {
+    let (left, right) = GRAVITY_ROTATE_KEYS;
+    let mut axis = 0.;
+    if keyboard_input.pressed(left) {
+        axis -= 1.;
+    }
+    if keyboard_input.pressed(right) {
+        axis += 1.;
+    }
+    if axis != 0. {
+        let angle = gravity.angle() + axis * GRAVITY_ROTATE_RATE * time.delta_seconds();
+        gravity.set_angle(angle);
+    }
+}
+
+
+/// Points from the container center the way things fall, radial gravity has no single direction
+fn draw_gravity_arrow(
+    mut gizmos: Gizmos,
+    gravity: Res<Gravity>,
+    mode: Res<GravityMode>,
+    container: Res<FluidContainer>,
+) {
+    if *mode != GravityMode::Uniform || gravity.magnitude() == 0. {
+        return;
     }
+    let end = container.position + gravity.value.xyz() * GRAVITY_ARROW_SCALE;
+    gizmos.arrow(container.position, end, GRAVITY_ARROW_COLOR);
 }
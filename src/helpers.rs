@@ -1,4 +1,13 @@
-use bevy::math::Vec3;
+use std::path::Path;
+
+use bevy::math::{Quat, UVec3, Vec2, Vec3, Vec4Swizzles};
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::texture::{CompressedImageFormats, Image, ImageSampler, ImageType};
+
+use crate::fluid_container::FluidContainer;
+
+const DAM_BREAK_FILL: Vec3 = Vec3::new(1. / 3., 0.6, 1.);  // Share of the container per axis
+const IMAGE_SPAWN_THRESHOLD: u8 = 127;  // Brighter pixels get a particle
 
 pub fn cube_fluid(ni: usize, nj: usize, nk: usize, particle_rad: f32) -> Vec<Vec3> {
     let mut points = Vec::new();
@@ -18,3 +27,355 @@ pub fn cube_fluid(ni: usize, nj: usize, nk: usize, particle_rad: f32) -> Vec<Vec
 
     points
 }
+
+
+pub fn grid_fluid_2d(ni: usize, nj: usize, particle_rad: f32) -> Vec<Vec2> {
+    let mut points = Vec::with_capacity(ni * nj);
+    let half_extents = Vec2::new(ni as f32, nj as f32) * particle_rad;
+    let offset = Vec2::new(particle_rad, particle_rad) - half_extents;
+    let diam = particle_rad * 2.;
+    for i in 0..ni {
+        let x = (i as f32) * diam;
+        for j in 0..nj {
+            let y = (j as f32) * diam;
+            points.push(Vec2::new(x, y) + offset);
+        }
+    }
+
+    points
+}
+
+
+/// Hexagonal packing, odd rows shifted by a radius and rows sqrt(3) radii apart, every neighbour a diameter away
+pub fn hex_pack_fluid(rows: usize, cols: usize, particle_rad: f32) -> Vec<Vec2> {
+    let mut points = Vec::with_capacity(rows * cols);
+    let diam = particle_rad * 2.;
+    let row_spacing = particle_rad * 3f32.sqrt();
+    let half_extents = Vec2::new((cols as f32 - 0.5) * diam, (rows.max(1) - 1) as f32 * row_spacing) / 2.;
+    for j in 0..rows {
+        let y = (j as f32) * row_spacing;
+        let shift = if j % 2 == 1 { particle_rad } else { 0. };
+        for i in 0..cols {
+            let x = (i as f32) * diam + shift;
+            points.push(Vec2::new(x, y) - half_extents);
+        }
+    }
+
+    points
+}
+
+
+/// Column in the lower X third of the container, released it collapses into the classic dam break
+pub fn dam_break(container: &FluidContainer, particle_rad: f32) -> Vec<Vec3> {
+    let ext = container.get_ext(particle_rad);
+    let ext_min = ext.ext_min.xyz();
+    let fill_size = ((ext.ext_max - ext.ext_min).xyz() * DAM_BREAK_FILL).max(Vec3::ZERO);
+    let diam = particle_rad * 2.;
+    let counts = (fill_size / diam).floor().as_uvec3() + 1;
+
+    let mut points = Vec::with_capacity((counts.x * counts.y * counts.z) as usize);
+    for i in 0..counts.x {
+        let x = (i as f32) * diam;
+        for j in 0..counts.y {
+            let y = (j as f32) * diam;
+            for k in 0..counts.z {
+                let z = (k as f32) * diam;
+                // The extents are in the unrotated frame around the container position
+                let point = ext_min + Vec3::new(x, y, z) - container.position;
+                points.push(container.position + container.rotation * point);
+            }
+        }
+    }
+
+    points
+}
+
+
+/// Particle per pixel of the grayscale image brighter than the threshold, centered on the container's XY face.
+/// Rows go down from the top. The pixels are a diameter apart unless the image has to shrink to fit the walls.
+pub fn fluid_from_luma(width: u32, height: u32, luma: &[u8], container: &FluidContainer, particle_rad: f32) -> Vec<Vec2> {
+    let ext = container.get_ext(container.wall_margin + particle_rad);
+    let ext_min = ext.ext_min.xy();
+    let ext_max = ext.ext_max.xy().max(ext_min);
+    let size = Vec2::new(width as f32, height as f32);
+    let spacing = ((ext_max - ext_min) / size).min_element().min(particle_rad * 2.);
+    let top_left = (ext_min + ext_max) / 2. + Vec2::new(-size.x, size.y) * spacing / 2.;
+
+    let mut points = Vec::new();
+    for row in 0..height {
+        for col in 0..width {
+            if luma[(row * width + col) as usize] > IMAGE_SPAWN_THRESHOLD {
+                points.push(top_left + Vec2::new(col as f32 + 0.5, -(row as f32 + 0.5)) * spacing);
+            }
+        }
+    }
+
+    points
+}
+
+
+/// `fluid_from_luma` over a PNG or any format Bevy reads, empty with a warning when the image can't be read
+pub fn fluid_from_image(path: impl AsRef<Path>, container: &FluidContainer, particle_rad: f32) -> Vec<Vec2> {
+    let path = path.as_ref();
+    let extension = path.extension().and_then(|it| it.to_str()).unwrap_or("png");
+    let image = std::fs::read(path)
+        .map_err(|err| err.to_string())
+        .and_then(|bytes| Image::from_buffer(
+            &bytes,
+            ImageType::Extension(extension),
+            CompressedImageFormats::NONE,
+            false,
+            ImageSampler::Default,
+            RenderAssetUsages::default(),
+        ).map_err(|err| err.to_string()))
+        .and_then(|image| image.try_into_dynamic().map_err(|err| err.to_string()));
+    match image {
+        Ok(image) => {
+            let luma = image.to_luma8();
+            fluid_from_luma(luma.width(), luma.height(), luma.as_raw(), container, particle_rad)
+        },
+        Err(err) => {
+            println!("Warning: can't read the spawn image {} ({})", path.display(), err);
+            Vec::new()
+        },
+    }
+}
+
+
+/// Fixed particles lining the container walls about a diameter apart, the mirror plane gets none
+pub fn boundary_particles(container: &FluidContainer, particle_rad: f32) -> Vec<Vec3> {
+    let ext = container.get_ext(container.wall_margin);
+    let mut ext_min = ext.ext_min.xyz() - container.position;
+    let mut ext_max = ext.ext_max.xyz() - container.position;
+    let mut rotation = container.rotation;
+    if container.mirror_x {
+        // Same frame as the simulation, which drops the rotation when mirroring
+        ext_max.x = 0.;
+        rotation = Quat::IDENTITY;
+    }
+    ext_min = ext_min.min(ext_max);
+    let size = ext_max - ext_min;
+    let counts = (size / (particle_rad * 2.)).round().as_uvec3().max(UVec3::ONE);
+    let step = size / counts.as_vec3();
+
+    let mut points = Vec::new();
+    for i in 0..=counts.x {
+        if container.mirror_x && i == counts.x {
+            continue;
+        }
+        for j in 0..=counts.y {
+            for k in 0..=counts.z {
+                let on_wall = i == 0 || i == counts.x || j == 0 || j == counts.y || k == 0 || k == counts.z;
+                if !on_wall {
+                    continue;
+                }
+                let point = ext_min + UVec3::new(i, j, k).as_vec3() * step;
+                points.push(container.position + rotation * point);
+            }
+        }
+    }
+
+    points
+}
+
+
+/// SplitMix64, enough for reproducible spawns without pulling in a crate
+struct SeededRng(u64);
+
+
+impl SeededRng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in the open interval (0, 1)
+    fn next_f32(&mut self) -> f32 {
+        ((self.next_u64() >> 40) as f32 + 0.5) / (1u64 << 24) as f32
+    }
+}
+
+
+/// Uniformly scattered points strictly inside the box, the same seed gives the same points
+pub fn random_fluid(n: usize, min: Vec3, max: Vec3, seed: u64) -> Vec<Vec3> {
+    let mut rng = SeededRng(seed);
+    let mut points = Vec::with_capacity(n);
+    for _ in 0..n {
+        let t = Vec3::new(rng.next_f32(), rng.next_f32(), rng.next_f32());
+        points.push(min + (max - min) * t);
+    }
+
+    points
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_has_a_point_per_cell() {
+        assert_eq!(grid_fluid_2d(4, 3, 0.5).len(), 12);
+        assert_eq!(grid_fluid_2d(0, 3, 0.5).len(), 0);
+    }
+
+    #[test]
+    fn grid_is_centered_on_the_origin() {
+        let points = grid_fluid_2d(4, 3, 0.5);
+        let mean = points.iter().sum::<Vec2>() / points.len() as f32;
+        assert!(mean.length() < 1e-5, "{mean}");
+        let min = points.iter().fold(Vec2::INFINITY, |acc, it| acc.min(*it));
+        let max = points.iter().fold(Vec2::NEG_INFINITY, |acc, it| acc.max(*it));
+        // Particle edges span a diameter per cell
+        assert!((min - 0.5 + Vec2::new(2., 1.5)).length() < 1e-5, "{min}");
+        assert!((max + 0.5 - Vec2::new(2., 1.5)).length() < 1e-5, "{max}");
+    }
+
+    #[test]
+    fn hex_packing_has_rows_times_cols_points() {
+        assert_eq!(hex_pack_fluid(5, 7, 0.1).len(), 35);
+        assert_eq!(hex_pack_fluid(1, 1, 0.1).len(), 1);
+        assert_eq!(hex_pack_fluid(0, 7, 0.1).len(), 0);
+    }
+
+    #[test]
+    fn hex_packing_keeps_a_diameter_apart() {
+        let particle_rad = 0.1;
+        let points = hex_pack_fluid(5, 7, particle_rad);
+        let mut min_distance = f32::INFINITY;
+        for (i, a) in points.iter().enumerate() {
+            for b in &points[i + 1..] {
+                min_distance = min_distance.min(a.distance(*b));
+            }
+        }
+        assert!((min_distance - 2. * particle_rad).abs() < 1e-5, "{min_distance}");
+    }
+
+    #[test]
+    fn random_fluid_repeats_with_the_seed() {
+        let a = random_fluid(64, Vec3::ZERO, Vec3::ONE, 7);
+        let b = random_fluid(64, Vec3::ZERO, Vec3::ONE, 7);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn random_fluid_differs_between_seeds() {
+        let a = random_fluid(64, Vec3::ZERO, Vec3::ONE, 7);
+        let b = random_fluid(64, Vec3::ZERO, Vec3::ONE, 8);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn random_fluid_stays_in_the_box() {
+        let min = Vec3::new(-2., 0.5, 3.);
+        let max = Vec3::new(1., 4., 3.25);
+        let points = random_fluid(1000, min, max, 42);
+        assert_eq!(points.len(), 1000);
+        for point in points {
+            assert!(point.cmpge(min).all() && point.cmple(max).all(), "{point}");
+        }
+    }
+
+    #[test]
+    fn luma_spawns_on_the_bright_pixels() {
+        let luma = [0, 255, 128, IMAGE_SPAWN_THRESHOLD, 200, 0];
+        let points = fluid_from_luma(3, 2, &luma, &FluidContainer::default(), 0.1);
+        assert_eq!(points.len(), 3);
+        // Rows go down from the top
+        assert!(points[0].y > points[2].y);
+        assert!(points[1].x > points[0].x);
+    }
+
+    #[test]
+    fn luma_all_dark_spawns_nothing() {
+        let luma = [IMAGE_SPAWN_THRESHOLD; 16];
+        assert!(fluid_from_luma(4, 4, &luma, &FluidContainer::default(), 0.1).is_empty());
+    }
+
+    fn assert_inside(container: &FluidContainer, particle_rad: f32) {
+        let ext = container.get_ext(particle_rad);
+        let points = dam_break(container, particle_rad);
+        assert!(!points.is_empty());
+        for point in points {
+            // Back to the unrotated frame the extents are in
+            let local = container.position + container.rotation.inverse() * (point - container.position);
+            assert!(local.cmpge(ext.ext_min.xyz() - 1e-4).all(), "{local}");
+            assert!(local.cmple(ext.ext_max.xyz() + 1e-4).all(), "{local}");
+        }
+    }
+
+    #[test]
+    fn dam_break_stays_inside_the_container() {
+        assert_inside(&FluidContainer::default(), 0.1);
+        assert_inside(&FluidContainer::default(), 0.35);
+    }
+
+    #[test]
+    fn dam_break_follows_an_offset_rotated_container() {
+        let container = FluidContainer {
+            position: Vec3::new(3., -1., 2.),
+            size: Vec3::new(5., 3., 2.),
+            rotation: Quat::from_rotation_z(0.4),
+            ..FluidContainer::default()
+        };
+        assert_inside(&container, 0.1);
+    }
+
+    fn get_wall_area(size: Vec3) -> f32 {
+        2. * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    #[test]
+    fn boundary_particles_grow_with_the_wall_area() {
+        let container = FluidContainer { size: Vec3::new(4., 2., 2.), wall_margin: 0., ..FluidContainer::default() };
+        let wider = FluidContainer { size: Vec3::new(8., 2., 4.), ..container.clone() };
+        // The faces of a 20x10x10 and a 40x10x20 lattice
+        let count = boundary_particles(&container, 0.1).len();
+        let wider_count = boundary_particles(&wider, 0.1).len();
+        assert_eq!(count, 21 * 11 * 11 - 19 * 9 * 9);
+        assert_eq!(wider_count, 41 * 11 * 21 - 39 * 9 * 19);
+        let area_ratio = get_wall_area(wider.size) / get_wall_area(container.size);
+        let count_ratio = wider_count as f32 / count as f32;
+        assert!((count_ratio / area_ratio - 1.).abs() < 0.05, "{count_ratio} vs {area_ratio}");
+    }
+
+    fn assert_on_walls(container: &FluidContainer, particle_rad: f32) -> Vec<Vec3> {
+        let ext = container.get_ext(container.wall_margin);
+        let (ext_min, ext_max) = (ext.ext_min.xyz(), ext.ext_max.xyz());
+        let points = boundary_particles(container, particle_rad);
+        assert!(!points.is_empty());
+        let mut locals = Vec::with_capacity(points.len());
+        for point in points {
+            let local = container.position + container.rotation.inverse() * (point - container.position);
+            assert!(local.cmpge(ext_min - 1e-4).all() && local.cmple(ext_max + 1e-4).all(), "{local}");
+            let on_wall = (local - ext_min).abs().cmplt(Vec3::splat(1e-4)).any()
+                || (local - ext_max).abs().cmplt(Vec3::splat(1e-4)).any();
+            assert!(on_wall, "{local} is off the walls");
+            locals.push(local);
+        }
+        locals
+    }
+
+    #[test]
+    fn boundary_particles_lie_on_the_walls() {
+        let container = FluidContainer {
+            position: Vec3::new(3., -1., 2.),
+            size: Vec3::new(5., 3., 2.),
+            rotation: Quat::from_rotation_z(0.4),
+            ..FluidContainer::default()
+        };
+        assert_on_walls(&container, 0.1);
+        assert_on_walls(&container, 0.3);
+    }
+
+    #[test]
+    fn mirrored_boundary_particles_skip_the_mirror_plane() {
+        let container = FluidContainer { size: Vec3::new(4., 2., 2.), mirror_x: true, ..FluidContainer::default() };
+        let locals = assert_on_walls(&container, 0.1);
+        // Only the simulated half, nothing on the plane the ghosts are reflected across
+        assert!(locals.iter().all(|local| local.x < container.position.x - 1e-4));
+    }
+}
@@ -1,20 +1,47 @@
 use bevy::prelude::*;
+use bevy::diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin};
+use bevy_app_compute::prelude::*;
 
 use crate::schedule::InGameSet;
 use crate::state::GameState;
-use crate::gravity::Gravity;
-use crate::fluid_compute::FluidStaticProps;
+use crate::gravity::{Gravity, GravityMode};
+use crate::fluid_compute::{
+    ComputeBackendStatus, FluidCapacity, FluidParticle, FluidStaticProps, FluidWorker, PARTICLE_RADIUS,
+};
+use crate::debug::{GridOverlay, NeighborSearchCheck};
+use crate::force_toggles::ForceToggles;
+use crate::particle_color::ColorSettings;
+use crate::world_cursor::WorldCursor;
+use crate::fluid_container::WallProps;
 
 const TEXT_COLOR: Color = Color::rgb(0.9, 0.9, 0.9);
+const WARNING_TEXT_COLOR: Color = Color::rgb(0.95, 0.4, 0.3);
 const TEXT_FONT_SIZE: f32 = 20.;
 
-const FLUID_PROPS_CHANGE_STEP: f32 = 0.1;
+// Change per second while the key is held
+const FLUID_PROPS_CHANGE_RATE: f32 = 1.;
+const SMOOTHING_RADIUS_CHANGE_RATE: f32 = 0.2;
+const LOOKAHEAD_CHANGE_RATE: f32 = 0.02;
+const GRAVITY_CHANGE_RATE: f32 = 2.;
+const XSPH_CHANGE_RATE: f32 = 0.2;
+
+const SMOOTHING_RADIUS_MIN: f32 = 0.05;
+const FLUID_PROPS_RESET_KEY: KeyCode = KeyCode::Backspace;  // R already raises the viscosity
+const HUD_TOGGLE_KEY: KeyCode = KeyCode::F1;  // H already runs the emitter
+const AVG_DENSITY_REFRESH_FRAMES: u32 = 30;  // Averaging reads back every particle
+const VELOCITY_STATS_REFRESH_FRAMES: u32 = 30;
+const NEIGHBOR_COUNT_REFRESH_FRAMES: u32 = 30;
+const NEIGHBOR_COUNT_HEALTHY: (f32, f32) = (20., 40.);  // Average SPH needs for a smooth density
 
 
 #[derive(Component, Debug)]
 pub struct HudItem;
 
 
+#[derive(Component, Debug)]
+pub struct FpsHudItem;
+
+
 #[derive(Component, Debug)]
 pub struct PressureHudItem;
 
@@ -27,10 +54,34 @@ pub struct NearPressureHudItem;
 pub struct TargetDensityHudItem;
 
 
+#[derive(Component, Debug)]
+pub struct AvgDensityHudItem;
+
+
+#[derive(Component, Debug)]
+pub struct VelocityStatsHudItem;
+
+
+#[derive(Component, Debug)]
+pub struct NeighborCountHudItem;
+
+
 #[derive(Component, Debug)]
 pub struct ViscosityHudItem;
 
 
+#[derive(Component, Debug)]
+pub struct CohesionHudItem;
+
+
+#[derive(Component, Debug)]
+pub struct XsphHudItem;
+
+
+#[derive(Component, Debug)]
+pub struct ThermalHudItem;
+
+
 #[derive(Component, Debug)]
 pub struct SmoothingRadiusHudItem;
 
@@ -39,24 +90,88 @@ pub struct SmoothingRadiusHudItem;
 pub struct GravityHudItem;
 
 
+#[derive(Component, Debug)]
+pub struct LookaheadHudItem;
+
+
+#[derive(Component, Debug)]
+pub struct NeighborMissHudItem;
+
+
+#[derive(Component, Debug)]
+pub struct GridOverlayHudItem;
+
+
+#[derive(Component, Debug)]
+pub struct ForceTogglesHudItem;
+
+
+#[derive(Component, Debug)]
+pub struct ParticleCountHudItem;
+
+
+#[derive(Component, Debug)]
+pub struct ColorLegendHudItem;
+
+
+#[derive(Component, Debug)]
+pub struct CursorModeHudItem;
+
+
+#[derive(Component, Debug)]
+pub struct WallsHudItem;
+
+
+#[derive(Component, Debug)]
+pub struct ComputeBackendHudItem;
+
+
 pub struct HudPlugin;
 
 
 impl Plugin for HudPlugin {
     fn build(&self, app: &mut App) {
         app
+            .add_plugins(FrameTimeDiagnosticsPlugin)
+            .add_systems(Update, toggle_hud.in_set(InGameSet::UserInput))
             .add_systems(Update, (
                 update_fluid_props,
                 (
+                    update_fps_in_hud,
                     update_pressure_in_hud,
                     update_near_pressure_in_hud,
                     update_target_density_in_hud,
+                    update_avg_density_in_hud,
+                    update_velocity_stats_in_hud,
+                    update_neighbor_count_in_hud,
                     update_viscosity_in_hud,
+                    update_cohesion_in_hud,
+                    update_xsph_in_hud,
+                    update_thermal_in_hud,
+                ),
+                (
                     update_smoothing_radius_in_hud,
                     update_gravity_in_hud,
+                    update_lookahead_in_hud,
+                    update_neighbor_miss_in_hud,
+                    update_grid_overlay_in_hud,
+                    update_force_toggles_in_hud,
+                    update_particle_count_in_hud,
+                    update_color_legend_in_hud,
+                    update_cursor_mode_in_hud,
+                    update_walls_in_hud,
+                    update_compute_backend_in_hud,
                 ),
             ).chain().in_set(InGameSet::EntityUpdates))
-            .add_systems(OnExit(GameState::Menu), setup_hud);
+            .add_systems(OnTransition { from: GameState::Menu, to: GameState::InGame }, setup_hud)
+            .add_systems(OnTransition { from: GameState::Paused, to: GameState::Menu }, despawn_hud);
+    }
+}
+
+
+fn despawn_hud(mut commands: Commands, query: Query<Entity, With<HudItem>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
     }
 }
 
@@ -75,6 +190,14 @@ fn setup_hud(mut commands: Commands) {
         },
         HudItem,
     )).with_children(|parent| {
+        parent.spawn((
+            TextBundle::from_section("- fps", TextStyle {
+                font_size: TEXT_FONT_SIZE,
+                color: TEXT_COLOR,
+                ..default()
+            }),
+            FpsHudItem,
+        ));
         parent.spawn((
             TextBundle::from_section("P: 0", TextStyle {
                 font_size: TEXT_FONT_SIZE,
@@ -99,6 +222,30 @@ fn setup_hud(mut commands: Commands) {
             }),
             TargetDensityHudItem,
         ));
+        parent.spawn((
+            TextBundle::from_section("avg ρ: -", TextStyle {
+                font_size: TEXT_FONT_SIZE,
+                color: TEXT_COLOR,
+                ..default()
+            }),
+            AvgDensityHudItem,
+        ));
+        parent.spawn((
+            TextBundle::from_section("|v|: -", TextStyle {
+                font_size: TEXT_FONT_SIZE,
+                color: TEXT_COLOR,
+                ..default()
+            }),
+            VelocityStatsHudItem,
+        ));
+        parent.spawn((
+            TextBundle::from_section("Neighbors: 0", TextStyle {
+                font_size: TEXT_FONT_SIZE,
+                color: TEXT_COLOR,
+                ..default()
+            }),
+            NeighborCountHudItem,
+        ));
         parent.spawn((
             TextBundle::from_section("Viscosity: 0", TextStyle {
                 font_size: TEXT_FONT_SIZE,
@@ -107,6 +254,30 @@ fn setup_hud(mut commands: Commands) {
             }),
             ViscosityHudItem,
         ));
+        parent.spawn((
+            TextBundle::from_section("C: 0", TextStyle {
+                font_size: TEXT_FONT_SIZE,
+                color: TEXT_COLOR,
+                ..default()
+            }),
+            CohesionHudItem,
+        ));
+        parent.spawn((
+            TextBundle::from_section("XSPH: 0", TextStyle {
+                font_size: TEXT_FONT_SIZE,
+                color: TEXT_COLOR,
+                ..default()
+            }),
+            XsphHudItem,
+        ));
+        parent.spawn((
+            TextBundle::from_section("Thermal: 0", TextStyle {
+                font_size: TEXT_FONT_SIZE,
+                color: TEXT_COLOR,
+                ..default()
+            }),
+            ThermalHudItem,
+        ));
         parent.spawn((
             TextBundle::from_section("Smoothing Radius: 0", TextStyle {
                 font_size: TEXT_FONT_SIZE,
@@ -123,45 +294,178 @@ fn setup_hud(mut commands: Commands) {
             }),
             GravityHudItem,
         ));
+        parent.spawn((
+            TextBundle::from_section("Lookahead: 0", TextStyle {
+                font_size: TEXT_FONT_SIZE,
+                color: TEXT_COLOR,
+                ..default()
+            }),
+            LookaheadHudItem,
+        ));
+        parent.spawn((
+            TextBundle::from_section("Neighbor miss: off", TextStyle {
+                font_size: TEXT_FONT_SIZE,
+                color: TEXT_COLOR,
+                ..default()
+            }),
+            NeighborMissHudItem,
+        ));
+        parent.spawn((
+            TextBundle::from_section("Grid: off", TextStyle {
+                font_size: TEXT_FONT_SIZE,
+                color: TEXT_COLOR,
+                ..default()
+            }),
+            GridOverlayHudItem,
+        ));
+        parent.spawn((
+            TextBundle::from_section("Forces: P nP V G", TextStyle {
+                font_size: TEXT_FONT_SIZE,
+                color: TEXT_COLOR,
+                ..default()
+            }),
+            ForceTogglesHudItem,
+        ));
+        parent.spawn((
+            TextBundle::from_section("Particles: 0", TextStyle {
+                font_size: TEXT_FONT_SIZE,
+                color: TEXT_COLOR,
+                ..default()
+            }),
+            ParticleCountHudItem,
+        ));
+        parent.spawn((
+            TextBundle::from_section("Color: solid", TextStyle {
+                font_size: TEXT_FONT_SIZE,
+                color: TEXT_COLOR,
+                ..default()
+            }),
+            ColorLegendHudItem,
+        ));
+        parent.spawn((
+            TextBundle::from_section("Cursor: off", TextStyle {
+                font_size: TEXT_FONT_SIZE,
+                color: TEXT_COLOR,
+                ..default()
+            }),
+            CursorModeHudItem,
+        ));
+        parent.spawn((
+            TextBundle::from_section("Floor friction: 0", TextStyle {
+                font_size: TEXT_FONT_SIZE,
+                color: TEXT_COLOR,
+                ..default()
+            }),
+            WallsHudItem,
+        ));
+        parent.spawn((
+            TextBundle::from_section("Compute: initializing", TextStyle {
+                font_size: TEXT_FONT_SIZE,
+                color: TEXT_COLOR,
+                ..default()
+            }),
+            ComputeBackendHudItem,
+        ));
     });
 }
 
 
+/// -1, 0 or 1 depending on which of the two keys are held
+fn get_key_axis(keyboard_input: &ButtonInput<KeyCode>, decrease: KeyCode, increase: KeyCode) -> f32 {
+    let mut axis = 0.;
+    if keyboard_input.pressed(decrease) {
+        axis -= 1.;
+    }
+    if keyboard_input.pressed(increase) {
+        axis += 1.;
+    }
+    axis
+}
+
+
+/// Keeps the tuned props in the range the solver can run with
+pub fn clamp_fluid_props(fluid_props: &FluidStaticProps) -> FluidStaticProps {
+    let mut fluid_props = *fluid_props;
+    fluid_props.smoothing_radius = fluid_props.smoothing_radius.max(SMOOTHING_RADIUS_MIN);
+    fluid_props.target_density = fluid_props.target_density.max(0.);
+    fluid_props.pressure_scalar = fluid_props.pressure_scalar.max(0.);
+    fluid_props.near_pressure_scalar = fluid_props.near_pressure_scalar.max(0.);
+    fluid_props.viscosity_strength = fluid_props.viscosity_strength.max(0.);
+    fluid_props.cohesion_strength = fluid_props.cohesion_strength.max(0.);
+    fluid_props.lookahead_time = fluid_props.lookahead_time.max(0.);
+    fluid_props.max_speed = fluid_props.max_speed.max(0.);
+    fluid_props.xsph_strength = fluid_props.xsph_strength.clamp(0., 1.);
+    fluid_props.thermal_diffusion = fluid_props.thermal_diffusion.max(0.);
+    fluid_props.buoyancy = fluid_props.buoyancy.max(0.);
+    fluid_props
+}
+
+
+/// Only hides the items, their values keep updating underneath
+fn toggle_hud(mut query: Query<&mut Visibility, With<HudItem>>, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if !keyboard_input.just_pressed(HUD_TOGGLE_KEY) {
+        return;
+    }
+    for mut visibility in query.iter_mut() {
+        *visibility = match *visibility {
+            Visibility::Hidden => Visibility::Inherited,
+            _ => Visibility::Hidden,
+        };
+    }
+}
+
+
 fn update_fluid_props(
     mut fluid_props: ResMut<FluidStaticProps>,
     mut gravity: ResMut<Gravity>,
-    keyboard_input: Res<ButtonInput<KeyCode>>
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
 ) {
-    if keyboard_input.just_pressed(KeyCode::Digit1) && fluid_props.smoothing_radius - FLUID_PROPS_CHANGE_STEP > 0. {
-        fluid_props.smoothing_radius -= FLUID_PROPS_CHANGE_STEP;
-    } else if keyboard_input.just_pressed(KeyCode::Digit2) {
-        fluid_props.smoothing_radius += FLUID_PROPS_CHANGE_STEP;
-    } else if keyboard_input.just_pressed(KeyCode::KeyQ) {
-        fluid_props.pressure_scalar -= FLUID_PROPS_CHANGE_STEP;
-    } else if keyboard_input.just_pressed(KeyCode::KeyW) {
-        fluid_props.pressure_scalar += FLUID_PROPS_CHANGE_STEP;
-    } else if keyboard_input.just_pressed(KeyCode::KeyA) {
-        fluid_props.near_pressure_scalar -= FLUID_PROPS_CHANGE_STEP;
-    } else if keyboard_input.just_pressed(KeyCode::KeyS) {
-        fluid_props.near_pressure_scalar += FLUID_PROPS_CHANGE_STEP;
-    } else if keyboard_input.just_pressed(KeyCode::KeyZ) {
-        fluid_props.target_density -= FLUID_PROPS_CHANGE_STEP;
-    } else if keyboard_input.just_pressed(KeyCode::KeyX) {
-        fluid_props.target_density += FLUID_PROPS_CHANGE_STEP;
-    } else if keyboard_input.just_pressed(KeyCode::Digit3) {
-        gravity.value.y += FLUID_PROPS_CHANGE_STEP;
-    } else if keyboard_input.just_pressed(KeyCode::Digit4) {
-        gravity.value.y -= FLUID_PROPS_CHANGE_STEP;
-    } else if keyboard_input.just_pressed(KeyCode::KeyE) {
-        fluid_props.viscosity_strength -= FLUID_PROPS_CHANGE_STEP;
-    } else if keyboard_input.just_pressed(KeyCode::KeyR) {
-        fluid_props.viscosity_strength += FLUID_PROPS_CHANGE_STEP;
-    } else if keyboard_input.just_pressed(KeyCode::Digit0) {
+    if keyboard_input.just_pressed(FLUID_PROPS_RESET_KEY) {
+        // Tuning only, the particles keep going
+        *fluid_props = FluidStaticProps::default();
+        gravity.set_default();
+        return;
+    }
+    if keyboard_input.just_pressed(KeyCode::Digit0) {
         gravity.set_zero();
     } else if keyboard_input.just_pressed(KeyCode::Digit9) {
         gravity.set_default();
     }
 
+    // Held keys change their value continuously, several at a time
+    let delta = time.delta_seconds();
+    let axis = |decrease, increase| get_key_axis(&keyboard_input, decrease, increase) * delta;
+    let mut tuned = *fluid_props;
+    tuned.smoothing_radius += axis(KeyCode::Digit1, KeyCode::Digit2) * SMOOTHING_RADIUS_CHANGE_RATE;
+    tuned.pressure_scalar += axis(KeyCode::KeyQ, KeyCode::KeyW) * FLUID_PROPS_CHANGE_RATE;
+    tuned.near_pressure_scalar += axis(KeyCode::KeyA, KeyCode::KeyS) * FLUID_PROPS_CHANGE_RATE;
+    tuned.target_density += axis(KeyCode::KeyZ, KeyCode::KeyX) * FLUID_PROPS_CHANGE_RATE;
+    tuned.viscosity_strength += axis(KeyCode::KeyE, KeyCode::KeyR) * FLUID_PROPS_CHANGE_RATE;
+    tuned.cohesion_strength += axis(KeyCode::Digit7, KeyCode::Digit8) * FLUID_PROPS_CHANGE_RATE;
+    tuned.lookahead_time += axis(KeyCode::Digit5, KeyCode::Digit6) * LOOKAHEAD_CHANGE_RATE;
+    tuned.xsph_strength += axis(KeyCode::KeyU, KeyCode::KeyI) * XSPH_CHANGE_RATE;
+    // Keep change detection quiet while nothing is held
+    fluid_props.set_if_neq(clamp_fluid_props(&tuned));
+
+    let gravity_change = axis(KeyCode::Digit4, KeyCode::Digit3) * GRAVITY_CHANGE_RATE;
+    if gravity_change != 0. {
+        let magnitude = (gravity.magnitude() - gravity_change).max(0.);
+        gravity.set_magnitude(magnitude);
+    }
+}
+
+
+fn update_fps_in_hud(mut query: Query<&mut Text, With<FpsHudItem>>, diagnostics: Res<DiagnosticsStore>) {
+    let Ok(mut fps_hud_item) = query.get_single_mut() else { return };
+    if fps_hud_item.sections.is_empty() {
+        return;
+    }
+    // Both need a few frames of history before they have a value
+    let fps = diagnostics.get(&FrameTimeDiagnosticsPlugin::FPS).and_then(|it| it.smoothed());
+    let frame_time = diagnostics.get(&FrameTimeDiagnosticsPlugin::FRAME_TIME).and_then(|it| it.smoothed());
+    let (Some(fps), Some(frame_time)) = (fps, frame_time) else { return };
+    fps_hud_item.sections[0].value = format!("{:.0} fps ({:.1} ms)", fps, frame_time);
 }
 
 
@@ -192,6 +496,119 @@ fn update_target_density_in_hud(mut query: Query<&mut Text, With<TargetDensityHu
 }
 
 
+/// Live density from the GPU, left untouched while the worker is busy
+fn update_avg_density_in_hud(
+    mut query: Query<&mut Text, With<AvgDensityHudItem>>,
+    mut frames: Local<u32>,
+    worker: Res<AppComputeWorker<FluidWorker>>,
+    capacity: Res<FluidCapacity>,
+) {
+    if *frames > 0 {
+        *frames -= 1;
+        return;
+    }
+    if !worker.ready() || capacity.num_particles == 0 {
+        return;
+    }
+    let Ok(mut avg_density_hud_item) = query.get_single_mut() else { return };
+    if avg_density_hud_item.sections.is_empty() {
+        return;
+    }
+    *frames = AVG_DENSITY_REFRESH_FRAMES;
+
+    let particles = worker.read_vec::<FluidParticle>("particles");
+    let live_particles = &particles[..capacity.num_particles as usize];
+    let avg_density = live_particles.iter().map(|particle| particle.density.x).sum::<f32>() / live_particles.len() as f32;
+    avg_density_hud_item.sections[0].value = format!("avg ρ: {:.2}", avg_density);
+}
+
+
+/// Min, max and mean speed, turns red once the simulation blows up
+fn update_velocity_stats_in_hud(
+    mut query: Query<&mut Text, With<VelocityStatsHudItem>>,
+    mut frames: Local<u32>,
+    worker: Res<AppComputeWorker<FluidWorker>>,
+    capacity: Res<FluidCapacity>,
+    fluid_props: Res<FluidStaticProps>,
+) {
+    if *frames > 0 {
+        *frames -= 1;
+        return;
+    }
+    if !worker.ready() || capacity.num_particles == 0 {
+        return;
+    }
+    let Ok(mut velocity_stats_hud_item) = query.get_single_mut() else { return };
+    if velocity_stats_hud_item.sections.is_empty() {
+        return;
+    }
+    *frames = VELOCITY_STATS_REFRESH_FRAMES;
+
+    let particles = worker.read_vec::<FluidParticle>("particles");
+    let live_particles = &particles[..capacity.num_particles as usize];
+    let mut min_speed = f32::INFINITY;
+    let mut max_speed = 0_f32;
+    let mut sum_speed = 0.;
+    let mut finite = true;
+    for particle in live_particles {
+        let speed = particle.velocity.truncate().length();
+        finite &= speed.is_finite();
+        min_speed = min_speed.min(speed);
+        max_speed = max_speed.max(speed);
+        sum_speed += speed;
+    }
+    let section = &mut velocity_stats_hud_item.sections[0];
+    section.value = format!(
+        "|v|: {:.2}/{:.2}/{:.2}",
+        min_speed, max_speed, sum_speed / live_particles.len() as f32,
+    );
+    // The clamp is meant to stay inert, reaching it hints at a stiff tuning
+    if fluid_props.max_speed > 0. {
+        section.value += &format!(" (cap {:.1})", fluid_props.max_speed);
+    } else {
+        section.value += " (no cap)";
+    }
+    section.style.color = if finite { TEXT_COLOR } else { WARNING_TEXT_COLOR };
+}
+
+
+/// Average neighbour count, hints at the smoothing radius change once it leaves the healthy band
+fn update_neighbor_count_in_hud(
+    mut query: Query<&mut Text, With<NeighborCountHudItem>>,
+    mut frames: Local<u32>,
+    worker: Res<AppComputeWorker<FluidWorker>>,
+    capacity: Res<FluidCapacity>,
+) {
+    if *frames > 0 {
+        *frames -= 1;
+        return;
+    }
+    if !worker.ready() || capacity.num_particles == 0 {
+        return;
+    }
+    let Ok(mut neighbor_count_hud_item) = query.get_single_mut() else { return };
+    if neighbor_count_hud_item.sections.is_empty() {
+        return;
+    }
+    *frames = NEIGHBOR_COUNT_REFRESH_FRAMES;
+
+    let particles = worker.read_vec::<FluidParticle>("particles");
+    let live_particles = &particles[..capacity.num_particles as usize];
+    let avg_count = live_particles.iter().map(|particle| particle.neighbors.x).sum::<f32>() / live_particles.len() as f32;
+    let (min_count, max_count) = NEIGHBOR_COUNT_HEALTHY;
+    let hint = if avg_count < min_count {
+        " (raise smoothing radius)"
+    } else if avg_count > max_count {
+        " (lower smoothing radius)"
+    } else {
+        ""
+    };
+    let section = &mut neighbor_count_hud_item.sections[0];
+    section.value = format!("Neighbors: {:.1}{}", avg_count, hint);
+    section.style.color = if hint.is_empty() { TEXT_COLOR } else { WARNING_TEXT_COLOR };
+}
+
+
 fn update_viscosity_in_hud(mut query: Query<&mut Text, With<ViscosityHudItem>>, fluid_props: Res<FluidStaticProps>) {
     let Ok(mut viscosity_hud_item) = query.get_single_mut() else { return };
     if viscosity_hud_item.sections.is_empty() {
@@ -201,6 +618,39 @@ fn update_viscosity_in_hud(mut query: Query<&mut Text, With<ViscosityHudItem>>,
 }
 
 
+fn update_cohesion_in_hud(mut query: Query<&mut Text, With<CohesionHudItem>>, fluid_props: Res<FluidStaticProps>) {
+    let Ok(mut cohesion_hud_item) = query.get_single_mut() else { return };
+    if cohesion_hud_item.sections.is_empty() {
+        return;
+    }
+    // Between two particles at rest, a diameter apart
+    let pull = fluid_props.get_cohesion_force(Vec3::ZERO, Vec3::X * PARTICLE_RADIUS * 2.).x;
+    cohesion_hud_item.sections[0].value = format!("C: {:.3} ({:.2} pull)", fluid_props.cohesion_strength, pull);
+}
+
+
+fn update_xsph_in_hud(mut query: Query<&mut Text, With<XsphHudItem>>, fluid_props: Res<FluidStaticProps>) {
+    let Ok(mut xsph_hud_item) = query.get_single_mut() else { return };
+    if xsph_hud_item.sections.is_empty() {
+        return;
+    }
+    xsph_hud_item.sections[0].value = format!("XSPH: {:.3}", fluid_props.xsph_strength);
+}
+
+
+fn update_thermal_in_hud(mut query: Query<&mut Text, With<ThermalHudItem>>, fluid_props: Res<FluidStaticProps>) {
+    let Ok(mut thermal_hud_item) = query.get_single_mut() else { return };
+    if thermal_hud_item.sections.is_empty() {
+        return;
+    }
+    thermal_hud_item.sections[0].value = format!(
+        "Thermal: diffusion {:.2}, buoyancy {:.2}",
+        fluid_props.thermal_diffusion,
+        fluid_props.buoyancy,
+    );
+}
+
+
 fn update_smoothing_radius_in_hud(mut query: Query<&mut Text, With<SmoothingRadiusHudItem>>, fluid_props: Res<FluidStaticProps>) {
     let Ok(mut smoothing_radius_hud_item) = query.get_single_mut() else { return };
     if smoothing_radius_hud_item.sections.is_empty() {
@@ -210,10 +660,183 @@ fn update_smoothing_radius_in_hud(mut query: Query<&mut Text, With<SmoothingRadi
 }
 
 
-fn update_gravity_in_hud(mut query: Query<&mut Text, With<GravityHudItem>>, gravity: Res<Gravity>) {
+fn update_gravity_in_hud(
+    mut query: Query<&mut Text, With<GravityHudItem>>,
+    gravity: Res<Gravity>,
+    gravity_mode: Res<GravityMode>,
+) {
     let Ok(mut gravity_hud_item) = query.get_single_mut() else { return };
     if gravity_hud_item.sections.is_empty() {
         return;
     }
-    gravity_hud_item.sections[0].value = format!("Gravity: {:.3}", -gravity.value.y);
+    gravity_hud_item.sections[0].value = match *gravity_mode {
+        GravityMode::Uniform => format!("Gravity: {:.3} at {:.0}°", gravity.magnitude(), gravity.angle().to_degrees()),
+        GravityMode::Radial { strength, .. } => format!("Gravity: {:.3} ({})", strength, gravity_mode.get_name()),
+    };
+}
+
+
+fn update_lookahead_in_hud(mut query: Query<&mut Text, With<LookaheadHudItem>>, fluid_props: Res<FluidStaticProps>) {
+    let Ok(mut lookahead_hud_item) = query.get_single_mut() else { return };
+    if lookahead_hud_item.sections.is_empty() {
+        return;
+    }
+    lookahead_hud_item.sections[0].value = format!("Lookahead: {:.3}", fluid_props.lookahead_time);
+}
+
+
+fn update_neighbor_miss_in_hud(mut query: Query<&mut Text, With<NeighborMissHudItem>>, check: Res<NeighborSearchCheck>) {
+    let Ok(mut neighbor_miss_hud_item) = query.get_single_mut() else { return };
+    if neighbor_miss_hud_item.sections.is_empty() {
+        return;
+    }
+    neighbor_miss_hud_item.sections[0].value = if check.enabled {
+        format!("Neighbor miss: {:.2}%", check.miss_rate * 100.)
+    } else {
+        "Neighbor miss: off".to_string()
+    };
+}
+
+
+fn update_grid_overlay_in_hud(mut query: Query<&mut Text, With<GridOverlayHudItem>>, overlay: Res<GridOverlay>) {
+    let Ok(mut grid_overlay_hud_item) = query.get_single_mut() else { return };
+    if grid_overlay_hud_item.sections.is_empty() {
+        return;
+    }
+    grid_overlay_hud_item.sections[0].value = if overlay.enabled {
+        format!(
+            "Grid: cell {:.3}, {} non-empty, {} mismatched",
+            overlay.cell_size,
+            overlay.non_empty_cells,
+            overlay.mismatched,
+        )
+    } else {
+        "Grid: off".to_string()
+    };
+}
+
+
+fn update_force_toggles_in_hud(mut query: Query<&mut Text, With<ForceTogglesHudItem>>, toggles: Res<ForceToggles>) {
+    let Ok(mut force_toggles_hud_item) = query.get_single_mut() else { return };
+    if force_toggles_hud_item.sections.is_empty() {
+        return;
+    }
+    let label = |enabled: bool, name: &'static str| if enabled { name } else { "-" };
+    force_toggles_hud_item.sections[0].value = format!(
+        "Forces: {} {} {} {}",
+        label(toggles.pressure, "P"),
+        label(toggles.near_pressure, "nP"),
+        label(toggles.viscosity, "V"),
+        label(toggles.gravity, "G"),
+    );
+}
+
+
+fn update_particle_count_in_hud(mut query: Query<&mut Text, With<ParticleCountHudItem>>, capacity: Res<FluidCapacity>) {
+    let Ok(mut particle_count_hud_item) = query.get_single_mut() else { return };
+    if particle_count_hud_item.sections.is_empty() {
+        return;
+    }
+    let section = &mut particle_count_hud_item.sections[0];
+    section.value = format!(
+        "Particles: {}/{} ({} free)",
+        capacity.num_particles, capacity.max_particles, capacity.get_free(),
+    );
+    if capacity.rejected {
+        section.value += " (full)";
+        section.style.color = WARNING_TEXT_COLOR;
+    } else {
+        section.style.color = TEXT_COLOR;
+    }
+}
+
+
+fn update_color_legend_in_hud(mut query: Query<&mut Text, With<ColorLegendHudItem>>, settings: Res<ColorSettings>) {
+    let Ok(mut color_legend_hud_item) = query.get_single_mut() else { return };
+    if color_legend_hud_item.sections.is_empty() {
+        return;
+    }
+    color_legend_hud_item.sections[0].value = settings.get_legend();
+}
+
+
+fn update_cursor_mode_in_hud(mut query: Query<&mut Text, With<CursorModeHudItem>>, cursor: Res<WorldCursor>) {
+    let Ok(mut cursor_mode_hud_item) = query.get_single_mut() else { return };
+    if cursor_mode_hud_item.sections.is_empty() {
+        return;
+    }
+    cursor_mode_hud_item.sections[0].value = cursor.get_legend();
+}
+
+
+fn update_walls_in_hud(mut query: Query<&mut Text, With<WallsHudItem>>, walls: Res<WallProps>) {
+    let Ok(mut walls_hud_item) = query.get_single_mut() else { return };
+    if walls_hud_item.sections.is_empty() {
+        return;
+    }
+    walls_hud_item.sections[0].value = format!("Floor friction: {:.2}", walls.floor_friction);
+}
+
+
+fn update_compute_backend_in_hud(mut query: Query<&mut Text, With<ComputeBackendHudItem>>, status: Res<ComputeBackendStatus>) {
+    let Ok(mut compute_backend_hud_item) = query.get_single_mut() else { return };
+    if compute_backend_hud_item.sections.is_empty() {
+        return;
+    }
+    let section = &mut compute_backend_hud_item.sections[0];
+    section.value = format!("Compute: {}", status.get_name());
+    section.style.color = if *status == ComputeBackendStatus::Failed { WARNING_TEXT_COLOR } else { TEXT_COLOR };
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smoothing_radius_stays_positive() {
+        for smoothing_radius in [-1., 0., 0.01] {
+            let fluid_props = clamp_fluid_props(&FluidStaticProps { smoothing_radius, ..default() });
+            assert_eq!(fluid_props.smoothing_radius, SMOOTHING_RADIUS_MIN);
+        }
+        let fluid_props = clamp_fluid_props(&FluidStaticProps { smoothing_radius: 0.4, ..default() });
+        assert_eq!(fluid_props.smoothing_radius, 0.4);
+    }
+
+    #[test]
+    fn scalars_stay_non_negative() {
+        let fluid_props = clamp_fluid_props(&FluidStaticProps {
+            target_density: -1.,
+            pressure_scalar: -2.,
+            near_pressure_scalar: -3.,
+            viscosity_strength: -4.,
+            cohesion_strength: -5.,
+            lookahead_time: -6.,
+            max_speed: -7.,
+            thermal_diffusion: -8.,
+            buoyancy: -9.,
+            ..default()
+        });
+        assert_eq!(fluid_props.target_density, 0.);
+        assert_eq!(fluid_props.pressure_scalar, 0.);
+        assert_eq!(fluid_props.near_pressure_scalar, 0.);
+        assert_eq!(fluid_props.viscosity_strength, 0.);
+        assert_eq!(fluid_props.cohesion_strength, 0.);
+        assert_eq!(fluid_props.lookahead_time, 0.);
+        assert_eq!(fluid_props.max_speed, 0.);
+        assert_eq!(fluid_props.thermal_diffusion, 0.);
+        assert_eq!(fluid_props.buoyancy, 0.);
+    }
+
+    #[test]
+    fn xsph_stays_a_fraction() {
+        assert_eq!(clamp_fluid_props(&FluidStaticProps { xsph_strength: -0.5, ..default() }).xsph_strength, 0.);
+        assert_eq!(clamp_fluid_props(&FluidStaticProps { xsph_strength: 1.5, ..default() }).xsph_strength, 1.);
+        assert_eq!(clamp_fluid_props(&FluidStaticProps { xsph_strength: 0.3, ..default() }).xsph_strength, 0.3);
+    }
+
+    #[test]
+    fn defaults_are_already_in_range() {
+        assert_eq!(clamp_fluid_props(&FluidStaticProps::default()), FluidStaticProps::default());
+    }
 }
@@ -1,46 +1,174 @@
 mod helpers;
+mod config;
+mod presets;
 mod state;
 mod schedule;
 mod debug;
 mod camera;
 mod menu;
 mod hud;
+mod settings_panel;
 mod fluid_container;
+mod container_handles;
 mod field;
 mod gravity;
+mod force_toggles;
+mod paddle;
+mod obstacles;
+mod emitter;
+mod drain;
+mod thermal;
+mod shake;
+mod rigid_ball;
+mod trails;
+mod world_cursor;
+mod snapshot;
+mod picking;
+mod compute_tuning;
+mod gpu_sort;
 mod fluid_compute;
+mod particle_color;
+mod metaballs;
+mod still_render;
+mod export;
+mod record;
+mod soak;
+mod bench;
 
 use bevy::prelude::*;
 
 use menu::MenuPlugin;
 use state::StatePlugin;
+use config::ConfigPlugin;
+use presets::PresetsPlugin;
 use schedule::SchedulePlugin;
 use debug::DebugPlugin;
 use camera::CameraPlugin;
 use hud::HudPlugin;
+use settings_panel::SettingsPanelPlugin;
 use fluid_container::GizmoPlugin;
+use container_handles::ContainerHandlesPlugin;
 use field::FieldPlugin;
 use gravity::GravityPlugin;
-use fluid_compute::FluidPlugin;
+use force_toggles::ForceTogglesPlugin;
+use paddle::PaddlePlugin;
+use obstacles::ObstaclesPlugin;
+use emitter::EmitterPlugin;
+use drain::DrainPlugin;
+use thermal::ThermalPlugin;
+use shake::ShakePlugin;
+use rigid_ball::RigidBallPlugin;
+use trails::TrailsPlugin;
+use world_cursor::WorldCursorPlugin;
+use snapshot::SnapshotPlugin;
+use picking::PickingPlugin;
+use fluid_compute::{FluidPlugin, FluidRandomSeed, NeighborSearch};
+use particle_color::ParticleColorPlugin;
+use metaballs::MetaballsPlugin;
+use still_render::StillRenderPlugin;
+use export::ExportPlugin;
+use record::RecordPlugin;
 
 
 fn main() {
-    App::new()
+    if std::env::args().any(|arg| arg == "--soak") {
+        let passed = soak::run_soak_test();
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(it) = args.iter().position(|arg| arg == "--shutdown-cycles") {
+        let Some(cycles) = args.get(it + 1).and_then(|arg| arg.parse::<usize>().ok()) else {
+            println!("Usage: --shutdown-cycles <cycles>");
+            std::process::exit(2);
+        };
+        let passed = soak::run_shutdown_cycles(cycles);
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+    if let Some(it) = args.iter().position(|arg| arg == "--headless") {
+        let usage = "Usage: --headless <steps> [bitonic|counting] [<x> <y> <z>]";
+        let Some(steps) = args.get(it + 1).and_then(|arg| arg.parse::<usize>().ok()) else {
+            println!("{}", usage);
+            std::process::exit(2);
+        };
+        let neighbor_search = match args.get(it + 2).map(String::as_str) {
+            None | Some("bitonic") => NeighborSearch::Bitonic,
+            Some("counting") => NeighborSearch::CountingSort,
+            Some(_) => {
+                println!("{}", usage);
+                std::process::exit(2);
+            },
+        };
+        let shape: Vec<u32> = args.iter().skip(it + 3).take(3).filter_map(|arg| arg.parse().ok()).collect();
+        let shape = (shape.len() == 3).then(|| UVec3::new(shape[0], shape[1], shape[2]));
+        let result = bench::run_headless_bench(steps, neighbor_search, shape);
+        result.print();
+        std::process::exit(if result.finite { 0 } else { 1 });
+    }
+    let record = args.iter().position(|arg| arg == "--record").map(|it| {
+        let dir = args.get(it + 1);
+        let frames = args.get(it + 2).and_then(|arg| arg.parse::<u32>().ok());
+        let (Some(dir), Some(frames)) = (dir, frames) else {
+            println!("Usage: --record <dir> <frames>");
+            std::process::exit(2);
+        };
+        RecordPlugin { dir: dir.into(), frames }
+    });
+    let seed = args.iter().position(|arg| arg == "--seed").map(|it| {
+        let Some(seed) = args.get(it + 1).and_then(|arg| arg.parse::<u64>().ok()) else {
+            println!("Usage: --seed <u64>");
+            std::process::exit(2);
+        };
+        seed
+    });
+
+    let mut app = App::new();
+    app
+        .add_plugins(DefaultPlugins)
         .add_plugins((
-            DefaultPlugins,
             // Misc.
+            ConfigPlugin,
+            PresetsPlugin,
             StatePlugin,
             SchedulePlugin,
             DebugPlugin,
+            StillRenderPlugin,
+            ExportPlugin,
+        ))
+        .add_plugins((
             // World defaults
             CameraPlugin,
             MenuPlugin,
             HudPlugin,
+            SettingsPanelPlugin,
             GizmoPlugin,
+            ContainerHandlesPlugin,
             FieldPlugin,
             GravityPlugin,
-            // Game logic
-            FluidPlugin,
+            ForceTogglesPlugin,
+            PaddlePlugin,
+            ObstaclesPlugin,
+            EmitterPlugin,
+            DrainPlugin,
+            ThermalPlugin,
+            ShakePlugin,
         ))
-        .run();
+        .add_plugins((
+            // Game logic
+            FluidPlugin::default(),
+            ParticleColorPlugin,
+            MetaballsPlugin,
+            RigidBallPlugin,
+            TrailsPlugin,
+            WorldCursorPlugin,
+            SnapshotPlugin,
+            PickingPlugin,
+        ));
+    if let Some(record) = record {
+        app.add_plugins(record);
+    }
+    // Over the config file
+    if let Some(seed) = seed {
+        app.insert_resource(FluidRandomSeed(seed));
+    }
+    app.run();
 }
@@ -1,11 +1,31 @@
 use bevy::{app::AppExit, prelude::*};
 
 use crate::state::GameState;
+use crate::gravity::Gravity;
+use crate::fluid_compute::{FluidCapacity, FluidScenario, FluidSpawnCount, RebuildWorkerEvent};
+use crate::presets::{LoadPreset, PresetStore, PRESET_SLOTS};
 
 const TEXT_COLOR: Color = Color::rgb(0.9, 0.9, 0.9);
 const NORMAL_BUTTON: Color = Color::rgb(0.15, 0.15, 0.15);
 const HOVERED_BUTTON: Color = Color::rgb(0.25, 0.25, 0.25);
 const PRESSED_BUTTON: Color = Color::rgb(0.35, 0.35, 0.35);
+const FOCUSED_BUTTON: Color = Color::rgb(0.2, 0.3, 0.45);
+const PAUSE_OVERLAY_COLOR: Color = Color::rgba(0., 0., 0., 0.5);
+
+// Order in which the focus moves through the buttons
+const MENU_BUTTON_ORDER: [MenuButtonAction; 7] = [
+    MenuButtonAction::Play,
+    MenuButtonAction::Scenario,
+    MenuButtonAction::Preset(0),
+    MenuButtonAction::Preset(1),
+    MenuButtonAction::Preset(2),
+    MenuButtonAction::Settings,
+    MenuButtonAction::Quit,
+];
+
+// Cycled through by the settings screen buttons
+const PARTICLE_COUNT_OPTIONS: [Option<u32>; 4] = [None, Some(1000), Some(2500), Some(10000)];
+const GRAVITY_OPTIONS: [f32; 4] = [9.8, 4.9, 19.6, 0.];
 
 
 #[derive(Component, Debug)]
@@ -13,26 +33,95 @@ pub struct MainMenuItem;
 
 
 #[derive(Component, Debug)]
+struct SettingsMenuItem;
+
+
+#[derive(Component, Debug)]
+struct PauseMenuItem;
+
+
+#[derive(Component, PartialEq, Eq, Clone, Copy, Debug)]
 enum MenuButtonAction {
     Play,
+    Scenario,
+    /// Recalls the preset slot
+    Preset(usize),
+    Settings,
+    ParticleCount,
+    GravityStrength,
+    /// Leaves the settings screen
+    Back,
+    /// Closes the pause overlay
+    Resume,
+    /// Leaves the game for the menu, the fluid is reset
+    MainMenu,
     Quit,
 }
 
 
+#[derive(Component, Debug)]
+struct ScenarioButtonText;
+
+
+#[derive(Component, Debug)]
+struct ParticleCountButtonText;
+
+
+#[derive(Component, Debug)]
+struct GravityButtonText;
+
+
+fn get_particle_count_label(spawn_count: &FluidSpawnCount) -> String {
+    match spawn_count.0 {
+        Some(count) => format!("Particles: {}", count),
+        None => "Particles: default".to_string(),
+    }
+}
+
+
+fn get_gravity_label(gravity: &Gravity) -> String {
+    format!("Gravity: {:.1}", -gravity.value.y)
+}
+
+
+fn get_next_option<T: PartialEq + Copy>(options: &[T], current: T) -> T {
+    let index = options.iter().position(|option| *option == current).map_or(0, |index| index + 1);
+    options[index % options.len()]
+}
+
+
+/// Index into `MENU_BUTTON_ORDER` of the button selected by keyboard or gamepad
+#[derive(Resource, Default, Debug)]
+struct MenuFocus(usize);
+
+
 pub struct MenuPlugin;
 
 
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut App) {
         app
+            .init_resource::<MenuFocus>()
             .add_systems(Startup, setup_menu)
-            .add_systems(OnExit(GameState::Menu), despawn_menu)
-            .add_systems(Update, (button_system, menu_action).chain());
+            .add_systems(OnTransition { from: GameState::Menu, to: GameState::InGame }, despawn_menu)
+            .add_systems(OnEnter(GameState::Settings), (hide_menu, setup_settings_menu))
+            .add_systems(OnExit(GameState::Settings), (show_menu, despawn_settings_menu))
+            .add_systems(OnEnter(GameState::Paused), setup_pause_menu)
+            .add_systems(OnExit(GameState::Paused), despawn_pause_menu)
+            .add_systems(OnTransition { from: GameState::Paused, to: GameState::Menu }, (reset_menu_focus, setup_menu))
+            .add_systems(Update, (
+                menu_navigation.run_if(in_state(GameState::Menu)),
+                button_system,
+                menu_action,
+                update_scenario_button_text,
+                update_settings_button_text,
+                request_worker_rebuild.run_if(in_state(GameState::Settings)),
+            ).chain());
     }
 }
 
 
-fn setup_menu(mut commands: Commands) {
+fn setup_menu(mut commands: Commands, scenario: Res<FluidScenario>, presets: Res<PresetStore>) {
     // Common style for all buttons on the screen
     let button_style = Style {
         width: Val::Px(250.0),
@@ -47,6 +136,19 @@ fn setup_menu(mut commands: Commands) {
         color: TEXT_COLOR,
         ..default()
     };
+    let preset_button_style = Style {
+        width: Val::Px(130.0),
+        height: Val::Px(45.0),
+        margin: UiRect::all(Val::Px(10.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+    let preset_button_text_style = TextStyle {
+        font_size: 24.0,
+        color: TEXT_COLOR,
+        ..default()
+    };
 
     commands.spawn((
         NodeBundle {
@@ -81,8 +183,11 @@ fn setup_menu(mut commands: Commands) {
                 ..default()
             }));
 
-            // Display two buttons for each action available from the main menu:
+            // Display a button for each action available from the main menu:
             // - start
+            // - scenario
+            // - presets
+            // - settings
             // - quit
             parent.spawn((
                 ButtonBundle {
@@ -94,6 +199,47 @@ fn setup_menu(mut commands: Commands) {
             )).with_children(|parent| {
                 parent.spawn(TextBundle::from_section("Start", button_text_style.clone()));
             });
+            parent.spawn((
+                ButtonBundle {
+                    style: button_style.clone(),
+                    background_color: NORMAL_BUTTON.into(),
+                    ..default()
+                },
+                MenuButtonAction::Scenario,
+            )).with_children(|parent| {
+                parent.spawn((
+                    TextBundle::from_section(scenario.get_name(), button_text_style.clone()),
+                    ScenarioButtonText,
+                ));
+            });
+            parent.spawn(NodeBundle::default()).with_children(|parent| {
+                for slot in 0..PRESET_SLOTS {
+                    let label = match presets.load(slot) {
+                        Some(_) => format!("Preset {}", slot + 1),
+                        None => "Empty".to_string(),
+                    };
+                    parent.spawn((
+                        ButtonBundle {
+                            style: preset_button_style.clone(),
+                            background_color: NORMAL_BUTTON.into(),
+                            ..default()
+                        },
+                        MenuButtonAction::Preset(slot),
+                    )).with_children(|parent| {
+                        parent.spawn(TextBundle::from_section(label, preset_button_text_style.clone()));
+                    });
+                }
+            });
+            parent.spawn((
+                ButtonBundle {
+                    style: button_style.clone(),
+                    background_color: NORMAL_BUTTON.into(),
+                    ..default()
+                },
+                MenuButtonAction::Settings,
+            )).with_children(|parent| {
+                parent.spawn(TextBundle::from_section("Settings", button_text_style.clone()));
+            });
             parent.spawn((
                 ButtonBundle {
                     style: button_style,
@@ -109,34 +255,354 @@ fn setup_menu(mut commands: Commands) {
 }
 
 
-// This system handles changing all buttons color based on mouse interaction
-fn button_system(mut query: Query<(&Interaction, &mut BackgroundColor), (Changed<Interaction>, With<Button>)>) {
-    for (interaction, mut color) in query.iter_mut() {
+fn setup_settings_menu(mut commands: Commands, spawn_count: Res<FluidSpawnCount>, gravity: Res<Gravity>) {
+    let button_style = Style {
+        width: Val::Px(400.0),
+        height: Val::Px(65.0),
+        margin: UiRect::all(Val::Px(20.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+    let button_text_style = TextStyle {
+        font_size: 40.0,
+        color: TEXT_COLOR,
+        ..default()
+    };
+
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            ..default()
+        },
+        SettingsMenuItem,
+    )).with_children(|parent| {
+        parent.spawn(NodeBundle {
+            style: Style {
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            ..default()
+        }).with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+            "Settings",
+            TextStyle {
+                font_size: 80.0,
+                color: TEXT_COLOR,
+                ..default()
+            }).with_style(Style {
+                margin: UiRect::all(Val::Px(50.0)),
+                ..default()
+            }));
+
+            // The solver is GPU only, so there is no solver choice
+            parent.spawn((
+                ButtonBundle {
+                    style: button_style.clone(),
+                    background_color: NORMAL_BUTTON.into(),
+                    ..default()
+                },
+                MenuButtonAction::ParticleCount,
+            )).with_children(|parent| {
+                parent.spawn((
+                    TextBundle::from_section(get_particle_count_label(&spawn_count), button_text_style.clone()),
+                    ParticleCountButtonText,
+                ));
+            });
+            parent.spawn((
+                ButtonBundle {
+                    style: button_style.clone(),
+                    background_color: NORMAL_BUTTON.into(),
+                    ..default()
+                },
+                MenuButtonAction::GravityStrength,
+            )).with_children(|parent| {
+                parent.spawn((
+                    TextBundle::from_section(get_gravity_label(&gravity), button_text_style.clone()),
+                    GravityButtonText,
+                ));
+            });
+            parent.spawn((
+                ButtonBundle {
+                    style: button_style,
+                    background_color: NORMAL_BUTTON.into(),
+                    ..default()
+                },
+                MenuButtonAction::Back,
+            )).with_children(|parent| {
+                parent.spawn(TextBundle::from_section("Back", button_text_style));
+            });
+        });
+    });
+}
+
+
+/// Drawn over the frozen simulation, the pause state stops every compute pass
+fn setup_pause_menu(mut commands: Commands) {
+    let button_style = Style {
+        width: Val::Px(300.0),
+        height: Val::Px(65.0),
+        margin: UiRect::all(Val::Px(20.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+    let button_text_style = TextStyle {
+        font_size: 40.0,
+        color: TEXT_COLOR,
+        ..default()
+    };
+
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            background_color: PAUSE_OVERLAY_COLOR.into(),
+            z_index: ZIndex::Global(1),
+            ..default()
+        },
+        PauseMenuItem,
+    )).with_children(|parent| {
+        parent.spawn(TextBundle::from_section(
+        "Paused",
+        TextStyle {
+            font_size: 80.0,
+            color: TEXT_COLOR,
+            ..default()
+        }).with_style(Style {
+            margin: UiRect::all(Val::Px(50.0)),
+            ..default()
+        }));
+        parent.spawn((
+            ButtonBundle {
+                style: button_style.clone(),
+                background_color: NORMAL_BUTTON.into(),
+                ..default()
+            },
+            MenuButtonAction::Resume,
+        )).with_children(|parent| {
+            parent.spawn(TextBundle::from_section("Resume", button_text_style.clone()));
+        });
+        parent.spawn((
+            ButtonBundle {
+                style: button_style,
+                background_color: NORMAL_BUTTON.into(),
+                ..default()
+            },
+            MenuButtonAction::MainMenu,
+        )).with_children(|parent| {
+            parent.spawn(TextBundle::from_section("Main Menu", button_text_style));
+        });
+    });
+}
+
+
+// This system handles changing all buttons color based on mouse interaction and keyboard/gamepad focus
+fn button_system(
+    mut query: Query<(&Interaction, &MenuButtonAction, &mut BackgroundColor), With<Button>>,
+    focus: Res<MenuFocus>,
+) {
+    for (interaction, menu_button_action, mut color) in query.iter_mut() {
+        let focused = MENU_BUTTON_ORDER[focus.0] == *menu_button_action;
         *color = match *interaction {
             Interaction::Pressed => PRESSED_BUTTON.into(),
             Interaction::Hovered => HOVERED_BUTTON.into(),
+            _ if focused => FOCUSED_BUTTON.into(),
             _ => NORMAL_BUTTON.into(),
         }
     }
 }
 
 
+fn menu_navigation(
+    mut focus: ResMut<MenuFocus>,
+    mut app_exit_events: EventWriter<AppExit>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut scenario: ResMut<FluidScenario>,
+    mut spawn_count: ResMut<FluidSpawnCount>,
+    mut gravity: ResMut<Gravity>,
+    mut preset_events: EventWriter<LoadPreset>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    gamepad_input: Res<ButtonInput<GamepadButton>>,
+    gamepads: Res<Gamepads>,
+) {
+    let gamepad_pressed = |button_type: GamepadButtonType| {
+        gamepads.iter().any(|gamepad| gamepad_input.just_pressed(GamepadButton::new(gamepad, button_type)))
+    };
+
+    let num_buttons = MENU_BUTTON_ORDER.len();
+    if keyboard_input.just_pressed(KeyCode::ArrowUp) || gamepad_pressed(GamepadButtonType::DPadUp) {
+        focus.0 = (focus.0 + num_buttons - 1) % num_buttons;
+    } else if keyboard_input.just_pressed(KeyCode::ArrowDown) || gamepad_pressed(GamepadButtonType::DPadDown) {
+        focus.0 = (focus.0 + 1) % num_buttons;
+    } else if keyboard_input.any_just_pressed([KeyCode::Enter, KeyCode::NumpadEnter])
+        || gamepad_pressed(GamepadButtonType::South) {
+        apply_menu_action(
+            MENU_BUTTON_ORDER[focus.0],
+            &mut app_exit_events,
+            &mut next_state,
+            &mut scenario,
+            &mut spawn_count,
+            &mut gravity,
+            &mut preset_events,
+        );
+    }
+}
+
+
 fn menu_action(
     query: Query<(&Interaction, &MenuButtonAction), (Changed<Interaction>, With<Button>)>,
     mut app_exit_events: EventWriter<AppExit>,
     mut next_state: ResMut<NextState<GameState>>,
+    mut scenario: ResMut<FluidScenario>,
+    mut spawn_count: ResMut<FluidSpawnCount>,
+    mut gravity: ResMut<Gravity>,
+    mut preset_events: EventWriter<LoadPreset>,
 ) {
     for (interaction, menu_button_action) in query.iter() {
         if *interaction == Interaction::Pressed {
-            match menu_button_action {
-                MenuButtonAction::Quit => { app_exit_events.send(AppExit); },
-                MenuButtonAction::Play => { next_state.set(GameState::InGame); },
+            apply_menu_action(
+                *menu_button_action,
+                &mut app_exit_events,
+                &mut next_state,
+                &mut scenario,
+                &mut spawn_count,
+                &mut gravity,
+                &mut preset_events,
+            );
+        }
+    }
+}
+
+
+fn apply_menu_action(
+    menu_button_action: MenuButtonAction,
+    app_exit_events: &mut EventWriter<AppExit>,
+    next_state: &mut NextState<GameState>,
+    scenario: &mut FluidScenario,
+    spawn_count: &mut FluidSpawnCount,
+    gravity: &mut Gravity,
+    preset_events: &mut EventWriter<LoadPreset>,
+) {
+    match menu_button_action {
+        MenuButtonAction::Quit => { app_exit_events.send(AppExit); },
+        MenuButtonAction::Play => { next_state.set(GameState::InGame); },
+        MenuButtonAction::Scenario => { *scenario = scenario.next(); },
+        MenuButtonAction::Preset(slot) => { preset_events.send(LoadPreset(slot)); },
+        MenuButtonAction::Settings => { next_state.set(GameState::Settings); },
+        MenuButtonAction::ParticleCount => { spawn_count.0 = get_next_option(&PARTICLE_COUNT_OPTIONS, spawn_count.0); },
+        MenuButtonAction::GravityStrength => { gravity.value.y = -get_next_option(&GRAVITY_OPTIONS, -gravity.value.y); },
+        MenuButtonAction::Back => { next_state.set(GameState::Menu); },
+        MenuButtonAction::Resume => { next_state.set(GameState::InGame); },
+        MenuButtonAction::MainMenu => { next_state.set(GameState::Menu); },
+    }
+}
+
+
+fn update_scenario_button_text(mut query: Query<&mut Text, With<ScenarioButtonText>>, scenario: Res<FluidScenario>) {
+    if !scenario.is_changed() {
+        return;
+    }
+    let Ok(mut scenario_button_text) = query.get_single_mut() else { return };
+    if scenario_button_text.sections.is_empty() {
+        return;
+    }
+    scenario_button_text.sections[0].value = scenario.get_name().to_string();
+}
+
+
+fn update_settings_button_text(
+    mut count_query: Query<&mut Text, (With<ParticleCountButtonText>, Without<GravityButtonText>)>,
+    mut gravity_query: Query<&mut Text, With<GravityButtonText>>,
+    spawn_count: Res<FluidSpawnCount>,
+    gravity: Res<Gravity>,
+) {
+    if spawn_count.is_changed() {
+        if let Ok(mut count_button_text) = count_query.get_single_mut() {
+            if !count_button_text.sections.is_empty() {
+                count_button_text.sections[0].value = get_particle_count_label(&spawn_count);
+            }
+        }
+    }
+    if gravity.is_changed() {
+        if let Ok(mut gravity_button_text) = gravity_query.get_single_mut() {
+            if !gravity_button_text.sections.is_empty() {
+                gravity_button_text.sections[0].value = get_gravity_label(&gravity);
             }
         }
     }
 }
 
 
+/// The GPU buffers are sized at startup, a count past them needs a new worker
+fn request_worker_rebuild(
+    mut rebuild_events: EventWriter<RebuildWorkerEvent>,
+    spawn_count: Res<FluidSpawnCount>,
+    capacity: Res<FluidCapacity>,
+) {
+    if !spawn_count.is_changed() {
+        return;
+    }
+    if let Some(count) = spawn_count.0 {
+        if count > capacity.get_first_boundary() {
+            rebuild_events.send(RebuildWorkerEvent { num_particles: count });
+        }
+    }
+}
+
+
+/// The main menu stays spawned under the settings screen, only hidden
+fn hide_menu(mut query: Query<&mut Style, With<MainMenuItem>>) {
+    for mut style in query.iter_mut() {
+        style.display = Display::None;
+    }
+}
+
+
+fn show_menu(mut query: Query<&mut Style, With<MainMenuItem>>) {
+    for mut style in query.iter_mut() {
+        style.display = Display::Flex;
+    }
+}
+
+
+fn despawn_settings_menu(mut commands: Commands, query: Query<Entity, With<SettingsMenuItem>>) {
+    for entity in query.iter() {
+        if let Some(entity_commands) = commands.get_entity(entity) {
+            entity_commands.despawn_recursive();
+        }
+    }
+}
+
+
+fn despawn_pause_menu(mut commands: Commands, query: Query<Entity, With<PauseMenuItem>>) {
+    for entity in query.iter() {
+        if let Some(entity_commands) = commands.get_entity(entity) {
+            entity_commands.despawn_recursive();
+        }
+    }
+}
+
+
+fn reset_menu_focus(mut focus: ResMut<MenuFocus>) {
+    focus.0 = 0;
+}
+
+
 fn despawn_menu(mut commands: Commands, query: Query<Entity, With<MainMenuItem>>) {
     for entity in query.iter() {
         if let Some(entity_commands) = commands.get_entity(entity) {
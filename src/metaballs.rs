@@ -0,0 +1,233 @@
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::{AsBindGroup, Extent3d, ShaderRef, TextureDimension, TextureFormat};
+use bevy::ui::FocusPolicy;
+use bevy::window::PrimaryWindow;
+use bevy_app_compute::prelude::*;
+
+use crate::schedule::InGameSet;
+use crate::field::Theme;
+use crate::camera::Observer;
+use crate::fluid_container::FluidContainer;
+use crate::fluid_compute::{
+    FluidCapacity, FluidParticle, FluidParticleLabel, FluidStaticProps, FluidWorker, PARTICLE_RADIUS,
+};
+
+const METABALLS_SHADER_PATH: &str = "metaballs.wgsl";
+const METABALLS_MODE_KEY: KeyCode = KeyCode::KeyM;
+const METABALLS_DOWNSCALE: f32 = 4.;  // Window pixels per density texel
+const METABALLS_MIN_THRESHOLD: f32 = 0.05;  // For smoothing radii barely above the particle radius
+
+
+/// How the particles are drawn, the simulation is the same either way
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum ParticleRenderMode {
+    #[default]
+    Particles,
+    /// Densities splatted into a screen-space texture and thresholded into a surface
+    Metaballs,
+}
+
+
+/// Fullscreen pass over the density texture, texels at 0.5 sit on the surface
+#[derive(Asset, TypePath, AsBindGroup, Clone, Debug)]
+struct MetaballMaterial {
+    #[uniform(0)]
+    color: Vec4,
+    #[texture(1)]
+    #[sampler(2)]
+    density: Handle<Image>,
+}
+
+
+impl UiMaterial for MetaballMaterial {
+    fn fragment_shader() -> ShaderRef {
+        METABALLS_SHADER_PATH.into()
+    }
+}
+
+
+#[derive(Resource, Debug)]
+struct MetaballTexture(Handle<Image>);
+
+
+#[derive(Resource, Debug)]
+struct MetaballMaterialHandle(Handle<MetaballMaterial>);
+
+
+#[derive(Component, Debug)]
+struct MetaballSurface;
+
+
+pub struct MetaballsPlugin;
+
+
+impl Plugin for MetaballsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_plugins(UiMaterialPlugin::<MetaballMaterial>::default())
+            .init_resource::<ParticleRenderMode>()
+            .add_systems(Startup, setup_metaballs)
+            .add_systems(Update, toggle_render_mode.in_set(InGameSet::UserInput))
+            .add_systems(Update, (apply_render_mode, splat_metaballs).in_set(InGameSet::EntityUpdates))
+            .add_systems(Update, apply_theme_to_metaballs);
+    }
+}
+
+
+fn setup_metaballs(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<MetaballMaterial>>,
+    theme: Res<Theme>,
+) {
+    // Resized to the window on the first splat
+    let density = images.add(Image::new_fill(
+        Extent3d { width: 1, height: 1, depth_or_array_layers: 1 },
+        TextureDimension::D2,
+        &[0],
+        TextureFormat::R8Unorm,
+        RenderAssetUsages::default(),
+    ));
+    let material = materials.add(MetaballMaterial {
+        color: Vec4::from_array(theme.particle_base.as_linear_rgba_f32()),
+        density: density.clone(),
+    });
+    commands.insert_resource(MetaballTexture(density));
+    commands.insert_resource(MetaballMaterialHandle(material.clone()));
+
+    commands.spawn((
+        MaterialNodeBundle {
+            style: Style {
+                display: Display::None,
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.),
+                height: Val::Percent(100.),
+                ..default()
+            },
+            material,
+            // Below the HUD and never in the way of clicks
+            focus_policy: FocusPolicy::Pass,
+            z_index: ZIndex::Global(-1),
+            ..default()
+        },
+        MetaballSurface,
+    ));
+}
+
+
+fn apply_theme_to_metaballs(
+    mut materials: ResMut<Assets<MetaballMaterial>>,
+    material: Option<Res<MetaballMaterialHandle>>,
+    theme: Res<Theme>,
+) {
+    if !theme.is_changed() {
+        return;
+    }
+    let Some(material) = material else { return };
+    if let Some(material) = materials.get_mut(&material.0) {
+        material.color = Vec4::from_array(theme.particle_base.as_linear_rgba_f32());
+    }
+}
+
+
+fn toggle_render_mode(mut render_mode: ResMut<ParticleRenderMode>, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if !keyboard_input.just_pressed(METABALLS_MODE_KEY) {
+        return;
+    }
+    *render_mode = match *render_mode {
+        ParticleRenderMode::Particles => ParticleRenderMode::Metaballs,
+        ParticleRenderMode::Metaballs => ParticleRenderMode::Particles,
+    };
+}
+
+
+/// Swaps the particle meshes for the surface, emitted particles pick up the mode when spawned
+fn apply_render_mode(
+    mut surface_query: Query<&mut Style, With<MetaballSurface>>,
+    mut particle_query: Query<(&mut Visibility, Ref<FluidParticleLabel>)>,
+    render_mode: Res<ParticleRenderMode>,
+) {
+    let visibility = match *render_mode {
+        ParticleRenderMode::Particles => Visibility::Inherited,
+        ParticleRenderMode::Metaballs => Visibility::Hidden,
+    };
+    for (mut particle_visibility, label) in particle_query.iter_mut() {
+        if render_mode.is_changed() || label.is_added() {
+            *particle_visibility = visibility;
+        }
+    }
+
+    if !render_mode.is_changed() {
+        return;
+    }
+    let Ok(mut style) = surface_query.get_single_mut() else { return };
+    style.display = match *render_mode {
+        ParticleRenderMode::Particles => Display::None,
+        ParticleRenderMode::Metaballs => Display::Flex,
+    };
+}
+
+
+/// Accumulates a kernel the size of the smoothing radius per particle, the threshold is where
+/// a lone particle's splat reaches the particle radius
+fn get_threshold(smoothing_radius: f32) -> f32 {
+    let q = (PARTICLE_RADIUS / smoothing_radius).min(1.);
+    ((1. - q * q) * (1. - q * q)).max(METABALLS_MIN_THRESHOLD)
+}
+
+
+fn splat_metaballs(
+    mut images: ResMut<Assets<Image>>,
+    texture: Res<MetaballTexture>,
+    render_mode: Res<ParticleRenderMode>,
+    worker: Res<AppComputeWorker<FluidWorker>>,
+    capacity: Res<FluidCapacity>,
+    fluid_props: Res<FluidStaticProps>,
+    container: Res<FluidContainer>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<Observer>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+) {
+    if *render_mode != ParticleRenderMode::Metaballs || !worker.ready() {
+        return;
+    }
+    let Ok((camera, camera_transform)) = camera_query.get_single() else { return };
+    let Ok(window) = window_query.get_single() else { return };
+    let Some(image) = images.get_mut(&texture.0) else { return };
+
+    let size = (window.resolution.size() / METABALLS_DOWNSCALE).ceil().as_uvec2().max(UVec2::ONE);
+    let mut density = vec![0f32; (size.x * size.y) as usize];
+    let right = camera_transform.right() * fluid_props.smoothing_radius;
+    let mut splat = |position: Vec3| {
+        let Some(center) = camera.world_to_viewport(camera_transform, position) else { return };
+        let Some(edge) = camera.world_to_viewport(camera_transform, position + right) else { return };
+        let center = center / METABALLS_DOWNSCALE;
+        let radius = center.distance(edge / METABALLS_DOWNSCALE).max(1.);
+        let min = (center - radius).floor().max(Vec2::ZERO).as_uvec2();
+        let max = (center + radius).ceil().min(size.as_vec2() - 1.).max(Vec2::ZERO).as_uvec2();
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let q2 = (Vec2::new(x as f32, y as f32) + 0.5).distance_squared(center) / (radius * radius);
+                if q2 < 1. {
+                    density[(y * size.x + x) as usize] += (1. - q2) * (1. - q2);
+                }
+            }
+        }
+    };
+
+    let particles = worker.read_vec::<FluidParticle>("particles");
+    for particle in particles[..capacity.num_particles as usize].iter() {
+        let position = particle.position.xyz();
+        splat(position);
+        if container.mirror_x {
+            splat(container.mirror_position(position));
+        }
+    }
+
+    // Half the byte range is the threshold, the shader smooths around it
+    let scale = 0.5 / get_threshold(fluid_props.smoothing_radius);
+    if image.width() != size.x || image.height() != size.y {
+        image.resize(Extent3d { width: size.x, height: size.y, depth_or_array_layers: 1 });
+    }
+    image.data = density.iter().map(|value| ((value * scale).min(1.) * 255.) as u8).collect();
+}
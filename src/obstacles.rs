@@ -0,0 +1,171 @@
+use bevy::prelude::*;
+use bevy::core::Pod;
+use bevy_app_compute::prelude::*;
+use bytemuck::Zeroable;
+
+use crate::schedule::InGameSet;
+use crate::fluid_container::FluidContainer;
+
+pub const OBSTACLES_MAX: usize = 16;  // Size of the uniform array
+
+const OBSTACLE_DEMO_RADIUS: f32 = 1.5;
+const OBSTACLE_DEMO_TOGGLE_KEY: KeyCode = KeyCode::KeyO;
+const OBSTACLE_COLOR: Color = Color::YELLOW;
+
+
+#[derive(Clone, Copy, Debug)]
+pub enum Obstacle {
+    Sphere { center: Vec3, radius: f32 },
+    /// Segment from `a` to `b` thickened by `radius`
+    Capsule { a: Vec3, b: Vec3, radius: f32 },
+}
+
+
+impl Obstacle {
+    /// Both shapes are sent as capsules, a sphere has both ends in the center
+    fn get_ext(&self) -> ObstacleExt {
+        let (a, b, radius) = match *self {
+            Obstacle::Sphere { center, radius } => (center, center, radius),
+            Obstacle::Capsule { a, b, radius } => (a, b, radius),
+        };
+        ObstacleExt {
+            a: a.extend(radius),
+            b: b.extend(0.),
+        }
+    }
+}
+
+
+#[derive(ShaderType, Pod, Zeroable, Clone, Copy)]
+#[repr(C)]
+pub struct ObstacleExt {
+    /// First segment end, radius in w
+    pub a: Vec4,
+    pub b: Vec4,
+}
+
+
+#[derive(ShaderType, Pod, Zeroable, Clone, Copy)]
+#[repr(C)]
+pub struct ObstaclesExt {
+    pub count: UVec4,
+    pub items: [ObstacleExt; OBSTACLES_MAX],
+}
+
+
+/// Static shapes the particles are pushed out of
+#[derive(Resource, Clone, Default, Debug)]
+pub struct Obstacles {
+    pub items: Vec<Obstacle>,
+}
+
+
+impl Obstacles {
+    /// Obstacles past `OBSTACLES_MAX` are ignored
+    pub fn get_ext(&self) -> ObstaclesExt {
+        let mut ext = ObstaclesExt::zeroed();
+        for (it, obstacle) in self.items.iter().take(OBSTACLES_MAX).enumerate() {
+            ext.items[it] = obstacle.get_ext();
+        }
+        ext.count.x = self.items.len().min(OBSTACLES_MAX) as u32;
+        ext
+    }
+}
+
+
+pub struct ObstaclesPlugin;
+
+
+impl Plugin for ObstaclesPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<Obstacles>()
+            .add_systems(Update, toggle_demo_obstacle.in_set(InGameSet::UserInput))
+            .add_systems(Update, draw_obstacles.in_set(InGameSet::EntityUpdates));
+    }
+}
+
+
+/// Drops a sphere in the middle of the container to pour the fluid over
+fn toggle_demo_obstacle(
+    mut obstacles: ResMut<Obstacles>,
+    container: Res<FluidContainer>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+) {
+    if !keyboard_input.just_pressed(OBSTACLE_DEMO_TOGGLE_KEY) {
+        return;
+    }
+    if obstacles.items.is_empty() {
+        obstacles.items.push(Obstacle::Sphere {
+            center: container.position,
+            radius: OBSTACLE_DEMO_RADIUS,
+        });
+    } else {
+        obstacles.items.clear();
+    }
+}
+
+
+fn draw_obstacles(mut gizmos: Gizmos, obstacles: Res<Obstacles>) {
+    for obstacle in obstacles.items.iter() {
+        match *obstacle {
+            Obstacle::Sphere { center, radius } => {
+                gizmos.sphere(center, Quat::IDENTITY, radius, OBSTACLE_COLOR);
+            },
+            Obstacle::Capsule { a, b, radius } => {
+                gizmos.sphere(a, Quat::IDENTITY, radius, OBSTACLE_COLOR);
+                gizmos.sphere(b, Quat::IDENTITY, radius, OBSTACLE_COLOR);
+                gizmos.line(a, b, OBSTACLE_COLOR);
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sphere_packs_as_a_point_capsule() {
+        let ext = Obstacle::Sphere { center: Vec3::new(1., 2., 3.), radius: 0.5 }.get_ext();
+        assert_eq!(ext.a, Vec4::new(1., 2., 3., 0.5));
+        assert_eq!(ext.b, Vec4::new(1., 2., 3., 0.));
+    }
+
+    #[test]
+    fn capsule_packs_its_ends_and_radius() {
+        let ext = Obstacle::Capsule { a: Vec3::X, b: Vec3::new(0., -1., 4.), radius: 0.25 }.get_ext();
+        assert_eq!(ext.a, Vec4::new(1., 0., 0., 0.25));
+        assert_eq!(ext.b, Vec4::new(0., -1., 4., 0.));
+    }
+
+    #[test]
+    fn packs_in_order_with_the_count() {
+        let obstacles = Obstacles {
+            items: vec![
+                Obstacle::Sphere { center: Vec3::ZERO, radius: 1. },
+                Obstacle::Capsule { a: Vec3::Y, b: Vec3::Z, radius: 2. },
+            ],
+        };
+        let ext = obstacles.get_ext();
+        assert_eq!(ext.count.x, 2);
+        assert_eq!(ext.items[0].a.w, 1.);
+        assert_eq!(ext.items[1].a, Vec4::new(0., 1., 0., 2.));
+        // Unused slots stay zeroed
+        assert_eq!(ext.items[2].a, Vec4::ZERO);
+    }
+
+    #[test]
+    fn drops_the_obstacles_past_the_array() {
+        let obstacles = Obstacles {
+            items: (0..OBSTACLES_MAX + 3)
+                .map(|it| Obstacle::Sphere { center: Vec3::splat(it as f32), radius: 1. })
+                .collect(),
+        };
+        let ext = obstacles.get_ext();
+        assert_eq!(ext.count.x, OBSTACLES_MAX as u32);
+        assert_eq!(ext.items[OBSTACLES_MAX - 1].a.x, (OBSTACLES_MAX - 1) as f32);
+        assert_eq!(Obstacles::default().get_ext().count.x, 0);
+    }
+}
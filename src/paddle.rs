@@ -0,0 +1,118 @@
+use bevy::prelude::*;
+use bevy::core::Pod;
+use bevy_app_compute::prelude::*;
+use bytemuck::Zeroable;
+
+use crate::schedule::InGameSet;
+use crate::fluid_container::FluidContainer;
+
+const PADDLE_SIZE: Vec3 = Vec3::new(0.5, 3., 9.);
+const PADDLE_POSITION: Vec3 = Vec3::new(-6., 0., 0.);
+const PADDLE_SPEED: f32 = 6.;
+const PADDLE_TOGGLE_KEY: KeyCode = KeyCode::KeyB;
+
+
+#[derive(ShaderType, Pod, Zeroable, Clone, Copy)]
+#[repr(C)]
+pub struct PaddleExt {
+    pub position: Vec4,
+    pub half_size: Vec4,
+    pub velocity: Vec4,
+}
+
+
+/// Rigid box moved with the arrow keys, particles bounce off it and pick up its velocity
+#[derive(Resource, Clone)]
+pub struct Paddle {
+    pub enabled: bool,
+    pub position: Vec3,
+    pub size: Vec3,
+    pub velocity: Vec3,
+}
+
+
+impl Default for Paddle {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            position: PADDLE_POSITION,
+            size: PADDLE_SIZE,
+            velocity: Vec3::ZERO,
+        }
+    }
+}
+
+
+impl Paddle {
+    pub fn get_ext(&self) -> PaddleExt {
+        // A disabled paddle has no volume, so nothing can be inside it
+        let half_size = if self.enabled { self.size / 2. } else { Vec3::ZERO };
+        PaddleExt {
+            position: self.position.extend(0.),
+            half_size: half_size.extend(0.),
+            velocity: self.velocity.extend(0.),
+        }
+    }
+}
+
+
+pub struct PaddlePlugin;
+
+
+impl Plugin for PaddlePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<Paddle>()
+            .add_systems(Update, move_paddle.in_set(InGameSet::UserInput))
+            .add_systems(Update, draw_paddle.in_set(InGameSet::EntityUpdates));
+    }
+}
+
+
+fn move_paddle(
+    mut paddle: ResMut<Paddle>,
+    container: Res<FluidContainer>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+) {
+    if keyboard_input.just_pressed(PADDLE_TOGGLE_KEY) {
+        paddle.enabled = !paddle.enabled;
+    }
+
+    let mut direction = Vec3::ZERO;
+    if keyboard_input.pressed(KeyCode::ArrowLeft) {
+        direction.x -= 1.;
+    }
+    if keyboard_input.pressed(KeyCode::ArrowRight) {
+        direction.x += 1.;
+    }
+    if keyboard_input.pressed(KeyCode::ArrowUp) {
+        direction.y += 1.;
+    }
+    if keyboard_input.pressed(KeyCode::ArrowDown) {
+        direction.y -= 1.;
+    }
+    if !paddle.enabled {
+        direction = Vec3::ZERO;
+    }
+
+    // Keep the paddle inside the container, the velocity is what was actually travelled
+    let half_extents = ((container.size - paddle.size) / 2.).max(Vec3::ZERO);
+    let previous_position = paddle.position;
+    paddle.position = (paddle.position + direction * PADDLE_SPEED * time.delta_seconds())
+        .clamp(container.position - half_extents, container.position + half_extents);
+    paddle.velocity = if time.delta_seconds() > 0. {
+        (paddle.position - previous_position) / time.delta_seconds()
+    } else {
+        Vec3::ZERO
+    };
+}
+
+
+fn draw_paddle(mut gizmos: Gizmos, paddle: Res<Paddle>) {
+    if !paddle.enabled {
+        return;
+    }
+    let transform = Transform::from_translation(paddle.position).with_scale(paddle.size);
+    gizmos.cuboid(transform, Color::ORANGE);
+}
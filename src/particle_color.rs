@@ -0,0 +1,251 @@
+use bevy::prelude::*;
+use bevy_app_compute::prelude::*;
+
+use crate::schedule::InGameSet;
+use crate::field::Theme;
+use crate::fluid_compute::{
+    FluidParticle, FluidParticleLabel, FluidParticlesInitial, FluidStaticProps, FluidTypes, FluidWorker,
+};
+
+const PARTICLE_PALETTE_SIZE: usize = 32;
+const PARTICLE_ACCELERATION_RANGE: f32 = 100.;
+const PARTICLE_VELOCITY_RANGE: f32 = 6.3;  // ~sqrt(40), the old squared speed cutoff
+const PARTICLE_TEMPERATURE_RANGE: f32 = 10.;  // Degrees above the ambient
+const PARTICLE_DENSITY_RANGE: f32 = 0.5;  // Fraction of the target density either way
+const PARTICLE_DENSITY_LOW_COLOR: Color = Color::rgb(0.1, 0.3, 1.);
+const PARTICLE_DENSITY_HIGH_COLOR: Color = Color::rgb(1., 0.15, 0.1);
+const PARTICLE_COLOR_MODE_KEY: KeyCode = KeyCode::KeyC;
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum ColorMode {
+    #[default]
+    Solid,
+    /// Gradient by speed
+    Velocity,
+    /// Gradient by acceleration magnitude, shows force hotspots
+    Acceleration,
+    /// Gradient by temperature above the ambient
+    Thermal,
+    /// Blue below the target density, white at it, red above, shows compression waves and voids
+    Density,
+}
+
+
+impl ColorMode {
+    /// Cycle order, new modes go here to be reachable from the keyboard
+    pub const ALL: [ColorMode; 5] = [
+        ColorMode::Solid,
+        ColorMode::Velocity,
+        ColorMode::Acceleration,
+        ColorMode::Thermal,
+        ColorMode::Density,
+    ];
+
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|mode| *mode == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct ColorSettings {
+    pub mode: ColorMode,
+    /// Speed mapped to the hot end of the gradient
+    pub velocity_range: f32,
+    /// Acceleration magnitude mapped to the hot end of the gradient
+    pub acceleration_range: f32,
+    /// Degrees above the ambient mapped to the hot end of the gradient
+    pub temperature_range: f32,
+    /// Deviation from the target density mapped to either end, as a fraction of the target
+    pub density_range: f32,
+}
+
+
+impl Default for ColorSettings {
+    fn default() -> Self {
+        Self {
+            mode: ColorMode::default(),
+            velocity_range: PARTICLE_VELOCITY_RANGE,
+            acceleration_range: PARTICLE_ACCELERATION_RANGE,
+            temperature_range: PARTICLE_TEMPERATURE_RANGE,
+            density_range: PARTICLE_DENSITY_RANGE,
+        }
+    }
+}
+
+
+impl ColorSettings {
+    /// Human readable mode and range, for the HUD legend
+    pub fn get_legend(&self) -> String {
+        match self.mode {
+            ColorMode::Solid => "Color: solid".to_string(),
+            ColorMode::Velocity => format!("Color: speed 0 - {:.1}", self.velocity_range),
+            ColorMode::Acceleration => format!("Color: acceleration 0 - {:.0}", self.acceleration_range),
+            ColorMode::Thermal => format!("Color: temperature +0 - +{:.1}", self.temperature_range),
+            ColorMode::Density => format!("Color: density target ±{:.0}%", self.density_range * 100.),
+        }
+    }
+}
+
+
+/// Shared materials, particles switch handles instead of owning a material each
+#[derive(Resource, Debug)]
+pub struct ParticlePalette {
+    /// Color of the first fluid
+    pub solid: Handle<StandardMaterial>,
+    /// Color of each fluid type, indexed by the fluid id
+    pub fluids: Vec<Handle<StandardMaterial>>,
+    pub gradient: Vec<Handle<StandardMaterial>>,
+    /// Low to high through white in the middle
+    pub diverging: Vec<Handle<StandardMaterial>>,
+}
+
+
+impl ParticlePalette {
+    /// Gradient material for `value` in 0..1, cold to hot
+    pub fn get_gradient(&self, value: f32) -> &Handle<StandardMaterial> {
+        let index = (value.clamp(0., 1.) * (self.gradient.len() - 1) as f32).round() as usize;
+        &self.gradient[index]
+    }
+
+    /// Diverging material for `value` in -1..1, white at zero
+    pub fn get_diverging(&self, value: f32) -> &Handle<StandardMaterial> {
+        let t = (value.clamp(-1., 1.) + 1.) / 2.;
+        let index = (t * (self.diverging.len() - 1) as f32).round() as usize;
+        &self.diverging[index]
+    }
+}
+
+
+pub struct ParticleColorPlugin;
+
+
+impl Plugin for ParticleColorPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<ColorSettings>()
+            .add_systems(Startup, setup_palette)
+            .add_systems(Update, cycle_color_mode.in_set(InGameSet::UserInput))
+            .add_systems(Update, update_particle_colors.in_set(InGameSet::EntityUpdates))
+            .add_systems(Update, apply_theme_to_palette);
+    }
+}
+
+
+fn setup_palette(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    fluid_types: Res<FluidTypes>,
+    theme: Res<Theme>,
+) {
+    // The theme picks the first fluid's color, the others keep their own
+    let fluids: Vec<_> = fluid_types.items.iter()
+        .enumerate()
+        .map(|(it, fluid_type)| materials.add(StandardMaterial {
+            base_color: if it == 0 { theme.particle_base } else { fluid_type.color },
+            ..default()
+        }))
+        .collect();
+
+    // HSL: 200 <= H <= 20, S = 100, L = 50
+    let mut gradient = Vec::with_capacity(PARTICLE_PALETTE_SIZE);
+    for it in 0..PARTICLE_PALETTE_SIZE {
+        let t = it as f32 / (PARTICLE_PALETTE_SIZE - 1) as f32;
+        gradient.push(materials.add(StandardMaterial {
+            base_color: Color::hsl((1. - t) * 180. + 20., 1., 0.5),
+            ..default()
+        }));
+    }
+
+    // Odd size, so zero lands on pure white
+    let mut diverging = Vec::with_capacity(PARTICLE_PALETTE_SIZE + 1);
+    for it in 0..=PARTICLE_PALETTE_SIZE {
+        let t = it as f32 / PARTICLE_PALETTE_SIZE as f32 * 2. - 1.;
+        let end = if t < 0. { PARTICLE_DENSITY_LOW_COLOR } else { PARTICLE_DENSITY_HIGH_COLOR };
+        let color = Vec3::ONE.lerp(Vec3::new(end.r(), end.g(), end.b()), t.abs());
+        diverging.push(materials.add(StandardMaterial {
+            base_color: Color::rgb(color.x, color.y, color.z),
+            ..default()
+        }));
+    }
+
+    commands.insert_resource(ParticlePalette {
+        solid: fluids[0].clone(),
+        fluids,
+        gradient,
+        diverging,
+    });
+}
+
+
+/// Edits the shared material, so every particle using it is restyled without touching the entities
+fn apply_theme_to_palette(
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    palette: Option<Res<ParticlePalette>>,
+    theme: Res<Theme>,
+) {
+    if !theme.is_changed() {
+        return;
+    }
+    let Some(palette) = palette else { return };
+    if let Some(material) = materials.get_mut(&palette.solid) {
+        material.base_color = theme.particle_base;
+    }
+}
+
+
+fn cycle_color_mode(mut settings: ResMut<ColorSettings>, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if keyboard_input.just_pressed(PARTICLE_COLOR_MODE_KEY) {
+        settings.mode = settings.mode.next();
+    }
+}
+
+
+fn update_particle_colors(
+    mut query: Query<(&mut Handle<StandardMaterial>, &FluidParticleLabel)>,
+    worker: Res<AppComputeWorker<FluidWorker>>,
+    settings: Res<ColorSettings>,
+    palette: Res<ParticlePalette>,
+    fluid_initials: Res<FluidParticlesInitial>,
+    fluid_props: Res<FluidStaticProps>,
+    fluid_types: Res<FluidTypes>,
+) {
+    // A single fluid doesn't need the read-back
+    if settings.mode == ColorMode::Solid && fluid_initials.fluid_ids.is_empty() {
+        if settings.is_changed() {
+            query.par_iter_mut().for_each(|(mut material, _)| {
+                *material = palette.solid.clone();
+            });
+        }
+        return;
+    }
+    if !worker.ready() {
+        return;
+    }
+
+    let particles = worker.read_vec::<FluidParticle>("particles");
+    query.par_iter_mut().for_each(|(mut material, particle)| {
+        let target = match settings.mode {
+            ColorMode::Solid => &palette.fluids[particles[particle.0].get_fluid_id() as usize],
+            ColorMode::Velocity => palette.get_gradient(particles[particle.0].velocity.length() / settings.velocity_range),
+            ColorMode::Acceleration => palette.get_gradient(particles[particle.0].acceleration.length() / settings.acceleration_range),
+            ColorMode::Thermal => palette.get_gradient(
+                (particles[particle.0].temperature.x - fluid_props.ambient_temperature) / settings.temperature_range,
+            ),
+            ColorMode::Density => {
+                // Same per-fluid target as the pressure pass
+                let fluid_id = particles[particle.0].get_fluid_id() as usize;
+                let scale = fluid_types.items.get(fluid_id).map_or(1., |fluid_type| fluid_type.target_density);
+                let target_density = (fluid_props.target_density * scale).max(f32::EPSILON);
+                let deviation = particles[particle.0].density.x / target_density - 1.;
+                palette.get_diverging(deviation / settings.density_range.max(f32::EPSILON))
+            },
+        };
+        // Only touch the handle when it changes, to keep change detection quiet
+        if *material != *target {
+            *material = target.clone();
+        }
+    });
+}
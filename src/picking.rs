@@ -0,0 +1,233 @@
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use bevy_app_compute::prelude::*;
+
+use crate::schedule::InGameSet;
+use crate::state::GameState;
+use crate::camera::Observer;
+use crate::fluid_compute::{FluidCapacity, FluidParticle, FluidParticleLabel, FluidResetEvent, FluidWorker};
+
+const PICK_BUTTON: MouseButton = MouseButton::Left;
+const PICK_MODIFIERS: [KeyCode; 2] = [KeyCode::ControlLeft, KeyCode::ControlRight];
+const CLEAR_BUTTON: MouseButton = MouseButton::Right;
+const PICK_RADIUS: f32 = 16.;  // Pixels
+const PICK_HIGHLIGHT_RADIUS: f32 = 0.12;
+const PICK_HIGHLIGHT_COLOR: Color = Color::LIME_GREEN;
+const TEXT_COLOR: Color = Color::rgb(0.9, 0.9, 0.9);
+const TEXT_FONT_SIZE: f32 = 18.;
+const PANEL_COLOR: Color = Color::rgba(0.1, 0.1, 0.1, 0.8);
+
+
+/// Particle chosen with Ctrl+click, its live values are pinned in a panel
+#[derive(Resource, Default, Debug)]
+pub struct ParticleSelection {
+    pub label: Option<FluidParticleLabel>,
+}
+
+
+#[derive(Component, Debug)]
+pub struct SelectionPanel;
+
+
+#[derive(Component, Debug)]
+pub struct SelectionPanelText;
+
+
+pub struct PickingPlugin;
+
+
+impl Plugin for PickingPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<ParticleSelection>()
+            .add_systems(OnTransition { from: GameState::Menu, to: GameState::InGame }, setup_selection_panel)
+            .add_systems(OnTransition { from: GameState::Paused, to: GameState::Menu }, despawn_selection_panel)
+            .add_systems(Update, pick_particle.in_set(InGameSet::UserInput))
+            .add_systems(Update, (clear_selection, update_selection_panel, draw_selection).chain().in_set(InGameSet::EntityUpdates));
+    }
+}
+
+
+/// Index of the screen point closest to the cursor, if any is within the radius
+pub fn pick_nearest(points: impl Iterator<Item = (usize, Vec2)>, cursor: Vec2, max_distance: f32) -> Option<usize> {
+    points
+        .map(|(index, point)| (index, point.distance(cursor)))
+        .filter(|(_, distance)| *distance < max_distance)
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(index, _)| index)
+}
+
+
+fn setup_selection_panel(mut commands: Commands) {
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                display: Display::None,
+                position_type: PositionType::Absolute,
+                left: Val::Px(10.),
+                top: Val::Percent(8.),
+                padding: UiRect::all(Val::Px(10.)),
+                ..default()
+            },
+            background_color: PANEL_COLOR.into(),
+            ..default()
+        },
+        SelectionPanel,
+    )).with_children(|parent| {
+        parent.spawn((
+            TextBundle::from_section("", TextStyle {
+                font_size: TEXT_FONT_SIZE,
+                color: TEXT_COLOR,
+                ..default()
+            }),
+            SelectionPanelText,
+        ));
+    });
+}
+
+
+fn despawn_selection_panel(
+    mut commands: Commands,
+    mut selection: ResMut<ParticleSelection>,
+    query: Query<Entity, With<SelectionPanel>>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    selection.label = None;
+}
+
+
+/// Ctrl+click selects the particle drawn closest to the cursor, a right click away from every particle clears it
+fn pick_particle(
+    mut selection: ResMut<ParticleSelection>,
+    worker: Res<AppComputeWorker<FluidWorker>>,
+    capacity: Res<FluidCapacity>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<Observer>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+) {
+    let picking = mouse_input.just_pressed(PICK_BUTTON) && keyboard_input.any_pressed(PICK_MODIFIERS);
+    let clearing = mouse_input.just_pressed(CLEAR_BUTTON) && selection.label.is_some();
+    if !(picking || clearing) || !worker.ready() {
+        return;
+    }
+    let Ok((camera, camera_transform)) = camera_query.get_single() else { return };
+    let Ok(window) = window_query.get_single() else { return };
+    let Some(cursor) = window.cursor_position() else { return };
+
+    // Only on clicks, projecting every particle is too slow to do each frame
+    let particles = worker.read_vec::<FluidParticle>("particles");
+    let points = particles.iter()
+        .take(capacity.num_particles as usize)
+        .enumerate()
+        .filter_map(|(index, particle)| Some((index, camera.world_to_viewport(camera_transform, particle.position.xyz())?)));
+    let nearest = pick_nearest(points, cursor, PICK_RADIUS);
+    if picking {
+        if let Some(index) = nearest {
+            selection.label = Some(FluidParticleLabel(index));
+        }
+    } else if nearest.is_none() {
+        selection.label = None;
+    }
+}
+
+
+/// Respawned or drained particles reuse the slots, the selection would jump to another one
+fn clear_selection(
+    mut selection: ResMut<ParticleSelection>,
+    mut reset_events: EventReader<FluidResetEvent>,
+    capacity: Res<FluidCapacity>,
+) {
+    let removed = selection.label.is_some_and(|label| label.0 >= capacity.num_particles as usize);
+    if !reset_events.is_empty() || removed {
+        selection.label = None;
+    }
+    reset_events.clear();
+}
+
+
+fn update_selection_panel(
+    mut panel_query: Query<&mut Style, With<SelectionPanel>>,
+    mut text_query: Query<&mut Text, With<SelectionPanelText>>,
+    selection: Res<ParticleSelection>,
+    worker: Res<AppComputeWorker<FluidWorker>>,
+) {
+    let Ok(mut style) = panel_query.get_single_mut() else { return };
+    let Some(label) = selection.label else {
+        style.display = Display::None;
+        return;
+    };
+    style.display = Display::Flex;
+    if !worker.ready() {
+        return;
+    }
+
+    let Ok(mut text) = text_query.get_single_mut() else { return };
+    if text.sections.is_empty() {
+        return;
+    }
+    let particles = worker.read_vec::<FluidParticle>("particles");
+    let Some(particle) = particles.get(label.0) else { return };
+    let velocity = particle.velocity.xyz();
+    text.sections[0].value = format!(
+        "Particle {}\nDensity: {:.2}\nPressure: {:.2}\nVelocity: ({:.2}, {:.2}, {:.2}) |{:.2}|\nNeighbors: {}",
+        label.0,
+        particle.density.x,
+        particle.pressure.x,
+        velocity.x, velocity.y, velocity.z, velocity.length(),
+        particle.neighbors.x as u32,
+    );
+}
+
+
+fn draw_selection(
+    mut gizmos: Gizmos,
+    selection: Res<ParticleSelection>,
+    query: Query<(&Transform, &FluidParticleLabel)>,
+) {
+    let Some(label) = selection.label else { return };
+    // The rendered transform, the mirrored copy shares the label but sits on the other side
+    for (transform, _) in query.iter().filter(|(_, it)| **it == label) {
+        gizmos.sphere(transform.translation, Quat::IDENTITY, PICK_HIGHLIGHT_RADIUS, PICK_HIGHLIGHT_COLOR);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points() -> Vec<(usize, Vec2)> {
+        vec![
+            (0, Vec2::new(0., 0.)),
+            (1, Vec2::new(10., 0.)),
+            (2, Vec2::new(4., 3.)),
+            (3, Vec2::new(100., 100.)),
+        ]
+    }
+
+    #[test]
+    fn picks_the_nearest_point() {
+        assert_eq!(pick_nearest(points().into_iter(), Vec2::new(5., 2.), 16.), Some(2));
+        assert_eq!(pick_nearest(points().into_iter(), Vec2::new(99., 98.), 16.), Some(3));
+    }
+
+    #[test]
+    fn ties_go_to_the_first_point() {
+        // Halfway between the first two
+        assert_eq!(pick_nearest(points().into_iter(), Vec2::new(5., -10.), 16.), Some(0));
+    }
+
+    #[test]
+    fn nothing_within_reach() {
+        assert_eq!(pick_nearest(points().into_iter(), Vec2::new(50., 50.), 16.), None);
+        assert_eq!(pick_nearest(std::iter::empty(), Vec2::ZERO, 16.), None);
+    }
+
+    #[test]
+    fn reach_is_exclusive() {
+        assert_eq!(pick_nearest(points().into_iter(), Vec2::new(-5., 0.), 5.), None);
+    }
+}
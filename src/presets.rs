@@ -0,0 +1,240 @@
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::schedule::InGameSet;
+use crate::gravity::Gravity;
+use crate::fluid_container::FluidContainer;
+use crate::fluid_compute::FluidStaticProps;
+
+pub const PRESET_SLOTS: usize = 3;
+
+const PRESETS_PATH: &str = "presets.ron";
+const PRESETS_VERSION: u32 = 1;
+// Held together with the slot keys, F6-F8 alone toggle the forces
+const PRESET_SAVE_MODIFIERS: [KeyCode; 2] = [KeyCode::ShiftLeft, KeyCode::ShiftRight];
+const PRESET_SAVE_KEYS: [KeyCode; PRESET_SLOTS] = [KeyCode::F6, KeyCode::F7, KeyCode::F8];
+
+
+/// Tuning snapshot, missing fields fall back to the defaults
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(default)]
+pub struct Preset {
+    pub fluid_props: FluidStaticProps,
+    pub gravity: [f32; 3],
+    pub container_size: [f32; 3],
+}
+
+
+impl Default for Preset {
+    fn default() -> Self {
+        Self::capture(&FluidStaticProps::default(), &Gravity::default(), &FluidContainer::default())
+    }
+}
+
+
+impl Preset {
+    pub fn capture(fluid_props: &FluidStaticProps, gravity: &Gravity, container: &FluidContainer) -> Self {
+        Self {
+            fluid_props: *fluid_props,
+            gravity: gravity.value.truncate().to_array(),
+            container_size: container.size.to_array(),
+        }
+    }
+
+    /// Particles are kept, the ones outside a shrunk container are pushed back by the walls
+    pub fn apply(&self, fluid_props: &mut FluidStaticProps, gravity: &mut Gravity, container: &mut FluidContainer) {
+        *fluid_props = self.fluid_props;
+        gravity.value = Vec3::from_array(self.gravity).extend(0.);
+        container.size = Vec3::from_array(self.container_size);
+    }
+}
+
+
+/// On-disk layout, `version` is bumped on incompatible changes only
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+struct PresetFile {
+    version: u32,
+    slots: Vec<Option<Preset>>,
+}
+
+
+impl Default for PresetFile {
+    fn default() -> Self {
+        Self {
+            version: PRESETS_VERSION,
+            slots: vec![None; PRESET_SLOTS],
+        }
+    }
+}
+
+
+/// Preset slots mirrored to `presets.ron`
+#[derive(Resource, Debug)]
+pub struct PresetStore {
+    path: PathBuf,
+    slots: [Option<Preset>; PRESET_SLOTS],
+}
+
+
+impl PresetStore {
+    /// Starts empty when the file is absent, malformed or from a newer version
+    pub fn open(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let mut slots = [None; PRESET_SLOTS];
+        let file = std::fs::read_to_string(&path).ok()
+            .and_then(|source| match ron::from_str::<PresetFile>(&source) {
+                Ok(file) => Some(file),
+                Err(err) => {
+                    println!("Presets: {} is malformed, ignoring it ({})", path.display(), err);
+                    None
+                },
+            })
+            .filter(|file| {
+                let supported = file.version <= PRESETS_VERSION;
+                if !supported {
+                    println!("Presets: {} has unsupported version {}", path.display(), file.version);
+                }
+                supported
+            });
+        if let Some(file) = file {
+            for (slot, preset) in slots.iter_mut().zip(file.slots) {
+                *slot = preset;
+            }
+        }
+        Self { path, slots }
+    }
+
+    pub fn load(&self, slot: usize) -> Option<Preset> {
+        self.slots.get(slot).copied().flatten()
+    }
+
+    /// Stores the preset and rewrites the whole file
+    pub fn save(&mut self, slot: usize, preset: Preset) -> Result<(), String> {
+        let Some(stored) = self.slots.get_mut(slot) else {
+            return Err(format!("no preset slot {}", slot));
+        };
+        *stored = Some(preset);
+        let file = PresetFile {
+            version: PRESETS_VERSION,
+            slots: self.slots.to_vec(),
+        };
+        let source = ron::ser::to_string_pretty(&file, ron::ser::PrettyConfig::default()).map_err(|err| err.to_string())?;
+        std::fs::write(&self.path, source).map_err(|err| err.to_string())
+    }
+}
+
+
+/// Sent by the menu to recall a slot
+#[derive(Event, Clone, Copy, Debug)]
+pub struct LoadPreset(pub usize);
+
+
+pub struct PresetsPlugin;
+
+
+impl Plugin for PresetsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(PresetStore::open(PRESETS_PATH))
+            .add_event::<LoadPreset>()
+            .add_systems(Update, save_preset.in_set(InGameSet::UserInput))
+            .add_systems(Update, load_preset);
+    }
+}
+
+
+fn save_preset(
+    mut store: ResMut<PresetStore>,
+    fluid_props: Res<FluidStaticProps>,
+    gravity: Res<Gravity>,
+    container: Res<FluidContainer>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+) {
+    if !keyboard_input.any_pressed(PRESET_SAVE_MODIFIERS) {
+        return;
+    }
+    let Some(slot) = PRESET_SAVE_KEYS.iter().position(|key| keyboard_input.just_pressed(*key)) else { return };
+    match store.save(slot, Preset::capture(&fluid_props, &gravity, &container)) {
+        Ok(()) => println!("Presets: saved slot {}", slot + 1),
+        Err(err) => println!("Presets: saving slot {} failed ({})", slot + 1, err),
+    }
+}
+
+
+fn load_preset(
+    mut events: EventReader<LoadPreset>,
+    store: Res<PresetStore>,
+    mut fluid_props: ResMut<FluidStaticProps>,
+    mut gravity: ResMut<Gravity>,
+    mut container: ResMut<FluidContainer>,
+) {
+    for LoadPreset(slot) in events.read() {
+        let Some(preset) = store.load(*slot) else { continue };
+        preset.apply(&mut fluid_props, &mut gravity, &mut container);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Unique per test, the tests run in parallel
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("water-sandbox-presets-{}-{}.ron", std::process::id(), name))
+    }
+
+    fn make_preset() -> Preset {
+        let fluid_props = FluidStaticProps { collision_damping: 0.25, ..default() };
+        let gravity = Gravity::new(Vec4::new(0., -3., 1., 0.));
+        let container = FluidContainer { size: Vec3::new(6., 5., 4.), ..default() };
+        Preset::capture(&fluid_props, &gravity, &container)
+    }
+
+    #[test]
+    fn saved_presets_survive_a_reopen() {
+        let path = temp_path("round-trip");
+        let mut store = PresetStore::open(&path);
+        assert_eq!(store.load(1), None);
+        store.save(1, make_preset()).unwrap();
+        assert_eq!(store.load(1), Some(make_preset()));
+
+        let reopened = PresetStore::open(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(reopened.load(0), None);
+        assert_eq!(reopened.load(1), Some(make_preset()));
+        assert_eq!(reopened.load(2), None);
+    }
+
+    #[test]
+    fn saving_past_the_slots_fails() {
+        let path = temp_path("past-the-slots");
+        let mut store = PresetStore::open(&path);
+        assert!(store.save(PRESET_SLOTS, make_preset()).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn old_files_fill_the_missing_fields() {
+        let path = temp_path("old-file");
+        std::fs::write(&path, "(version: 1, slots: [None, Some((gravity: (0., -5., 0.)))])").unwrap();
+        let store = PresetStore::open(&path);
+        std::fs::remove_file(&path).unwrap();
+        let preset = store.load(1).unwrap();
+        assert_eq!(preset.gravity, [0., -5., 0.]);
+        assert_eq!(preset.fluid_props, FluidStaticProps::default());
+        assert_eq!(preset.container_size, FluidContainer::default().size.to_array());
+    }
+
+    #[test]
+    fn newer_files_are_ignored() {
+        let path = temp_path("newer-file");
+        std::fs::write(&path, format!("(version: {}, slots: [Some(())])", PRESETS_VERSION + 1)).unwrap();
+        let store = PresetStore::open(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(store.load(0), None);
+    }
+}
@@ -0,0 +1,138 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::app::AppExit;
+use bevy::time::TimeUpdateStrategy;
+use bevy::render::view::screenshot::ScreenshotManager;
+use bevy::window::PrimaryWindow;
+
+use crate::state::GameState;
+
+const RECORD_FRAME_TIME: f32 = 1. / 60.;  // Game time per recorded frame, regardless of the real frame rate
+const RECORD_EXIT_DELAY_FRAMES: u32 = 10;  // The last screenshots are encoded and written on the task pool
+
+
+/// Frames captured so far out of the requested number
+#[derive(Clone, Copy, Debug)]
+pub struct RecordProgress {
+    pub captured: u32,
+    pub requested: u32,
+}
+
+
+impl RecordProgress {
+    pub fn new(requested: u32) -> Self {
+        Self {
+            captured: 0,
+            requested,
+        }
+    }
+
+    /// Counts a capture unless the requested number was reached, true once it is
+    pub fn advance(&mut self) -> bool {
+        if !self.is_done() {
+            self.captured += 1;
+        }
+        self.is_done()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.captured >= self.requested
+    }
+}
+
+
+#[derive(Resource, Debug)]
+struct Recording {
+    dir: PathBuf,
+    progress: RecordProgress,
+    exit_delay: u32,
+}
+
+
+/// Writes numbered PNGs of the primary window once in game, then exits
+pub struct RecordPlugin {
+    pub dir: PathBuf,
+    pub frames: u32,
+}
+
+
+impl Plugin for RecordPlugin {
+    fn build(&self, app: &mut App) {
+        if let Err(err) = std::fs::create_dir_all(&self.dir) {
+            println!("Warning: can't create {} ({}), recording is disabled", self.dir.display(), err);
+            return;
+        }
+
+        app
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(RECORD_FRAME_TIME)))
+            .insert_resource(Recording {
+                dir: self.dir.clone(),
+                progress: RecordProgress::new(self.frames),
+                exit_delay: RECORD_EXIT_DELAY_FRAMES,
+            })
+            .add_systems(Last, capture_frame.run_if(in_state(GameState::InGame)));
+    }
+}
+
+
+fn capture_frame(
+    mut recording: ResMut<Recording>,
+    mut screenshot_manager: ResMut<ScreenshotManager>,
+    mut exit_events: EventWriter<AppExit>,
+    window_query: Query<Entity, With<PrimaryWindow>>,
+) {
+    if recording.progress.is_done() {
+        if recording.exit_delay == 0 {
+            println!("Recorded {} frames to {}", recording.progress.captured, recording.dir.display());
+            exit_events.send(AppExit);
+        } else {
+            recording.exit_delay -= 1;
+        }
+        return;
+    }
+    let Ok(window) = window_query.get_single() else { return };
+
+    // A failed request is retried next frame, so the numbering stays gapless
+    let path = recording.dir.join(format!("frame_{:05}.png", recording.progress.captured));
+    match screenshot_manager.save_screenshot_to_disk(window, &path) {
+        Ok(()) => {
+            recording.progress.advance();
+        },
+        Err(err) => println!("Warning: recording frame {} failed ({})", recording.progress.captured, err),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn done_after_the_requested_frames() {
+        let mut progress = RecordProgress::new(3);
+        assert!(!progress.is_done());
+        assert!(!progress.advance());
+        assert!(!progress.advance());
+        assert!(progress.advance());
+        assert!(progress.is_done());
+        assert_eq!(progress.captured, 3);
+    }
+
+    #[test]
+    fn stops_counting_once_done() {
+        let mut progress = RecordProgress::new(1);
+        assert!(progress.advance());
+        assert!(progress.advance());
+        assert_eq!(progress.captured, 1);
+    }
+
+    #[test]
+    fn nothing_requested_is_done_at_once() {
+        let mut progress = RecordProgress::new(0);
+        assert!(progress.is_done());
+        assert!(progress.advance());
+        assert_eq!(progress.captured, 0);
+    }
+}
@@ -0,0 +1,245 @@
+use bevy::prelude::*;
+use bevy::core::Pod;
+use bevy::window::PrimaryWindow;
+use bevy_app_compute::prelude::*;
+use bytemuck::Zeroable;
+
+use crate::schedule::InGameSet;
+use crate::camera::Observer;
+use crate::gravity::Gravity;
+use crate::fluid_container::FluidContainer;
+use crate::container_handles::ContainerDrag;
+use crate::fluid_compute::{FluidStaticProps, FluidWorker};
+
+const BALL_TOGGLE_KEY: KeyCode = KeyCode::Insert;  // No letter is left
+const BALL_DRAG_BUTTON: MouseButton = MouseButton::Left;
+const BALL_POSITION: Vec3 = Vec3::new(0., 2., 0.);
+const BALL_RADIUS: f32 = 0.8;
+const BALL_MASS: f32 = 60.;  // In particle masses
+const BALL_WALL_RESTITUTION: f32 = 0.4;
+const BALL_COLOR: Color = Color::rgb(0.9, 0.3, 0.5);
+const BALL_DRAGGED_COLOR: Color = Color::ORANGE;
+const BALL_FIXED_POINT_SCALE: f32 = 1000.;  // Must match the shader
+
+
+#[derive(ShaderType, Pod, Zeroable, Clone, Copy)]
+#[repr(C)]
+pub struct RigidBallExt {
+    /// Center in xyz, radius in w, zero while disabled
+    pub position: Vec4,
+    pub velocity: Vec4,
+}
+
+
+/// Sphere the particles bounce off, pushed back by the momentum they lose on it
+#[derive(Resource, Clone, Debug)]
+pub struct RigidBall {
+    pub enabled: bool,
+    pub position: Vec3,
+    pub velocity: Vec3,
+    pub radius: f32,
+    /// In particle masses, the particles count as one each
+    pub mass: f32,
+    /// Held by the mouse, physics is paused meanwhile
+    pub dragged: bool,
+}
+
+
+impl Default for RigidBall {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            position: BALL_POSITION,
+            velocity: Vec3::ZERO,
+            radius: BALL_RADIUS,
+            mass: BALL_MASS,
+            dragged: false,
+        }
+    }
+}
+
+
+impl RigidBall {
+    pub fn get_ext(&self) -> RigidBallExt {
+        let radius = if self.enabled { self.radius } else { 0. };
+        RigidBallExt {
+            position: self.position.extend(radius),
+            velocity: self.velocity.extend(0.),
+        }
+    }
+
+    /// Takes the momentum the particles handed over during a step, plus gravity over it
+    fn apply_impulse(&mut self, impulse: Vec3, gravity: Vec3, delta_time: f32) {
+        self.velocity += impulse / self.mass.max(f32::EPSILON) + gravity * delta_time;
+    }
+
+    /// Keeps the ball inside the container walls, bouncing off them like the particles do
+    fn collide_container(&mut self, container: &FluidContainer) {
+        let mut ext = container.get_ext(container.wall_margin + self.radius);
+        // A container narrower than the ball holds it at the center
+        ext.ext_min = ext.ext_min.min(container.position.extend(0.));
+        ext.ext_max = ext.ext_max.max(container.position.extend(0.));
+        ext.restitution_min = Vec4::splat(BALL_WALL_RESTITUTION);
+        ext.restitution_max = Vec4::splat(BALL_WALL_RESTITUTION);
+        ext.collide(&mut self.position, &mut self.velocity);
+    }
+}
+
+
+pub struct RigidBallPlugin;
+
+
+impl Plugin for RigidBallPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<RigidBall>()
+            .add_systems(Update, (
+                toggle_rigid_ball,
+                drag_rigid_ball,
+            ).chain().in_set(InGameSet::UserInput))
+            .add_systems(Update, (step_rigid_ball, draw_rigid_ball).chain().in_set(InGameSet::EntityUpdates));
+    }
+}
+
+
+fn toggle_rigid_ball(mut ball: ResMut<RigidBall>, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if !keyboard_input.just_pressed(BALL_TOGGLE_KEY) {
+        return;
+    }
+    ball.enabled = !ball.enabled;
+    ball.velocity = Vec3::ZERO;
+    ball.dragged = false;
+}
+
+
+/// Moves the ball on the camera-facing plane through its center, it keeps the drag velocity when let go
+fn drag_rigid_ball(
+    mut ball: ResMut<RigidBall>,
+    container_drag: Option<Res<ContainerDrag>>,
+    container: Res<FluidContainer>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<Observer>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    time: Res<Time>,
+) {
+    if !ball.enabled || mouse_input.just_released(BALL_DRAG_BUTTON) {
+        ball.dragged = false;
+        return;
+    }
+    let Ok((camera, camera_transform)) = camera_query.get_single() else { return };
+    let Ok(window) = window_query.get_single() else { return };
+    let Some(cursor) = window.cursor_position() else { return };
+    let Some(ray) = camera.viewport_to_world(camera_transform, cursor) else { return };
+
+    // Container handles win when both are under the cursor
+    let wall_dragged = container_drag.is_some_and(|drag| drag.wall.is_some());
+    if mouse_input.just_pressed(BALL_DRAG_BUTTON) && !wall_dragged {
+        let to_center = ball.position - ray.origin;
+        let along = to_center.dot(*ray.direction);
+        ball.dragged = along > 0. && (to_center - *ray.direction * along).length() < ball.radius;
+    }
+    if !ball.dragged {
+        return;
+    }
+
+    let Some(distance) = ray.intersect_plane(ball.position, Plane3d::new(camera_transform.back())) else { return };
+    let previous_position = ball.position;
+    ball.position = ray.get_point(distance);
+    ball.collide_container(&container);
+    ball.velocity = if time.delta_seconds() > 0. {
+        (ball.position - previous_position) / time.delta_seconds()
+    } else {
+        Vec3::ZERO
+    };
+}
+
+
+/// Momentum the shader accumulated in `ball_impulse`, in particle masses times speed
+fn decode_impulse(impulse: IVec4) -> Vec3 {
+    impulse.truncate().as_vec3() / BALL_FIXED_POINT_SCALE
+}
+
+
+/// Integrates the ball over the step the worker just finished, with the momentum the particles handed over
+fn step_rigid_ball(
+    mut ball: ResMut<RigidBall>,
+    mut worker: ResMut<AppComputeWorker<FluidWorker>>,
+    gravity: Res<Gravity>,
+    container: Res<FluidContainer>,
+    fluid_props: Res<FluidStaticProps>,
+) {
+    if !worker.ready() {
+        return;
+    }
+
+    let impulse = decode_impulse(worker.read::<IVec4>("ball_impulse"));
+    // Reset the accumulator for the next step
+    worker.write("ball_impulse", &IVec4::ZERO);
+
+    if ball.enabled && !ball.dragged {
+        let delta_time = fluid_props.delta_time;
+        ball.apply_impulse(impulse, gravity.value.xyz(), delta_time);
+        ball.position += ball.velocity * delta_time;
+        ball.collide_container(&container);
+    }
+    worker.write("rigid_ball", &ball.get_ext());
+}
+
+
+fn draw_rigid_ball(mut gizmos: Gizmos, ball: Res<RigidBall>) {
+    if !ball.enabled {
+        return;
+    }
+    let color = if ball.dragged { BALL_DRAGGED_COLOR } else { BALL_COLOR };
+    gizmos.sphere(ball.position, Quat::IDENTITY, ball.radius, color);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What `collide_rigid_ball` adds to the accumulator for a particle velocity change
+    fn encode_reaction(change: Vec3) -> IVec3 {
+        (-change * BALL_FIXED_POINT_SCALE).as_ivec3()
+    }
+
+    #[test]
+    fn impulse_round_trips_through_fixed_point() {
+        let change = Vec3::new(1.25, -0.5, 3.);
+        let impulse = decode_impulse(encode_reaction(change).extend(0));
+        assert!((impulse + change).length() < 1e-6, "{impulse}");
+        // Below the resolution the fraction is truncated towards zero
+        let impulse = decode_impulse(encode_reaction(Vec3::new(0.0123456, -0.0123456, 0.)).extend(0));
+        assert_eq!(impulse, Vec3::new(-0.012, 0.012, 0.));
+        // The w component isn't part of the impulse
+        assert_eq!(decode_impulse(IVec4::new(0, 0, 0, 7)), Vec3::ZERO);
+    }
+
+    #[test]
+    fn momentum_is_balanced() {
+        let mut ball = RigidBall { mass: 60., ..default() };
+        // Unit mass particles bouncing off the ball
+        let changes = [
+            Vec3::new(0., 2.5, 0.),
+            Vec3::new(-1.2, 0.3, 0.4),
+            Vec3::new(0.05, 0.9, -2.),
+            Vec3::new(3., 0., 0.),
+        ];
+        let accumulated = changes.iter().fold(IVec3::ZERO, |acc, change| acc + encode_reaction(*change));
+        ball.apply_impulse(decode_impulse(accumulated.extend(0)), Vec3::ZERO, 1. / 60.);
+
+        let particle_momentum = changes.iter().sum::<Vec3>();
+        let ball_momentum = ball.velocity * ball.mass;
+        // Each particle may lose up to one fixed point unit per axis
+        let tolerance = changes.len() as f32 / BALL_FIXED_POINT_SCALE;
+        assert!((particle_momentum + ball_momentum).abs().max_element() <= tolerance, "{particle_momentum} vs {ball_momentum}");
+    }
+
+    #[test]
+    fn gravity_applies_without_impulse() {
+        let mut ball = RigidBall::default();
+        ball.apply_impulse(Vec3::ZERO, Vec3::new(0., -9.8, 0.), 0.5);
+        assert_eq!(ball.velocity, Vec3::new(0., -4.9, 0.));
+    }
+}
@@ -2,7 +2,11 @@ use bevy::prelude::*;
 
 use crate::state::GameState;
 
+const SIM_PAUSE_KEY: KeyCode = KeyCode::KeyP;
+const SIM_STEP_KEY: KeyCode = KeyCode::Period;
 
+
+/// `Update` sets, chained in declaration order and only run in `GameState::InGame`
 #[derive(SystemSet, Hash, PartialEq, Eq, Clone, Debug)]
 pub enum InGameSet {
     UserInput,
@@ -11,19 +15,46 @@ pub enum InGameSet {
 }
 
 
+/// `PostUpdate` sets driving the GPU worker, after every `InGameSet` of the frame
 #[derive(SystemSet, Hash, PartialEq, Eq, Clone, Debug)]
 pub enum ShaderPhysicsSet {
+    /// Buffers written during `Update` are unmapped and uploaded
     Prepare,
+    /// The compute passes are dispatched
     Pass,
 }
 
 
+/// Freezes the compute passes while input, camera and rendering keep going
+#[derive(Resource, Clone, Copy, Default, Debug)]
+pub struct SimControl {
+    pub paused: bool,
+    /// Runs exactly one step while paused, cleared once it is dispatched
+    pub single_step: bool,
+    /// Set by the fixed clock, the passes are dispatched at most once per frame however many steps elapsed
+    pub step_due: bool,
+}
+
+
+impl SimControl {
+    pub fn is_running(&self) -> bool {
+        !self.paused || self.single_step
+    }
+
+    /// Whether the passes are dispatched this frame
+    pub fn is_step_due(&self) -> bool {
+        (!self.paused && self.step_due) || self.single_step
+    }
+}
+
+
 pub struct SchedulePlugin;
 
 
 impl Plugin for SchedulePlugin {
     fn build(&self, app: &mut App) {
         app
+            .init_resource::<SimControl>()
             .configure_sets(Update, (
                 InGameSet::DespawnEntities,
                 InGameSet::UserInput,
@@ -32,6 +63,74 @@ impl Plugin for SchedulePlugin {
             .configure_sets(PostUpdate, (
                 ShaderPhysicsSet::Prepare,
                 ShaderPhysicsSet::Pass,
-            ).chain().run_if(in_state(GameState::InGame)));
+            ).chain().run_if(in_state(GameState::InGame)).run_if(sim_running))
+            // Between the steps the read-backs are released, the worker isn't ready until the next dispatch
+            .configure_sets(PostUpdate, ShaderPhysicsSet::Pass.run_if(sim_step_due))
+            .add_systems(FixedUpdate, tick_sim_step)
+            .add_systems(Update, update_sim_control.in_set(InGameSet::UserInput))
+            .add_systems(PostUpdate, clear_sim_step.after(ShaderPhysicsSet::Pass));
+    }
+}
+
+
+fn sim_running(control: Res<SimControl>) -> bool {
+    control.is_running()
+}
+
+
+fn sim_step_due(control: Res<SimControl>) -> bool {
+    control.is_step_due()
+}
+
+
+/// Runs once per elapsed `Time::<Fixed>` period, the period is the simulation step length
+fn tick_sim_step(mut control: ResMut<SimControl>) {
+    control.step_due = true;
+}
+
+
+fn update_sim_control(mut control: ResMut<SimControl>, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if keyboard_input.just_pressed(SIM_PAUSE_KEY) {
+        control.paused = !control.paused;
+        control.single_step = false;
+    } else if keyboard_input.just_pressed(SIM_STEP_KEY) && control.paused {
+        control.single_step = true;
+    }
+}
+
+
+/// Steps elapsed while paused or out of the game are dropped, not caught up on
+fn clear_sim_step(mut control: ResMut<SimControl>) {
+    if control.single_step || control.step_due {
+        control.single_step = false;
+        control.step_due = false;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_only_when_due() {
+        let control = SimControl::default();
+        assert!(control.is_running());
+        assert!(!control.is_step_due());
+        assert!(SimControl { step_due: true, ..default() }.is_step_due());
+    }
+
+    #[test]
+    fn pause_holds_the_due_steps() {
+        let control = SimControl { paused: true, step_due: true, ..default() };
+        assert!(!control.is_running());
+        assert!(!control.is_step_due());
+    }
+
+    #[test]
+    fn single_step_ignores_the_clock() {
+        let control = SimControl { paused: true, single_step: true, ..default() };
+        assert!(control.is_running());
+        assert!(control.is_step_due());
     }
 }
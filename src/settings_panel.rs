@@ -0,0 +1,248 @@
+use bevy::prelude::*;
+use bevy::ui::RelativeCursorPosition;
+
+use crate::schedule::InGameSet;
+use crate::state::GameState;
+use crate::gravity::Gravity;
+use crate::fluid_compute::FluidStaticProps;
+
+const SETTINGS_PANEL_KEY: KeyCode = KeyCode::Tab;
+const SETTINGS_PANEL_WIDTH: f32 = 280.;
+const SLIDER_HEIGHT: f32 = 12.;
+const TEXT_COLOR: Color = Color::rgb(0.9, 0.9, 0.9);
+const TEXT_FONT_SIZE: f32 = 18.;
+const PANEL_COLOR: Color = Color::rgba(0.1, 0.1, 0.1, 0.8);
+const SLIDER_TRACK_COLOR: Color = Color::rgb(0.25, 0.25, 0.25);
+const SLIDER_FILL_COLOR: Color = Color::rgb(0.2, 0.5, 0.8);
+
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum SliderField {
+    Pressure,
+    NearPressure,
+    TargetDensity,
+    SmoothingRadius,
+    Viscosity,
+    MaxSpeed,
+    Gravity,
+}
+
+
+impl SliderField {
+    const ALL: [SliderField; 7] = [
+        SliderField::Pressure,
+        SliderField::NearPressure,
+        SliderField::TargetDensity,
+        SliderField::SmoothingRadius,
+        SliderField::Viscosity,
+        SliderField::MaxSpeed,
+        SliderField::Gravity,
+    ];
+
+    fn get_label(&self) -> &'static str {
+        match self {
+            SliderField::Pressure => "Pressure",
+            SliderField::NearPressure => "Near pressure",
+            SliderField::TargetDensity => "Target density",
+            SliderField::SmoothingRadius => "Smoothing radius",
+            SliderField::Viscosity => "Viscosity",
+            SliderField::MaxSpeed => "Max speed",
+            SliderField::Gravity => "Gravity",
+        }
+    }
+
+    fn get_range(&self) -> (f32, f32) {
+        match self {
+            SliderField::Pressure => (0., 100.),
+            SliderField::NearPressure => (0., 20.),
+            SliderField::TargetDensity => (0., 50.),
+            SliderField::SmoothingRadius => (0.05, 1.),
+            SliderField::Viscosity => (0., 2.),
+            // Zero turns the clamp off
+            SliderField::MaxSpeed => (0., 100.),
+            SliderField::Gravity => (0., 20.),
+        }
+    }
+
+    fn get(&self, fluid_props: &FluidStaticProps, gravity: &Gravity) -> f32 {
+        match self {
+            SliderField::Pressure => fluid_props.pressure_scalar,
+            SliderField::NearPressure => fluid_props.near_pressure_scalar,
+            SliderField::TargetDensity => fluid_props.target_density,
+            SliderField::SmoothingRadius => fluid_props.smoothing_radius,
+            SliderField::Viscosity => fluid_props.viscosity_strength,
+            SliderField::MaxSpeed => fluid_props.max_speed,
+            SliderField::Gravity => gravity.magnitude(),
+        }
+    }
+
+    fn set(&self, fluid_props: &mut FluidStaticProps, gravity: &mut Gravity, value: f32) {
+        match self {
+            SliderField::Pressure => fluid_props.pressure_scalar = value,
+            SliderField::NearPressure => fluid_props.near_pressure_scalar = value,
+            SliderField::TargetDensity => fluid_props.target_density = value,
+            SliderField::SmoothingRadius => fluid_props.smoothing_radius = value,
+            SliderField::Viscosity => fluid_props.viscosity_strength = value,
+            SliderField::MaxSpeed => fluid_props.max_speed = value,
+            SliderField::Gravity => gravity.set_magnitude(value),
+        }
+    }
+}
+
+
+#[derive(Component, Debug)]
+struct SettingsPanel;
+
+
+/// Track, clicking or dragging on it sets the value
+#[derive(Component, Debug)]
+struct Slider(SliderField);
+
+
+#[derive(Component, Debug)]
+struct SliderFill(SliderField);
+
+
+#[derive(Component, Debug)]
+struct SliderLabel(SliderField);
+
+
+pub struct SettingsPanelPlugin;
+
+
+impl Plugin for SettingsPanelPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(OnTransition { from: GameState::Menu, to: GameState::InGame }, setup_settings_panel)
+            .add_systems(OnTransition { from: GameState::Paused, to: GameState::Menu }, despawn_settings_panel)
+            .add_systems(Update, (toggle_settings_panel, drag_sliders).in_set(InGameSet::UserInput))
+            .add_systems(Update, update_sliders.in_set(InGameSet::EntityUpdates));
+    }
+}
+
+
+fn setup_settings_panel(mut commands: Commands) {
+    let text_style = TextStyle {
+        font_size: TEXT_FONT_SIZE,
+        color: TEXT_COLOR,
+        ..default()
+    };
+
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                // Hidden nodes don't take part in picking, so they can't swallow clicks
+                display: Display::None,
+                position_type: PositionType::Absolute,
+                right: Val::Px(10.),
+                top: Val::Percent(8.),
+                width: Val::Px(SETTINGS_PANEL_WIDTH),
+                flex_direction: FlexDirection::Column,
+                padding: UiRect::all(Val::Px(10.)),
+                row_gap: Val::Px(6.),
+                ..default()
+            },
+            background_color: PANEL_COLOR.into(),
+            ..default()
+        },
+        SettingsPanel,
+    )).with_children(|parent| {
+        for field in SliderField::ALL {
+            parent.spawn((
+                TextBundle::from_section(field.get_label(), text_style.clone()),
+                SliderLabel(field),
+            ));
+            parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        width: Val::Percent(100.),
+                        height: Val::Px(SLIDER_HEIGHT),
+                        ..default()
+                    },
+                    background_color: SLIDER_TRACK_COLOR.into(),
+                    ..default()
+                },
+                Interaction::default(),
+                RelativeCursorPosition::default(),
+                Slider(field),
+            )).with_children(|parent| {
+                parent.spawn((
+                    NodeBundle {
+                        style: Style {
+                            width: Val::Percent(0.),
+                            height: Val::Percent(100.),
+                            ..default()
+                        },
+                        background_color: SLIDER_FILL_COLOR.into(),
+                        ..default()
+                    },
+                    SliderFill(field),
+                ));
+            });
+        }
+    });
+}
+
+
+fn despawn_settings_panel(mut commands: Commands, query: Query<Entity, With<SettingsPanel>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+
+fn toggle_settings_panel(
+    mut query: Query<&mut Style, With<SettingsPanel>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+) {
+    if !keyboard_input.just_pressed(SETTINGS_PANEL_KEY) {
+        return;
+    }
+    let Ok(mut style) = query.get_single_mut() else { return };
+    style.display = match style.display {
+        Display::None => Display::Flex,
+        _ => Display::None,
+    };
+}
+
+
+/// A pressed track keeps following the cursor until the button is released
+fn drag_sliders(
+    query: Query<(&Interaction, &RelativeCursorPosition, &Slider)>,
+    mut fluid_props: ResMut<FluidStaticProps>,
+    mut gravity: ResMut<Gravity>,
+) {
+    for (interaction, cursor, slider) in query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let Some(normalized) = cursor.normalized else { continue };
+        let (min, max) = slider.0.get_range();
+        let value = min + normalized.x.clamp(0., 1.) * (max - min);
+        slider.0.set(&mut fluid_props, &mut gravity, value);
+    }
+}
+
+
+/// Reflects the resources, whichever way they were changed
+fn update_sliders(
+    mut fill_query: Query<(&mut Style, &SliderFill)>,
+    mut label_query: Query<(&mut Text, &SliderLabel)>,
+    fluid_props: Res<FluidStaticProps>,
+    gravity: Res<Gravity>,
+) {
+    if !fluid_props.is_changed() && !gravity.is_changed() {
+        return;
+    }
+    for (mut style, fill) in fill_query.iter_mut() {
+        let (min, max) = fill.0.get_range();
+        let value = fill.0.get(&fluid_props, &gravity);
+        style.width = Val::Percent(((value - min) / (max - min)).clamp(0., 1.) * 100.);
+    }
+    for (mut text, label) in label_query.iter_mut() {
+        if text.sections.is_empty() {
+            continue;
+        }
+        text.sections[0].value = format!("{}: {:.3}", label.0.get_label(), label.0.get(&fluid_props, &gravity));
+    }
+}
@@ -0,0 +1,88 @@
+use bevy::prelude::*;
+use bevy::window::{PrimaryWindow, WindowMoved};
+
+use crate::schedule::InGameSet;
+use crate::camera::Observer;
+use crate::gravity::Gravity;
+
+const SHAKE_SENSITIVITY: f32 = 0.05;  // Acceleration per pixel per second of window motion
+const SHAKE_MAX_IMPULSE: f32 = 40.;
+const SHAKE_DECAY: f32 = 6.;  // Per second, exponential
+
+
+/// Sloshes the fluid when the window is dragged, the acceleration is opposite the motion like a shaken glass
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct WindowShake {
+    pub enabled: bool,
+    pub sensitivity: f32,
+    /// Cap on the acceleration, a teleporting window would launch everything otherwise
+    pub max_impulse: f32,
+    pub decay: f32,
+    /// Current extra acceleration in world space
+    pub impulse: Vec3,
+}
+
+
+impl Default for WindowShake {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            sensitivity: SHAKE_SENSITIVITY,
+            max_impulse: SHAKE_MAX_IMPULSE,
+            decay: SHAKE_DECAY,
+            impulse: Vec3::ZERO,
+        }
+    }
+}
+
+
+impl WindowShake {
+    /// Gravity as seen by the shader, with the impulse on top
+    pub fn apply(&self, gravity: &Gravity) -> Gravity {
+        let mut gravity = *gravity;
+        gravity.value += self.impulse.extend(0.);
+        gravity
+    }
+}
+
+
+pub struct ShakePlugin;
+
+
+impl Plugin for ShakePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<WindowShake>()
+            .add_systems(Update, track_window_motion.in_set(InGameSet::UserInput));
+    }
+}
+
+
+fn track_window_motion(
+    mut shake: ResMut<WindowShake>,
+    mut last_position: Local<Option<IVec2>>,
+    mut moved_events: EventReader<WindowMoved>,
+    window_query: Query<Entity, With<PrimaryWindow>>,
+    camera_query: Query<&GlobalTransform, With<Observer>>,
+    time: Res<Time>,
+) {
+    let delta = time.delta_seconds();
+    shake.impulse *= (-shake.decay * delta).exp();
+
+    let Ok(window) = window_query.get_single() else { return };
+    let Some(position) = moved_events.read().filter(|event| event.window == window).last().map(|event| event.position)
+    else {
+        return;
+    };
+    let Some(previous) = last_position.replace(position) else { return };
+    if !shake.enabled || delta <= 0. {
+        return;
+    }
+    let Ok(camera_transform) = camera_query.get_single() else { return };
+
+    // Screen y grows downwards
+    let velocity = (position - previous).as_vec2() / delta;
+    let motion = *camera_transform.right() * velocity.x - *camera_transform.up() * velocity.y;
+    let impulse = shake.impulse - motion * shake.sensitivity;
+    shake.impulse = impulse.clamp_length_max(shake.max_impulse);
+}
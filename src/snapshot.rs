@@ -0,0 +1,266 @@
+use bevy::prelude::*;
+use bevy::tasks::AsyncComputeTaskPool;
+use bevy_app_compute::prelude::*;
+
+use crate::schedule::InGameSet;
+use crate::gravity::Gravity;
+use crate::presets::Preset;
+use crate::fluid_container::FluidContainer;
+use crate::particle_color::ParticlePalette;
+use crate::fluid_compute::{
+    restore_particles, spawn_particle_entities, FluidCapacity, FluidParticle, FluidResetEvent, FluidStaticProps,
+    FluidWorker, ParticleMesh,
+};
+
+const SNAPSHOT_PATH: &str = "snapshot.bin";
+const SNAPSHOT_MAGIC: [u8; 4] = *b"WSNP";
+const SNAPSHOT_VERSION: u32 = 1;
+const SNAPSHOT_KEY: KeyCode = KeyCode::F11;  // F9 already exports and F10 shows the grid
+// Held together with the key to save, like the preset slots
+const SNAPSHOT_SAVE_MODIFIERS: [KeyCode; 2] = [KeyCode::ShiftLeft, KeyCode::ShiftRight];
+
+
+/// Live particles together with the tuning and the container they ran in
+#[derive(Clone)]
+pub struct Snapshot {
+    pub preset: Preset,
+    pub particles: Vec<FluidParticle>,
+}
+
+
+/// Consumes the snapshot bytes front to back
+struct SnapshotReader<'a> {
+    bytes: &'a [u8],
+}
+
+
+impl<'a> SnapshotReader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < length {
+            return Err("the file is truncated".to_string());
+        }
+        let (head, tail) = self.bytes.split_at(length);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn take_u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+
+impl Snapshot {
+    /// Magic, version, the preset as RON, then the raw particle buffer after its stride and count.
+    /// Integers are little endian u32.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let preset = ron::to_string(&self.preset).map_err(|err| err.to_string())?;
+        let particles: &[u8] = bytemuck::cast_slice(&self.particles);
+        let mut bytes = Vec::with_capacity(20 + preset.len() + particles.len());
+        bytes.extend_from_slice(&SNAPSHOT_MAGIC);
+        bytes.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(preset.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(preset.as_bytes());
+        bytes.extend_from_slice(&(std::mem::size_of::<FluidParticle>() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.particles.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(particles);
+        Ok(bytes)
+    }
+
+    /// Refuses files from a newer version or with another particle layout instead of misreading them
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = SnapshotReader { bytes };
+        if reader.take(SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC {
+            return Err("not a snapshot".to_string());
+        }
+        let version = reader.take_u32()?;
+        if version > SNAPSHOT_VERSION {
+            return Err(format!("unsupported version {}", version));
+        }
+
+        let preset_length = reader.take_u32()? as usize;
+        let preset = std::str::from_utf8(reader.take(preset_length)?).map_err(|err| err.to_string())?;
+        // Missing fields fall back to the defaults, like the presets file
+        let preset = ron::from_str::<Preset>(preset).map_err(|err| err.to_string())?;
+
+        let stride = reader.take_u32()? as usize;
+        if stride != std::mem::size_of::<FluidParticle>() {
+            return Err(format!("particles of {} bytes, expected {}", stride, std::mem::size_of::<FluidParticle>()));
+        }
+        let count = reader.take_u32()? as usize;
+        let particles = reader.take(count * stride)?;
+        // The file bytes aren't aligned for the particle type, so they are copied rather than cast
+        let particles = bytemuck::pod_collect_to_vec::<u8, FluidParticle>(particles);
+        Ok(Self { preset, particles })
+    }
+}
+
+
+/// Set on the key press, cleared once a ready worker could be read or written
+#[derive(Resource, Default, Debug)]
+struct SnapshotRequest {
+    save: bool,
+    load: bool,
+}
+
+
+pub struct SnapshotPlugin;
+
+
+impl Plugin for SnapshotPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<SnapshotRequest>()
+            .add_systems(Update, request_snapshot.in_set(InGameSet::UserInput))
+            .add_systems(Update, (save_snapshot, load_snapshot).chain().in_set(InGameSet::EntityUpdates));
+    }
+}
+
+
+fn request_snapshot(mut request: ResMut<SnapshotRequest>, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if !keyboard_input.just_pressed(SNAPSHOT_KEY) {
+        return;
+    }
+    if keyboard_input.any_pressed(SNAPSHOT_SAVE_MODIFIERS) {
+        request.save = true;
+    } else {
+        request.load = true;
+    }
+}
+
+
+/// Copies the buffer on the main thread, the encoding and the file write happen on the task pool
+fn save_snapshot(
+    mut request: ResMut<SnapshotRequest>,
+    worker: Res<AppComputeWorker<FluidWorker>>,
+    capacity: Res<FluidCapacity>,
+    fluid_props: Res<FluidStaticProps>,
+    gravity: Res<Gravity>,
+    container: Res<FluidContainer>,
+) {
+    if !request.save || !worker.ready() {
+        return;
+    }
+    request.save = false;
+
+    let particles = worker.read_vec::<FluidParticle>("particles");
+    let snapshot = Snapshot {
+        preset: Preset::capture(&fluid_props, &gravity, &container),
+        particles: particles[..capacity.num_particles as usize].to_vec(),
+    };
+    AsyncComputeTaskPool::get().spawn(async move {
+        match snapshot.to_bytes().and_then(|bytes| std::fs::write(SNAPSHOT_PATH, bytes).map_err(|err| err.to_string())) {
+            Ok(()) => println!("Saved {} particles to {}", snapshot.particles.len(), SNAPSHOT_PATH),
+            Err(err) => println!("Snapshot save failed: {}", err),
+        }
+    }).detach();
+}
+
+
+/// Restores the tuning, the container size and the particles, extra render entities are spawned here
+/// and the surplus ones despawned with the other removed particles
+fn load_snapshot(
+    mut commands: Commands,
+    mut request: ResMut<SnapshotRequest>,
+    mut worker: ResMut<AppComputeWorker<FluidWorker>>,
+    mut capacity: ResMut<FluidCapacity>,
+    mut fluid_props: ResMut<FluidStaticProps>,
+    mut gravity: ResMut<Gravity>,
+    mut container: ResMut<FluidContainer>,
+    mut reset_events: EventWriter<FluidResetEvent>,
+    particle_mesh: Option<Res<ParticleMesh>>,
+    palette: Res<ParticlePalette>,
+) {
+    if !request.load || !worker.ready() {
+        return;
+    }
+    request.load = false;
+
+    let snapshot = std::fs::read(SNAPSHOT_PATH)
+        .map_err(|err| err.to_string())
+        .and_then(|bytes| Snapshot::from_bytes(&bytes));
+    let mut snapshot = match snapshot {
+        Ok(snapshot) => snapshot,
+        Err(err) => {
+            println!("Warning: can't load {} ({}), keeping the current state", SNAPSHOT_PATH, err);
+            return;
+        },
+    };
+    if snapshot.particles.len() > capacity.max_particles as usize {
+        println!("Snapshot of {} particles exceeds the capacity of {}, truncating", snapshot.particles.len(), capacity.max_particles);
+        snapshot.particles.truncate(capacity.max_particles as usize);
+    }
+
+    snapshot.preset.apply(&mut fluid_props, &mut gravity, &mut container);
+    let num_existing = capacity.num_particles as usize;
+    restore_particles(&mut worker, &mut capacity, &snapshot.particles);
+    reset_events.send(FluidResetEvent);
+
+    let Some(particle_mesh) = particle_mesh else { return };
+    if snapshot.particles.len() > num_existing {
+        let positions: Vec<Vec3> = snapshot.particles[num_existing..].iter().map(|it| it.position.xyz()).collect();
+        spawn_particle_entities(&mut commands, &particle_mesh.0, &palette.solid, &container, &positions, num_existing);
+    }
+    println!("Loaded {} particles from {}", snapshot.particles.len(), SNAPSHOT_PATH);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_snapshot() -> Snapshot {
+        let mut particles = FluidParticle::make_vec_from_positions(vec![Vec3::new(1., 2., 3.), Vec3::new(-4., 5., 0.5)]);
+        particles[1].velocity = Vec4::new(0.5, -1., 2., 0.);
+        particles[1].density = Vec2::new(998., 1000.);
+        Snapshot {
+            preset: Preset {
+                gravity: [0., -3., 0.],
+                ..default()
+            },
+            particles,
+        }
+    }
+
+    #[test]
+    fn round_trips_through_bytes() {
+        let snapshot = make_snapshot();
+        let restored = Snapshot::from_bytes(&snapshot.to_bytes().unwrap()).unwrap();
+        assert_eq!(restored.preset, snapshot.preset);
+        let restored_bytes: &[u8] = bytemuck::cast_slice(&restored.particles);
+        let bytes: &[u8] = bytemuck::cast_slice(&snapshot.particles);
+        assert_eq!(restored_bytes, bytes);
+    }
+
+    #[test]
+    fn truncated_bytes_are_refused() {
+        let bytes = make_snapshot().to_bytes().unwrap();
+        for length in [0, 3, 8, 12, 20, bytes.len() - 1] {
+            assert!(Snapshot::from_bytes(&bytes[..length]).is_err(), "{length} bytes");
+        }
+    }
+
+    #[test]
+    fn garbage_bytes_are_refused() {
+        assert!(Snapshot::from_bytes(b"not a snapshot at all").is_err());
+        // Valid header followed by noise, including a huge preset length
+        let mut bytes = SNAPSHOT_MAGIC.to_vec();
+        bytes.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&[0xFF; 32]);
+        assert!(Snapshot::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn newer_versions_and_other_layouts_are_refused() {
+        let mut bytes = make_snapshot().to_bytes().unwrap();
+        bytes[4..8].copy_from_slice(&(SNAPSHOT_VERSION + 1).to_le_bytes());
+        assert!(Snapshot::from_bytes(&bytes).is_err());
+
+        let mut bytes = make_snapshot().to_bytes().unwrap();
+        let preset_length = u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize;
+        let stride_at = 12 + preset_length;
+        bytes[stride_at..stride_at + 4].copy_from_slice(&16u32.to_le_bytes());
+        assert!(Snapshot::from_bytes(&bytes).is_err());
+    }
+}
@@ -0,0 +1,254 @@
+//! Headless stability sweep and shutdown check, run by hand with `--soak` or `--shutdown-cycles <cycles>`.
+//! Both need a GPU adapter, so they are not part of `cargo test` or any CI job, only `check_soak_step` is unit tested.
+
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::app::{AppExit, PluginsState};
+use bevy::render::render_resource::Maintain;
+use bevy::render::renderer::RenderDevice;
+use bevy::tasks::tick_global_task_pools_on_main_thread;
+use bevy::time::TimeUpdateStrategy;
+use bevy::window::ExitCondition;
+use bevy::winit::WinitPlugin;
+use bevy_app_compute::prelude::*;
+
+use crate::state::{GameState, StatePlugin};
+use crate::schedule::SchedulePlugin;
+use crate::fluid_container::GizmoPlugin;
+use crate::gravity::GravityPlugin;
+use crate::force_toggles::ForceTogglesPlugin;
+use crate::paddle::PaddlePlugin;
+use crate::obstacles::ObstaclesPlugin;
+use crate::thermal::ThermalPlugin;
+use crate::shake::ShakePlugin;
+use crate::rigid_ball::RigidBallPlugin;
+use crate::world_cursor::WorldCursorPlugin;
+use crate::particle_color::ParticleColorPlugin;
+use crate::fluid_compute::{FluidCapacity, FluidPlugin, FluidShape, FluidStaticProps, FluidWorker, ParticleState};
+
+const SOAK_STEPS: usize = 300;
+const SOAK_MAX_FRAMES: usize = SOAK_STEPS * 4;  // Bail out if the worker stalls
+const SOAK_MAX_KINETIC_ENERGY: f32 = 1000.;  // Mean per particle, unit mass
+
+const SOAK_SHAPES: [UVec3; 2] = [UVec3::new(16, 16, 16), UVec3::new(32, 16, 16)];
+const SOAK_SMOOTHING_RADII: [f32; 2] = [0.2, 0.35];
+const SOAK_PRESSURE_SCALARS: [f32; 2] = [10., 40.];
+const SOAK_VISCOSITY_STRENGTHS: [f32; 2] = [0.05, 0.5];
+const SHUTDOWN_SHAPE: UVec3 = UVec3::new(16, 16, 16);
+const SHUTDOWN_FRAMES: usize = 10;  // Updates before the exit, enough to leave dispatches in flight
+
+
+#[derive(Clone, Copy, Debug)]
+struct SoakCase {
+    shape: UVec3,
+    smoothing_radius: f32,
+    pressure_scalar: f32,
+    viscosity_strength: f32,
+}
+
+
+fn get_soak_cases() -> Vec<SoakCase> {
+    let mut cases = Vec::new();
+    for shape in SOAK_SHAPES {
+        for smoothing_radius in SOAK_SMOOTHING_RADII {
+            for pressure_scalar in SOAK_PRESSURE_SCALARS {
+                for viscosity_strength in SOAK_VISCOSITY_STRENGTHS {
+                    cases.push(SoakCase {
+                        shape,
+                        smoothing_radius,
+                        pressure_scalar,
+                        viscosity_strength,
+                    });
+                }
+            }
+        }
+    }
+    cases
+}
+
+
+/// Windowless app already past the menu, the fluid spawns on the first update
+pub fn build_headless_app(fluid_props: FluidStaticProps, fluid_plugin: FluidPlugin) -> App {
+    let mut app = App::new();
+    app
+        // One step length per update, so the fixed clock dispatches a step every update
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(fluid_props.delta_time.into())))
+        .insert_resource(fluid_props)
+        .add_plugins((
+            DefaultPlugins.set(WindowPlugin {
+                primary_window: None,
+                exit_condition: ExitCondition::DontExit,
+                close_when_requested: false,
+            }).disable::<WinitPlugin>(),
+            StatePlugin,
+            SchedulePlugin,
+            GizmoPlugin,
+            GravityPlugin,
+            ForceTogglesPlugin,
+            PaddlePlugin,
+            ObstaclesPlugin,
+            ThermalPlugin,
+            ShakePlugin,
+            RigidBallPlugin,
+            WorldCursorPlugin,
+            fluid_plugin,
+            ParticleColorPlugin,
+        ));
+
+    // Same as `App::run` does before the first update
+    while app.plugins_state() == PluginsState::Adding {
+        tick_global_task_pools_on_main_thread();
+    }
+    app.finish();
+    app.cleanup();
+
+    app.world.resource_mut::<NextState<GameState>>().set(GameState::InGame);
+    app
+}
+
+
+/// Fails on a non-finite particle or a mean kinetic energy above the bound, returns the reason
+fn check_soak_step(particles: &[ParticleState], step: usize) -> Option<String> {
+    if particles.iter().any(|it| !it.position.is_finite() || !it.velocity.is_finite()) {
+        return Some(format!("non-finite particle state at step {}", step));
+    }
+    let kinetic_energy = particles.iter()
+        .map(|it| it.velocity.length_squared() / 2.)
+        .sum::<f32>() / particles.len().max(1) as f32;
+    if kinetic_energy > SOAK_MAX_KINETIC_ENERGY {
+        return Some(format!("kinetic energy {:.3} out of bounds at step {}", kinetic_energy, step));
+    }
+    None
+}
+
+
+/// Returns the failure reason, if any
+fn run_soak_case(case: &SoakCase) -> Option<String> {
+    let mut app = build_headless_app(FluidStaticProps {
+        smoothing_radius: case.smoothing_radius,
+        pressure_scalar: case.pressure_scalar,
+        viscosity_strength: case.viscosity_strength,
+        ..default()
+    }, FluidPlugin::builder().shape(FluidShape::Cube(case.shape)).build());
+
+    let mut steps = 0;
+    for _ in 0..SOAK_MAX_FRAMES {
+        app.update();
+
+        let worker = app.world.resource::<AppComputeWorker<FluidWorker>>();
+        if !worker.ready() {
+            continue;
+        }
+        steps += 1;
+
+        let particles = ParticleState::read_all(worker, app.world.resource::<FluidCapacity>());
+        if let Some(reason) = check_soak_step(&particles, steps) {
+            return Some(reason);
+        }
+
+        if steps >= SOAK_STEPS {
+            return None;
+        }
+    }
+
+    Some(format!("worker stalled after {} steps", steps))
+}
+
+
+/// Sweeps solver parameters headless, returns whether every combination stayed stable
+pub fn run_soak_test() -> bool {
+    let cases = get_soak_cases();
+    let mut failures = Vec::new();
+    for (it, case) in cases.iter().enumerate() {
+        println!("Soak {}/{}: {:?}", it + 1, cases.len(), case);
+        if let Some(reason) = run_soak_case(case) {
+            println!("Soak {}/{}: FAILED, {}", it + 1, cases.len(), reason);
+            failures.push((case, reason));
+        }
+    }
+
+    println!("Soak: {} of {} combinations failed", failures.len(), cases.len());
+    for (case, reason) in failures.iter() {
+        println!("  {:?}: {}", case, reason);
+    }
+    failures.is_empty()
+}
+
+
+/// Returns the failure reason, if any
+fn run_shutdown_cycle() -> Option<String> {
+    let mut app = build_headless_app(
+        FluidStaticProps::default(),
+        FluidPlugin::builder().shape(FluidShape::Cube(SHUTDOWN_SHAPE)).build(),
+    );
+    for _ in 0..SHUTDOWN_FRAMES {
+        app.update();
+    }
+
+    app.world.send_event(AppExit);
+    app.update();
+    if app.world.contains_resource::<AppComputeWorker<FluidWorker>>() {
+        return Some("the worker outlived the exit".to_string());
+    }
+    if !app.world.resource::<RenderDevice>().poll(Maintain::Wait).is_queue_empty() {
+        return Some("GPU work was still queued after the exit".to_string());
+    }
+    None
+}
+
+
+/// Builds, steps and exits the headless app `cycles` times, returns whether every teardown was clean.
+/// Checks that the worker is dropped on exit with nothing left queued, GPU memory itself isn't measured,
+/// run it under the validation layers or a driver memory tool to catch leaked allocations.
+pub fn run_shutdown_cycles(cycles: usize) -> bool {
+    let mut failures = 0;
+    for it in 0..cycles {
+        if let Some(reason) = run_shutdown_cycle() {
+            println!("Shutdown {}/{}: FAILED, {}", it + 1, cycles, reason);
+            failures += 1;
+        }
+    }
+    println!("Shutdown: {} of {} cycles failed", failures, cycles);
+    failures == 0
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_particles(num_particles: usize, speed: f32) -> Vec<ParticleState> {
+        (0..num_particles)
+            .map(|it| ParticleState { position: Vec3::splat(it as f32), velocity: Vec3::X * speed, ..default() })
+            .collect()
+    }
+
+    #[test]
+    fn calm_particles_pass() {
+        assert_eq!(check_soak_step(&make_particles(4, 10.), 1), None);
+        assert_eq!(check_soak_step(&[], 1), None);
+    }
+
+    #[test]
+    fn flags_non_finite_particles() {
+        let mut particles = make_particles(3, 1.);
+        particles[1].velocity.y = f32::NAN;
+        assert!(check_soak_step(&particles, 7).is_some_and(|reason| reason.contains("non-finite")));
+        particles[1].velocity.y = 0.;
+        particles[2].position.x = f32::INFINITY;
+        assert!(check_soak_step(&particles, 7).is_some_and(|reason| reason.contains("step 7")));
+    }
+
+    #[test]
+    fn bounds_the_mean_kinetic_energy() {
+        // Half the squared speed per particle, just either side of the bound
+        let speed = (2. * SOAK_MAX_KINETIC_ENERGY).sqrt();
+        assert_eq!(check_soak_step(&make_particles(4, speed * 0.99), 1), None);
+        assert!(check_soak_step(&make_particles(4, speed * 1.01), 1).is_some_and(|reason| reason.contains("kinetic")));
+        // A single runaway particle is averaged over the rest
+        let mut particles = make_particles(10, 0.);
+        particles[0].velocity = Vec3::X * speed * 2.;
+        assert_eq!(check_soak_step(&particles, 1), None);
+    }
+}
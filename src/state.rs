@@ -5,6 +5,8 @@ use bevy::prelude::*;
 pub enum GameState {
     #[default]
     Menu,
+    /// Start options, reached from the menu and back
+    Settings,
     InGame,
     Paused,
     GameOver,
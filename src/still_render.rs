@@ -0,0 +1,151 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+use bevy::render::camera::RenderTarget;
+use bevy::render::renderer::RenderDevice;
+use bevy::render::view::screenshot::ScreenshotManager;
+use bevy::window::{WindowRef, WindowResolution};
+
+use crate::camera::Observer;
+use crate::schedule::InGameSet;
+
+const STILL_RENDER_KEY: KeyCode = KeyCode::F12;
+const STILL_RENDER_RESOLUTION: UVec2 = UVec2::new(3840, 2160);
+const STILL_RENDER_WARMUP_FRAMES: u32 = 3;  // Let the offscreen surface get configured first
+
+
+#[derive(Resource, Clone, Debug)]
+pub struct StillRenderSettings {
+    pub resolution: UVec2,
+}
+
+
+impl Default for StillRenderSettings {
+    fn default() -> Self {
+        Self {
+            resolution: STILL_RENDER_RESOLUTION,
+        }
+    }
+}
+
+
+#[derive(Default, Debug)]
+enum StillRenderPhase {
+    #[default]
+    Idle,
+    WarmingUp(u32),
+    Capturing,
+}
+
+
+#[derive(Resource, Default, Debug)]
+struct StillRender {
+    phase: StillRenderPhase,
+    window: Option<Entity>,
+    camera: Option<Entity>,
+}
+
+
+pub struct StillRenderPlugin;
+
+
+impl Plugin for StillRenderPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<StillRenderSettings>()
+            .init_resource::<StillRender>()
+            .add_systems(Update, (
+                start_still_render.in_set(InGameSet::UserInput),
+                capture_still_render,
+            ));
+    }
+}
+
+
+fn get_still_resolution(resolution: UVec2, max_dimension: u32) -> UVec2 {
+    let largest = resolution.max_element();
+    if largest <= max_dimension {
+        return resolution;
+    }
+    // Scale down uniformly so the framing is kept
+    println!(
+        "Still render: {}x{} exceeds the device limit of {}, scaling down",
+        resolution.x, resolution.y, max_dimension,
+    );
+    (resolution.as_vec2() * max_dimension as f32 / largest as f32).as_uvec2().max(UVec2::ONE)
+}
+
+
+fn start_still_render(
+    mut commands: Commands,
+    mut still_render: ResMut<StillRender>,
+    settings: Res<StillRenderSettings>,
+    render_device: Res<RenderDevice>,
+    observer_query: Query<(&Transform, &Projection), With<Observer>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+) {
+    if !keyboard_input.just_pressed(STILL_RENDER_KEY) || !matches!(still_render.phase, StillRenderPhase::Idle) {
+        return;
+    }
+    let Ok((transform, projection)) = observer_query.get_single() else { return };
+
+    let resolution = get_still_resolution(settings.resolution, render_device.limits().max_texture_dimension_2d);
+    let window = commands.spawn(Window {
+        title: "Still render".to_string(),
+        resolution: WindowResolution::new(resolution.x as f32, resolution.y as f32),
+        visible: false,
+        ..default()
+    }).id();
+
+    // Same framing as the observer, only the target differs
+    let mut projection = projection.clone();
+    if let Projection::Perspective(perspective) = &mut projection {
+        perspective.aspect_ratio = resolution.x as f32 / resolution.y as f32;
+    }
+    let camera = commands.spawn(Camera3dBundle {
+        camera: Camera {
+            target: RenderTarget::Window(WindowRef::Entity(window)),
+            ..default()
+        },
+        transform: *transform,
+        projection,
+        ..default()
+    }).id();
+
+    still_render.window = Some(window);
+    still_render.camera = Some(camera);
+    still_render.phase = StillRenderPhase::WarmingUp(STILL_RENDER_WARMUP_FRAMES);
+}
+
+
+fn capture_still_render(
+    mut commands: Commands,
+    mut still_render: ResMut<StillRender>,
+    mut screenshot_manager: ResMut<ScreenshotManager>,
+) {
+    let Some(window) = still_render.window else { return };
+    match still_render.phase {
+        StillRenderPhase::Idle => (),
+        StillRenderPhase::WarmingUp(0) => {
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |it| it.as_secs());
+            let path = format!("still_{}.png", timestamp);
+            match screenshot_manager.save_screenshot_to_disk(window, &path) {
+                Ok(()) => println!("Still render saved to {}", path),
+                Err(err) => println!("Still render failed: {}", err),
+            }
+            still_render.phase = StillRenderPhase::Capturing;
+        },
+        StillRenderPhase::WarmingUp(frames) => {
+            still_render.phase = StillRenderPhase::WarmingUp(frames - 1);
+        },
+        StillRenderPhase::Capturing => {
+            // The screenshot was taken during the previous frame render, tear down the offscreen target
+            if let Some(camera) = still_render.camera.take() {
+                commands.entity(camera).despawn();
+            }
+            commands.entity(window).despawn();
+            still_render.window = None;
+            still_render.phase = StillRenderPhase::Idle;
+        },
+    }
+}
@@ -0,0 +1,168 @@
+use bevy::prelude::*;
+use bevy::core::Pod;
+use bevy::window::PrimaryWindow;
+use bevy_app_compute::prelude::*;
+use bytemuck::Zeroable;
+
+use crate::schedule::InGameSet;
+use crate::camera::Observer;
+use crate::emitter::get_emitter_origin;
+use crate::fluid_container::FluidContainer;
+
+const HEAT_SOURCE_KEYS: [KeyCode; 2] = [KeyCode::AltLeft, KeyCode::AltRight];
+const HEAT_SOURCE_RADIUS: f32 = 0.6;
+const HEAT_SOURCE_RATE: f32 = 5.;  // Degrees per second
+const HEAT_SOURCE_COLOR: Color = Color::rgb(1., 0.35, 0.1);
+
+
+#[derive(ShaderType, Pod, Zeroable, Clone, Copy)]
+#[repr(C)]
+pub struct HeatSourceExt {
+    /// Center in xyz, radius in w
+    pub position: Vec4,
+    /// Degrees per second in x, zero while inactive
+    pub rate: Vec4,
+}
+
+
+/// Heats the particles around the cursor while Alt is held
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct HeatSource {
+    pub active: bool,
+    pub position: Vec3,
+    pub radius: f32,
+    pub rate: f32,
+}
+
+
+impl Default for HeatSource {
+    fn default() -> Self {
+        Self {
+            active: false,
+            position: Vec3::ZERO,
+            radius: HEAT_SOURCE_RADIUS,
+            rate: HEAT_SOURCE_RATE,
+        }
+    }
+}
+
+
+impl HeatSource {
+    pub fn get_ext(&self) -> HeatSourceExt {
+        HeatSourceExt {
+            position: self.position.extend(self.radius),
+            rate: Vec4::new(if self.active { self.rate } else { 0. }, 0., 0., 0.),
+        }
+    }
+}
+
+
+pub struct ThermalPlugin;
+
+
+impl Plugin for ThermalPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<HeatSource>()
+            .add_systems(Update, update_heat_source.in_set(InGameSet::UserInput))
+            .add_systems(Update, draw_heat_source.in_set(InGameSet::EntityUpdates));
+    }
+}
+
+
+fn update_heat_source(
+    mut heat_source: ResMut<HeatSource>,
+    container: Res<FluidContainer>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<Observer>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+) {
+    let position = keyboard_input.any_pressed(HEAT_SOURCE_KEYS)
+        .then(|| {
+            let (camera, camera_transform) = camera_query.get_single().ok()?;
+            let cursor = window_query.get_single().ok()?.cursor_position()?;
+            get_emitter_origin(camera, camera_transform, cursor, &container)
+        })
+        .flatten();
+    match position {
+        Some(position) => {
+            heat_source.active = true;
+            heat_source.position = position;
+        },
+        None if heat_source.active => heat_source.active = false,
+        None => (),
+    }
+}
+
+
+fn draw_heat_source(mut gizmos: Gizmos, heat_source: Res<HeatSource>) {
+    if heat_source.active {
+        gizmos.sphere(heat_source.position, Quat::IDENTITY, heat_source.radius, HEAT_SOURCE_COLOR);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fluid_compute::FluidStaticProps;
+
+    /// One step of the pairwise exchange in the pressure pass, without the heat source, the walls or the mirror ghosts
+    fn diffuse_heat(positions: &[Vec3], temperatures: &[f32], densities: &[f32], fluid_props: &FluidStaticProps) -> Vec<f32> {
+        let kernel = fluid_props.get_smoothing_kernel();
+        let radius = fluid_props.smoothing_radius;
+        (0..positions.len())
+            .map(|it| {
+                let mut heat_flow = 0.;
+                for (other, position) in positions.iter().enumerate() {
+                    let dst = positions[it].distance(*position);
+                    if other == it || dst > radius {
+                        continue;
+                    }
+                    let v = radius - dst;
+                    heat_flow += (temperatures[other] - temperatures[it]) * v * v * kernel.pow2 * 2. / (densities[other] + densities[it]);
+                }
+                temperatures[it] + heat_flow * fluid_props.thermal_diffusion * fluid_props.delta_time
+            })
+            .collect()
+    }
+
+    fn make_props() -> FluidStaticProps {
+        FluidStaticProps {
+            smoothing_radius: 0.5,
+            thermal_diffusion: 0.5,
+            delta_time: 1. / 60.,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn two_particles_even_out() {
+        let positions = [Vec3::ZERO, Vec3::new(0.2, 0., 0.)];
+        let temperatures = diffuse_heat(&positions, &[30., 10.], &[1000., 1000.], &make_props());
+        assert!(temperatures[0] < 30. && temperatures[1] > 10., "{temperatures:?}");
+        assert!((temperatures.iter().sum::<f32>() - 40.).abs() < 1e-4, "{temperatures:?}");
+    }
+
+    #[test]
+    fn three_particles_keep_the_total_heat() {
+        let positions = [Vec3::ZERO, Vec3::new(0.2, 0., 0.), Vec3::new(0.1, 0.3, 0.)];
+        let start = [80., 20., -5.];
+        let mut temperatures = start.to_vec();
+        // Unequal densities weigh the pairs differently but symmetrically
+        for _ in 0..100 {
+            temperatures = diffuse_heat(&positions, &temperatures, &[900., 1000., 1200.], &make_props());
+        }
+        let total = temperatures.iter().sum::<f32>();
+        assert!((total - start.iter().sum::<f32>()).abs() < 1e-2, "{temperatures:?}");
+        for temperature in temperatures {
+            assert!((-5. ..=80.).contains(&temperature), "{temperature}");
+        }
+    }
+
+    #[test]
+    fn particles_out_of_reach_keep_their_heat() {
+        let positions = [Vec3::ZERO, Vec3::new(0.6, 0., 0.)];
+        assert_eq!(diffuse_heat(&positions, &[30., 10.], &[1000., 1000.], &make_props()), [30., 10.]);
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use bevy::prelude::*;
+use bevy_app_compute::prelude::*;
+
+use crate::schedule::InGameSet;
+use crate::fluid_compute::{FluidParticle, FluidParticleLabel, FluidResetEvent, FluidWorker};
+
+const TRAILS_TOGGLE_KEY: KeyCode = KeyCode::Semicolon;
+const TRAILS_LENGTH_KEY: KeyCode = KeyCode::Quote;
+const TRAILS_STRIDE: usize = 16;  // Every Nth particle gets a trail
+const TRAILS_LENGTHS: [usize; 4] = [8, 16, 32, 64];  // Positions kept per trail, cycled by the key
+const TRAILS_MAX_JUMP: f32 = 1.;  // A longer step means the slot was reused, the drain swaps particles around
+const TRAILS_COLOR: Color = Color::rgb(0.6, 0.9, 1.);
+
+
+/// Recent positions of a sampled subset of the particles, drawn as fading polylines
+#[derive(Resource, Debug)]
+pub struct FluidTrails {
+    pub enabled: bool,
+    /// Sample every Nth particle
+    pub stride: usize,
+    /// Positions kept per trail
+    pub length: usize,
+    trails: HashMap<FluidParticleLabel, VecDeque<Vec3>>,
+}
+
+
+impl Default for FluidTrails {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            stride: TRAILS_STRIDE,
+            length: TRAILS_LENGTHS[1],
+            trails: HashMap::new(),
+        }
+    }
+}
+
+
+impl FluidTrails {
+    pub fn clear(&mut self) {
+        self.trails.clear();
+    }
+
+    fn next_length(&self) -> usize {
+        let index = TRAILS_LENGTHS.iter().position(|length| *length == self.length).unwrap_or(0);
+        TRAILS_LENGTHS[(index + 1) % TRAILS_LENGTHS.len()]
+    }
+
+    /// Appends to the particle's ring buffer, the oldest positions fall off the front
+    fn push(&mut self, label: FluidParticleLabel, position: Vec3) {
+        let trail = self.trails.entry(label).or_insert_with(|| VecDeque::with_capacity(self.length));
+        if trail.back().is_some_and(|last| last.distance(position) > TRAILS_MAX_JUMP) {
+            trail.clear();
+        }
+        trail.push_back(position);
+        while trail.len() > self.length {
+            trail.pop_front();
+        }
+    }
+}
+
+
+pub struct TrailsPlugin;
+
+
+impl Plugin for TrailsPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<FluidTrails>()
+            .add_systems(Update, update_trails_settings.in_set(InGameSet::UserInput))
+            .add_systems(Update, (clear_trails, record_trails, draw_trails).chain().in_set(InGameSet::EntityUpdates));
+    }
+}
+
+
+fn update_trails_settings(mut trails: ResMut<FluidTrails>, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if keyboard_input.just_pressed(TRAILS_TOGGLE_KEY) {
+        trails.enabled = !trails.enabled;
+        trails.clear();
+    }
+    if keyboard_input.just_pressed(TRAILS_LENGTH_KEY) {
+        trails.length = trails.next_length();
+    }
+}
+
+
+/// Old paths would connect to the respawned particles
+fn clear_trails(mut trails: ResMut<FluidTrails>, mut reset_events: EventReader<FluidResetEvent>) {
+    if reset_events.is_empty() {
+        return;
+    }
+    reset_events.clear();
+    trails.clear();
+}
+
+
+/// One position per finished step, so the trail length is in steps rather than frames
+fn record_trails(
+    mut trails: ResMut<FluidTrails>,
+    worker: Res<AppComputeWorker<FluidWorker>>,
+    query: Query<&FluidParticleLabel>,
+) {
+    if !trails.enabled || !worker.ready() {
+        return;
+    }
+
+    let particles = worker.read_vec::<FluidParticle>("particles");
+    let stride = trails.stride.max(1);
+    let sampled: HashSet<_> = query.iter()
+        .filter(|label| label.0 % stride == 0 && label.0 < particles.len())
+        .copied()
+        .collect();
+    // Despawned particles take their trails with them
+    trails.trails.retain(|label, _| sampled.contains(label));
+    for label in sampled {
+        trails.push(label, particles[label.0].position.xyz());
+    }
+}
+
+
+fn draw_trails(mut gizmos: Gizmos, trails: Res<FluidTrails>) {
+    if !trails.enabled {
+        return;
+    }
+    for trail in trails.trails.values() {
+        if trail.len() < 2 {
+            continue;
+        }
+        // Transparent at the tail, opaque at the particle
+        let last = (trail.len() - 1) as f32;
+        gizmos.linestrip_gradient(trail.iter().enumerate().map(|(it, position)| {
+            (*position, TRAILS_COLOR.with_a(it as f32 / last))
+        }));
+    }
+}
@@ -0,0 +1,186 @@
+use bevy::prelude::*;
+use bevy::core::Pod;
+use bevy::window::PrimaryWindow;
+use bevy_app_compute::prelude::*;
+use bytemuck::Zeroable;
+
+use crate::schedule::InGameSet;
+use crate::camera::Observer;
+use crate::emitter::get_emitter_origin;
+use crate::fluid_container::FluidContainer;
+use crate::fluid_compute::FluidWorker;
+
+const CURSOR_MODE_KEY: KeyCode = KeyCode::Backslash;
+const CURSOR_SWIRL_KEY: KeyCode = KeyCode::Backquote;
+const CURSOR_FORCE_BUTTON: MouseButton = MouseButton::Middle;
+const CURSOR_RADIUS: f32 = 1.5;
+const CURSOR_STRENGTH: f32 = 40.;  // Acceleration at the center, fades to zero at the radius
+const CURSOR_COLOR: Color = Color::rgb(0.4, 0.8, 1.);
+
+
+/// What the middle drag does to the particles around the cursor
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum CursorMode {
+    /// Middle drag pans the camera
+    #[default]
+    Off,
+    Push,
+    Pull,
+    /// Tangential force around the cursor, makes a vortex
+    Stir,
+}
+
+
+impl CursorMode {
+    pub const ALL: [CursorMode; 4] = [CursorMode::Off, CursorMode::Push, CursorMode::Pull, CursorMode::Stir];
+
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|mode| *mode == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            CursorMode::Off => "off",
+            CursorMode::Push => "push",
+            CursorMode::Pull => "pull",
+            CursorMode::Stir => "stir",
+        }
+    }
+}
+
+
+#[derive(ShaderType, Pod, Zeroable, Clone, Copy)]
+#[repr(C)]
+pub struct WorldCursorExt {
+    /// Center in xyz, radius in w
+    pub position: Vec4,
+    /// Towards the camera, the stir turns around it
+    pub axis: Vec4,
+    /// Radial acceleration in x, tangential in y, zero while inactive
+    pub force: Vec4,
+}
+
+
+/// Cursor projected into the container, drives the push, pull and stir forces while the middle button is held
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct WorldCursor {
+    pub mode: CursorMode,
+    pub active: bool,
+    /// Follows the pointer whenever it is over the window, also the camera's zoom focus
+    pub position: Vec3,
+    pub axis: Vec3,
+    pub radius: f32,
+    pub strength: f32,
+    /// Stir direction as seen from the camera
+    pub clockwise: bool,
+}
+
+
+impl Default for WorldCursor {
+    fn default() -> Self {
+        Self {
+            mode: CursorMode::default(),
+            active: false,
+            position: Vec3::ZERO,
+            axis: Vec3::Z,
+            radius: CURSOR_RADIUS,
+            strength: CURSOR_STRENGTH,
+            clockwise: false,
+        }
+    }
+}
+
+
+impl WorldCursor {
+    /// The middle drag belongs to the cursor rather than the camera
+    pub fn captures_drag(&self) -> bool {
+        self.mode != CursorMode::Off
+    }
+
+    pub fn get_ext(&self) -> WorldCursorExt {
+        let swirl = if self.clockwise { -self.strength } else { self.strength };
+        let force = match (self.active, self.mode) {
+            (false, _) | (_, CursorMode::Off) => Vec2::ZERO,
+            (true, CursorMode::Push) => Vec2::new(self.strength, 0.),
+            (true, CursorMode::Pull) => Vec2::new(-self.strength, 0.),
+            (true, CursorMode::Stir) => Vec2::new(0., swirl),
+        };
+        WorldCursorExt {
+            position: self.position.extend(self.radius),
+            axis: self.axis.extend(0.),
+            force: force.extend(0.).extend(0.),
+        }
+    }
+
+    /// Human readable mode, for the HUD
+    pub fn get_legend(&self) -> String {
+        match self.mode {
+            CursorMode::Stir if self.clockwise => "Cursor: stir cw".to_string(),
+            CursorMode::Stir => "Cursor: stir ccw".to_string(),
+            mode => format!("Cursor: {}", mode.get_name()),
+        }
+    }
+}
+
+
+pub struct WorldCursorPlugin;
+
+
+impl Plugin for WorldCursorPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<WorldCursor>()
+            .add_systems(Update, (update_cursor_settings, update_world_cursor).chain().in_set(InGameSet::UserInput))
+            .add_systems(Update, (sync_world_cursor, draw_world_cursor).in_set(InGameSet::EntityUpdates));
+    }
+}
+
+
+fn update_cursor_settings(mut cursor: ResMut<WorldCursor>, keyboard_input: Res<ButtonInput<KeyCode>>) {
+    if keyboard_input.just_pressed(CURSOR_MODE_KEY) {
+        cursor.mode = cursor.mode.next();
+    }
+    if keyboard_input.just_pressed(CURSOR_SWIRL_KEY) {
+        cursor.clockwise = !cursor.clockwise;
+    }
+}
+
+
+fn update_world_cursor(
+    mut cursor: ResMut<WorldCursor>,
+    container: Res<FluidContainer>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<Observer>>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    mouse_input: Res<ButtonInput<MouseButton>>,
+) {
+    let target = (|| {
+        let (camera, camera_transform) = camera_query.get_single().ok()?;
+        let cursor = window_query.get_single().ok()?.cursor_position()?;
+        let position = get_emitter_origin(camera, camera_transform, cursor, &container)?;
+        Some((position, camera_transform.back()))
+    })();
+    if let Some((position, axis)) = target {
+        cursor.position = position;
+        cursor.axis = axis;
+    }
+    let active = target.is_some() && cursor.captures_drag() && mouse_input.pressed(CURSOR_FORCE_BUTTON);
+    if cursor.active != active {
+        cursor.active = active;
+    }
+}
+
+
+fn sync_world_cursor(mut worker: ResMut<AppComputeWorker<FluidWorker>>, cursor: Res<WorldCursor>) {
+    if !worker.ready() {
+        return;
+    }
+    worker.write("world_cursor", &cursor.get_ext());
+}
+
+
+fn draw_world_cursor(mut gizmos: Gizmos, cursor: Res<WorldCursor>) {
+    if cursor.active {
+        gizmos.circle(cursor.position, Direction3d::new_unchecked(cursor.axis), cursor.radius, CURSOR_COLOR);
+    }
+}